@@ -0,0 +1,203 @@
+//! Throughput benchmarks for the database.
+//!
+//! run with `cargo bench`, criterion stores the results in `target/criterion`
+//! and compares every run against the previous one.
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use ledger::{actions::AccountAction, database::Database};
+use std::{fmt::Write, hint::black_box};
+
+const CLIENTS: u32 = 1_000;
+const TRANSACTIONS: u32 = 10_000;
+
+/// A small xorshift generator so the workload is the same on every run without pulling in `rand`.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u32) -> u32 {
+        (self.next() % n as u64) as u32
+    }
+}
+
+/// The shape of a synthetic workload.
+#[derive(Clone, Copy)]
+enum Workload {
+    /// only deposits, spread over all clients
+    Deposits,
+    /// a deposit of 100 for every client, followed by small withdrawals
+    Withdrawals,
+    /// a deposit for every transaction id, followed by a dispute and a resolve of each
+    DisputeResolve,
+}
+
+/// generate a CSV file for the given workload.
+/// the setup rows (if any) come first, see [`split_setup`].
+fn generate(workload: Workload) -> String {
+    let mut rng = Rng(0x5EED);
+    let mut csv = String::from("type,client,tx,amount\n");
+    match workload {
+        Workload::Deposits => {
+            for tx in 0..TRANSACTIONS {
+                let client = rng.below(CLIENTS);
+                let amount = rng.below(1_000_000);
+                writeln!(
+                    csv,
+                    "deposit,{client},{tx},{}.{:04}",
+                    amount / 10_000,
+                    amount % 10_000
+                )
+                .unwrap();
+            }
+        }
+        Workload::Withdrawals => {
+            for client in 0..CLIENTS {
+                writeln!(csv, "deposit,{client},{client},100.0").unwrap();
+            }
+            for tx in CLIENTS..CLIENTS + TRANSACTIONS {
+                let client = rng.below(CLIENTS);
+                let amount = rng.below(10_000);
+                writeln!(csv, "withdrawal,{client},{tx},0.{amount:04}").unwrap();
+            }
+        }
+        Workload::DisputeResolve => {
+            for tx in 0..TRANSACTIONS {
+                let client = rng.below(CLIENTS);
+                writeln!(csv, "deposit,{client},{tx},1.0").unwrap();
+            }
+            for tx in 0..TRANSACTIONS {
+                writeln!(csv, "dispute,0,{tx},\nresolve,0,{tx},").unwrap();
+            }
+        }
+    }
+    csv
+}
+
+fn parse(csv: &str) -> Vec<AccountAction> {
+    csv::ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(csv.as_bytes())
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .expect("generated workload should be valid")
+}
+
+/// split the actions of a workload into the setup actions, which are applied outside of the measurement,
+/// and the actions that are measured.
+fn split_setup(
+    workload: Workload,
+    mut actions: Vec<AccountAction>,
+) -> (Database, Vec<AccountAction>) {
+    let setup = match workload {
+        Workload::Deposits => 0,
+        Workload::Withdrawals => CLIENTS as usize,
+        Workload::DisputeResolve => TRANSACTIONS as usize,
+    };
+    let measured = actions.split_off(setup);
+    let mut db = Database::new();
+    for action in actions {
+        db.perform_action(action)
+            .expect("setup actions should apply");
+    }
+    (db, measured)
+}
+
+fn perform_action(c: &mut Criterion) {
+    let mut group = c.benchmark_group("perform_action");
+    for (name, workload) in [
+        ("deposits", Workload::Deposits),
+        ("withdrawals", Workload::Withdrawals),
+        ("dispute_resolve", Workload::DisputeResolve),
+    ] {
+        let csv = generate(workload);
+        let (_, measured) = split_setup(workload, parse(&csv));
+        group.throughput(Throughput::Elements(measured.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || split_setup(workload, parse(&csv)),
+                |(mut db, actions)| {
+                    for action in actions {
+                        let _ = black_box(db.perform_action(action));
+                    }
+                    db
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// ingest a workload of known size into a database with and without reserving room for it first.
+///
+/// with the default store [`Database::with_capacity`] can't reserve, so both should be the same;
+/// this is the baseline for a store that can.
+fn with_capacity(c: &mut Criterion) {
+    let actions = || parse(&generate(Workload::Deposits));
+    let mut group = c.benchmark_group("ingest");
+    group.throughput(Throughput::Elements(TRANSACTIONS as u64));
+    for (name, reserve) in [("new", false), ("with_capacity", true)] {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                actions,
+                |actions| {
+                    let mut db = if reserve {
+                        Database::with_capacity(CLIENTS as usize, TRANSACTIONS as usize)
+                    } else {
+                        Database::new()
+                    };
+                    for action in actions {
+                        let _ = black_box(db.perform_action(action));
+                    }
+                    db
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// deserialize the records of a workload into actions, which is dominated by parsing the amounts.
+fn deserialize(c: &mut Criterion) {
+    let csv = generate(Workload::Deposits);
+    let mut group = c.benchmark_group("deserialize");
+    group.throughput(Throughput::Elements(TRANSACTIONS as u64));
+    group.bench_function("deposits", |b| b.iter(|| black_box(parse(&csv))));
+    group.finish();
+}
+
+fn serialize_clients(c: &mut Criterion) {
+    let (mut db, actions) = split_setup(Workload::Deposits, parse(&generate(Workload::Deposits)));
+    for action in actions {
+        db.perform_action(action).unwrap();
+    }
+    let mut group = c.benchmark_group("clients");
+    group.throughput(Throughput::Elements(db.clients().count() as u64));
+    group.bench_function("serialize", |b| {
+        b.iter(|| {
+            let mut wtr = csv::Writer::from_writer(Vec::new());
+            for client in db.clients() {
+                wtr.serialize(client).unwrap();
+            }
+            black_box(wtr.into_inner().unwrap())
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    perform_action,
+    with_capacity,
+    deserialize,
+    serialize_clients
+);
+criterion_main!(benches);
@@ -1,49 +1,393 @@
-use crate::{Amount, ClientId, TransactionId};
+use crate::{Amount, ClientId, DisputeId, SignedAmount, TransactionId};
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Deserializer};
 use std::fmt::Debug;
 
+/// The columns of an input file, in the order they are expected in files without a header row.
+/// Files with a header row may have the columns in any order.
+/// The `memo`, `reason`, `dispute_id`, and `basis_points` columns are optional.
+pub const COLUMNS: [&str; 8] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "memo",
+    "reason",
+    "dispute_id",
+    "basis_points",
+];
+
+/// The values of the `type` column that are read as an action, any other type is unknown.
+pub const TYPES: [&str; 9] = [
+    "deposit",
+    "withdrawal",
+    "dispute",
+    "resolve",
+    "cancel",
+    "chargeback",
+    "refund",
+    "close",
+    "adjustment",
+];
+
 /// An action (transaction) on a client's account.
+///
+/// More kinds of actions will be added over time, so matching on an action outside of this crate requires a wildcard arm:
+/// ```
+/// # use ledger::actions::AccountAction;
+/// fn moves_funds(action: &AccountAction) -> bool {
+///     match action {
+///         AccountAction::Deposit(_) | AccountAction::Withdrawal(_) => true,
+///         _ => false,
+///     }
+/// }
+/// ```
+/// without it, the match does not compile:
+/// ```compile_fail,E0004
+/// # use ledger::actions::AccountAction;
+/// fn moves_funds(action: &AccountAction) -> bool {
+///     match action {
+///         AccountAction::Deposit(_) | AccountAction::Withdrawal(_) => true,
+///         AccountAction::Dispute(_) | AccountAction::Resolve(_) | AccountAction::Chargeback(_) => false,
+///     }
+/// }
+/// ```
+#[derive(Clone)]
+#[non_exhaustive]
 pub enum AccountAction {
     Deposit(Deposit),
     Withdrawal(Withdrawal),
     Dispute(Dispute),
     Resolve(Resolve),
+    CancelDispute(CancelDispute),
     Chargeback(Chargeback),
+    Refund(Refund),
+    Close(Close),
+    Adjustment(Adjustment),
+    NoOp(NoOp),
+}
+
+/// The kind of an [`AccountAction`], without any of its data.
+///
+/// ordered so that the actions creating a transaction come before the actions referring to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ActionKind {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    CancelDispute,
+    Chargeback,
+    Refund,
+    Close,
+    Adjustment,
+    NoOp,
+}
+
+impl AccountAction {
+    /// the kind of this action.
+    pub fn kind(&self) -> ActionKind {
+        match self {
+            AccountAction::Deposit(_) => ActionKind::Deposit,
+            AccountAction::Withdrawal(_) => ActionKind::Withdrawal,
+            AccountAction::Dispute(_) => ActionKind::Dispute,
+            AccountAction::Resolve(_) => ActionKind::Resolve,
+            AccountAction::CancelDispute(_) => ActionKind::CancelDispute,
+            AccountAction::Chargeback(_) => ActionKind::Chargeback,
+            AccountAction::Refund(_) => ActionKind::Refund,
+            AccountAction::Close(_) => ActionKind::Close,
+            AccountAction::Adjustment(_) => ActionKind::Adjustment,
+            AccountAction::NoOp(_) => ActionKind::NoOp,
+        }
+    }
+
+    /// the client named by this action, e.g. for logging or routing. `None` for no-op actions.
+    ///
+    /// for disputes, resolves, cancellations, chargebacks, and refunds this is the client of the row, which is only used to find
+    /// the referred transaction if transaction ids are scoped per client; the transaction may belong to another client.
+    pub fn client_id(&self) -> Option<ClientId> {
+        match self {
+            AccountAction::Deposit(deposit) => Some(deposit.client_id),
+            AccountAction::Withdrawal(withdrawal) => Some(withdrawal.client_id),
+            AccountAction::Dispute(dispute) => Some(dispute.client_id),
+            AccountAction::Resolve(resolve) => Some(resolve.client_id),
+            AccountAction::CancelDispute(cancel) => Some(cancel.client_id),
+            AccountAction::Chargeback(chargeback) => Some(chargeback.client_id),
+            AccountAction::Refund(refund) => Some(refund.client_id),
+            AccountAction::Close(close) => Some(close.client_id),
+            AccountAction::Adjustment(adjustment) => Some(adjustment.client_id),
+            AccountAction::NoOp(_) => None,
+        }
+    }
+
+    /// the id of this transaction, or of the transaction it refers to.
+    ///
+    /// `None` for closures and no-op actions, and for resolves, cancellations, and chargebacks that only refer to a
+    /// dispute by its id.
+    pub fn transaction_id(&self) -> Option<TransactionId> {
+        match self {
+            AccountAction::Deposit(deposit) => Some(deposit.transaction_id),
+            AccountAction::Withdrawal(withdrawal) => Some(withdrawal.transaction_id),
+            AccountAction::Dispute(dispute) => Some(dispute.disputed_transaction),
+            AccountAction::Resolve(resolve) => resolve.disputed_transaction,
+            AccountAction::CancelDispute(cancel) => cancel.disputed_transaction,
+            AccountAction::Chargeback(chargeback) => chargeback.disputed_transaction,
+            AccountAction::Refund(refund) => Some(refund.refunded_transaction),
+            AccountAction::Adjustment(adjustment) => Some(adjustment.transaction_id),
+            AccountAction::Close(_) | AccountAction::NoOp(_) => None,
+        }
+    }
+
+    /// the bytes this action owns on the heap: its memo, reason code, or the type of a no-op row.
+    pub(crate) fn heap_bytes(&self) -> usize {
+        match self {
+            AccountAction::Deposit(Deposit { memo, .. })
+            | AccountAction::Withdrawal(Withdrawal { memo, .. })
+            | AccountAction::Adjustment(Adjustment { memo, .. }) => {
+                memo.as_ref().map_or(0, String::len)
+            }
+            AccountAction::Dispute(Dispute { reason, .. })
+            | AccountAction::Chargeback(Chargeback { reason, .. }) => {
+                reason.as_ref().map_or(0, |reason| reason.0.len())
+            }
+            AccountAction::NoOp(noop) => noop.kind.len(),
+            AccountAction::Resolve(_)
+            | AccountAction::CancelDispute(_)
+            | AccountAction::Refund(_)
+            | AccountAction::Close(_) => 0,
+        }
+    }
+
+    /// a key to sort buffered actions by, e.g. with `actions.sort_by_key(AccountAction::sort_key)`.
+    ///
+    /// orders by [`AccountAction::transaction_id`], then by [`ActionKind`], so a deposit comes before its disputes
+    /// and a dispute before its resolve or chargeback. actions without a transaction id come last; the sort is
+    /// stable, so they keep their relative order.
+    pub fn sort_key(&self) -> impl Ord + Copy {
+        let transaction_id = self.transaction_id();
+        (transaction_id.is_none(), transaction_id, self.kind())
+    }
 }
 
 /// A credit of funds to a client's account.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Deposit {
     pub(crate) client_id: ClientId,
     pub(crate) transaction_id: TransactionId,
     pub(crate) amount: Amount,
+    /// free-text reference kept for auditing, it has no effect on the balances.
+    pub(crate) memo: Option<String>,
 }
 
 /// A debit of funds from a client's account.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Withdrawal {
     pub(crate) client_id: ClientId,
     pub(crate) transaction_id: TransactionId,
     pub(crate) amount: Amount,
+    /// free-text reference kept for auditing, it has no effect on the balances.
+    pub(crate) memo: Option<String>,
+}
+
+impl Deposit {
+    /// the free-text memo of the deposit, if any.
+    pub fn memo(&self) -> Option<&str> {
+        self.memo.as_deref()
+    }
+}
+
+impl Withdrawal {
+    /// the free-text memo of the withdrawal, if any.
+    pub fn memo(&self) -> Option<&str> {
+        self.memo.as_deref()
+    }
+}
+
+/// A reason code of a dispute or chargeback, as assigned by the card network (e.g. `10.4` or `4837`).
+///
+/// reason codes are only kept for reporting, they have no effect on the balances.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct ReasonCode(pub(crate) String);
+
+impl ReasonCode {
+    /// a reason code with the given text, kept as is.
+    ///
+    /// nothing is rejected: like the codes read from the input, the code is not checked against the codes of
+    /// any network. it may even be empty, while an empty field in the input is read as no reason code.
+    pub fn new(code: impl Into<String>) -> Self {
+        Self(code.into())
+    }
+
+    /// the text of the reason code, as it was given.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
 }
 
 /// A dispute of a deposit.
-#[derive(Debug)]
+///
+/// Disputes (and their resolves and chargebacks) only ever refer to deposits, never to withdrawals.
+/// If a withdrawal shares its transaction id with a deposit, which is only possible for different clients when
+/// transaction ids are scoped per client, the dispute finds the deposit of the client it names, or nothing at all.
+/// a dispute of a known withdrawal fails with [`Error::CannotDisputeWithdrawal`](crate::Error::CannotDisputeWithdrawal).
+///
+/// a dispute can carry its own [`DisputeId`], which later resolves and chargebacks can refer to instead of the deposit.
+#[derive(Debug, Clone)]
 pub struct Dispute {
+    /// only used to find the deposit if transaction ids are scoped per client
+    pub(crate) client_id: ClientId,
     pub(crate) disputed_transaction: TransactionId,
+    pub(crate) dispute_id: Option<DisputeId>,
+    pub(crate) reason: Option<ReasonCode>,
 }
 
 /// A resolution of a dispute.
-#[derive(Debug)]
+///
+/// refers to the disputed deposit, the dispute by its id, or both, in which case they must agree.
+#[derive(Debug, Clone)]
 pub struct Resolve {
-    pub(crate) disputed_transaction: TransactionId,
+    /// only used to find the deposit if transaction ids are scoped per client
+    pub(crate) client_id: ClientId,
+    pub(crate) disputed_transaction: Option<TransactionId>,
+    pub(crate) dispute_id: Option<DisputeId>,
+}
+
+/// A cancellation of a dispute, e.g. because the customer withdrew it.
+///
+/// the held funds are released like by a [`Resolve`], but the deposit counts the cancellation, so withdrawn disputes
+/// can be told apart from disputes resolved by the network.
+/// refers to the disputed deposit, the dispute by its id, or both, in which case they must agree.
+#[derive(Debug, Clone)]
+pub struct CancelDispute {
+    /// only used to find the deposit if transaction ids are scoped per client
+    pub(crate) client_id: ClientId,
+    pub(crate) disputed_transaction: Option<TransactionId>,
+    pub(crate) dispute_id: Option<DisputeId>,
 }
 
 /// A chargeback of a disputed transaction.
 /// This locks the client's account.
-#[derive(Debug)]
+///
+/// refers to the disputed deposit, the dispute by its id, or both, in which case they must agree.
+/// a partial chargeback, one with basis points, charges back only part of the held funds,
+/// see [`Config::resolve_partial_chargeback_remainder`](crate::config::Config::resolve_partial_chargeback_remainder).
+#[derive(Debug, Clone)]
 pub struct Chargeback {
-    pub(crate) disputed_transaction: TransactionId,
+    /// only used to find the deposit if transaction ids are scoped per client
+    pub(crate) client_id: ClientId,
+    pub(crate) disputed_transaction: Option<TransactionId>,
+    pub(crate) dispute_id: Option<DisputeId>,
+    /// replaces the reason of the dispute if set
+    pub(crate) reason: Option<ReasonCode>,
+    /// the part of the held funds that is charged back, in basis points (1/100th of a percent).
+    /// all held funds are charged back if `None`.
+    pub(crate) basis_points: Option<u32>,
+}
+
+/// A refund of an erroneous withdrawal, which credits the withdrawn funds back to the client.
+/// A withdrawal can only be refunded once.
+#[derive(Debug, Clone)]
+pub struct Refund {
+    /// only used to find the withdrawal if transaction ids are scoped per client
+    pub(crate) client_id: ClientId,
+    pub(crate) refunded_transaction: TransactionId,
+}
+
+/// A closure of a client's account, e.g. when offboarding a client.
+///
+/// unlike a lock, which only blocks withdrawals, a closed account accepts no more actions at all.
+/// an account can only be closed if it has no held funds or open disputes.
+#[derive(Debug, Clone)]
+pub struct Close {
+    pub(crate) client_id: ClientId,
+}
+
+/// A manual correction of a client's available funds, e.g. to undo an error made by operations.
+///
+/// the amount is signed: a positive adjustment credits the account, a negative one debits it, even below zero.
+/// adjustments are kept apart from deposits and withdrawals so they can be audited separately,
+/// and can't be disputed or refunded.
+#[derive(Debug, Clone)]
+pub struct Adjustment {
+    pub(crate) client_id: ClientId,
+    pub(crate) transaction_id: TransactionId,
+    pub(crate) signed_amount: SignedAmount,
+    /// free-text reference kept for auditing, it has no effect on the balances.
+    pub(crate) memo: Option<String>,
+}
+
+impl Adjustment {
+    /// the amount credited to, or if negative debited from, the available funds.
+    pub fn signed_amount(&self) -> SignedAmount {
+        self.signed_amount
+    }
+
+    /// the free-text memo of the adjustment, if any.
+    pub fn memo(&self) -> Option<&str> {
+        self.memo.as_deref()
+    }
+}
+
+/// A control row of the input, such as a `heartbeat`, which is accepted without any effect.
+#[derive(Debug, Clone)]
+pub struct NoOp {
+    /// the type of the row, as it appeared in the input
+    pub(crate) kind: String,
+}
+
+impl NoOp {
+    pub fn new(kind: impl Into<String>) -> Self {
+        Self { kind: kind.into() }
+    }
+
+    /// the type of the row, as it appeared in the input.
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+}
+
+impl Dispute {
+    /// the reason code of the dispute, if any.
+    pub fn reason(&self) -> Option<&ReasonCode> {
+        self.reason.as_ref()
+    }
+
+    /// the id assigned to the dispute, if any.
+    pub fn dispute_id(&self) -> Option<DisputeId> {
+        self.dispute_id
+    }
+}
+
+impl Resolve {
+    /// the id of the resolved dispute, if it is referred to by id.
+    pub fn dispute_id(&self) -> Option<DisputeId> {
+        self.dispute_id
+    }
+}
+
+impl CancelDispute {
+    /// the id of the cancelled dispute, if it is referred to by id.
+    pub fn dispute_id(&self) -> Option<DisputeId> {
+        self.dispute_id
+    }
+}
+
+impl Chargeback {
+    /// the reason code of the chargeback, if any.
+    pub fn reason(&self) -> Option<&ReasonCode> {
+        self.reason.as_ref()
+    }
+
+    /// the id of the charged back dispute, if it is referred to by id.
+    pub fn dispute_id(&self) -> Option<DisputeId> {
+        self.dispute_id
+    }
+
+    /// the part of the held funds that is charged back in basis points, `None` for all of them.
+    pub fn basis_points(&self) -> Option<u32> {
+        self.basis_points
+    }
 }
 
 impl Debug for AccountAction {
@@ -53,11 +397,17 @@ impl Debug for AccountAction {
             AccountAction::Withdrawal(withdrawal) => f.write_fmt(format_args!("{:?}", withdrawal)),
             AccountAction::Dispute(dispute) => f.write_fmt(format_args!("{:?}", dispute)),
             AccountAction::Resolve(resolve) => f.write_fmt(format_args!("{:?}", resolve)),
+            AccountAction::CancelDispute(cancel) => f.write_fmt(format_args!("{:?}", cancel)),
             AccountAction::Chargeback(chargeback) => f.write_fmt(format_args!("{:?}", chargeback)),
+            AccountAction::Refund(refund) => f.write_fmt(format_args!("{:?}", refund)),
+            AccountAction::Close(close) => f.write_fmt(format_args!("{:?}", close)),
+            AccountAction::Adjustment(adjustment) => f.write_fmt(format_args!("{:?}", adjustment)),
+            AccountAction::NoOp(noop) => f.write_fmt(format_args!("{:?}", noop)),
         }
     }
 }
 
+#[cfg(feature = "serde")]
 impl<'de> Deserialize<'de> for AccountAction {
     fn deserialize<D>(deserializer: D) -> Result<AccountAction, D::Error>
     where
@@ -70,7 +420,12 @@ impl<'de> Deserialize<'de> for AccountAction {
             Withdrawal,
             Dispute,
             Resolve,
+            #[serde(rename = "cancel")]
+            CancelDispute,
             Chargeback,
+            Refund,
+            Close,
+            Adjustment,
         }
 
         #[derive(Deserialize)]
@@ -78,15 +433,33 @@ impl<'de> Deserialize<'de> for AccountAction {
             //https://github.com/BurntSushi/rust-csv/issues/354 applies here unfortunately
             #[serde(rename = "type")]
             kind: TransactionType,
-            client: u16,
-            tx: u32,
-            amount: Option<Amount>,
+            client: crate::ClientIdRepr,
+            // only missing for closures, which don't refer to a transaction
+            tx: Option<crate::TransactionIdRepr>,
+            // signed, only adjustments may be negative
+            amount: Option<SignedAmount>,
+            // optional column, most feeds don't have it
+            #[serde(default)]
+            memo: Option<String>,
+            // optional column, only set for disputes and chargebacks
+            #[serde(default)]
+            reason: Option<ReasonCode>,
+            // optional column, only set for disputes, resolves, cancellations, and chargebacks
+            #[serde(default)]
+            dispute_id: Option<u32>,
+            // optional column, only set for partial chargebacks
+            #[serde(default)]
+            basis_points: Option<u32>,
         }
         let TransactionRecord {
             kind,
             client,
             tx,
             amount,
+            memo,
+            reason,
+            dispute_id,
+            basis_points,
         } = TransactionRecord::deserialize(deserializer)?;
 
         match kind {
@@ -97,44 +470,144 @@ impl<'de> Deserialize<'de> for AccountAction {
                         "missing amount for deposit or withdrawal",
                     ));
                 }
+                if amount.is_some_and(|amount| amount.is_negative()) {
+                    return Err(serde::de::Error::custom(
+                        "negative amount for deposit or withdrawal",
+                    ));
+                }
+            }
+            TransactionType::Adjustment => {
+                if amount.is_none() {
+                    return Err(serde::de::Error::custom("missing amount for adjustment"));
+                }
             }
-            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
-                // amount _must_ be missing for disputes, resolves, and chargebacks
+            TransactionType::Dispute
+            | TransactionType::Resolve
+            | TransactionType::CancelDispute
+            | TransactionType::Chargeback => {
+                // amount _must_ be missing for disputes, resolves, cancellations, and chargebacks
                 if amount.is_some() {
                     return Err(serde::de::Error::custom(
-                        "amount set for dispute, resolve, or chargeback",
+                        "amount set for dispute, resolve, cancel, or chargeback",
+                    ));
+                }
+            }
+            TransactionType::Refund => {
+                // the amount of a refund is the amount of the withdrawal
+                if amount.is_some() {
+                    return Err(serde::de::Error::custom("amount set for refund"));
+                }
+            }
+            TransactionType::Close => {
+                if amount.is_some() || tx.is_some() {
+                    return Err(serde::de::Error::custom(
+                        "transaction id or amount set for close",
                     ));
                 }
             }
         };
+        if reason.is_some()
+            && !matches!(kind, TransactionType::Dispute | TransactionType::Chargeback)
+        {
+            return Err(serde::de::Error::custom(
+                "reason set for deposit, withdrawal, resolve, cancel, refund, close, or adjustment",
+            ));
+        }
+        if dispute_id.is_some()
+            && !matches!(
+                kind,
+                TransactionType::Dispute
+                    | TransactionType::Resolve
+                    | TransactionType::CancelDispute
+                    | TransactionType::Chargeback
+            )
+        {
+            return Err(serde::de::Error::custom(
+                "dispute id set for deposit, withdrawal, refund, close, or adjustment",
+            ));
+        }
+        if let Some(basis_points) = basis_points {
+            if !matches!(kind, TransactionType::Chargeback) {
+                return Err(serde::de::Error::custom(
+                    "basis points set for deposit, withdrawal, dispute, resolve, cancel, refund, close, or adjustment",
+                ));
+            }
+            if !(1..=10_000).contains(&basis_points) {
+                return Err(serde::de::Error::custom(
+                    "basis points of a chargeback must be between 1 and 10000",
+                ));
+            }
+        }
+        let dispute_id = dispute_id.map(DisputeId);
+        // the transaction id _must_ be set for everything but closures,
+        // and resolves, cancellations, and chargebacks that refer to the dispute by its id
+        let by_dispute_id = dispute_id.is_some()
+            && matches!(
+                kind,
+                TransactionType::Resolve
+                    | TransactionType::CancelDispute
+                    | TransactionType::Chargeback
+            );
+        if tx.is_none() && !matches!(kind, TransactionType::Close) && !by_dispute_id {
+            return Err(serde::de::Error::custom("missing transaction id"));
+        }
 
         Ok(match kind {
             TransactionType::Deposit => AccountAction::Deposit(Deposit {
                 client_id: ClientId(client),
-                transaction_id: TransactionId(tx),
-                amount: amount.unwrap(),
+                transaction_id: TransactionId(tx.unwrap()),
+                amount: amount.unwrap().magnitude(),
+                memo,
             }),
             TransactionType::Withdrawal => AccountAction::Withdrawal(Withdrawal {
                 client_id: ClientId(client),
-                transaction_id: TransactionId(tx),
-                amount: amount.unwrap(),
+                transaction_id: TransactionId(tx.unwrap()),
+                amount: amount.unwrap().magnitude(),
+                memo,
             }),
             TransactionType::Dispute => AccountAction::Dispute(Dispute {
-                disputed_transaction: TransactionId(tx),
+                client_id: ClientId(client),
+                disputed_transaction: TransactionId(tx.unwrap()),
+                dispute_id,
+                reason,
             }),
             TransactionType::Resolve => AccountAction::Resolve(Resolve {
-                disputed_transaction: TransactionId(tx),
+                client_id: ClientId(client),
+                disputed_transaction: tx.map(TransactionId),
+                dispute_id,
+            }),
+            TransactionType::CancelDispute => AccountAction::CancelDispute(CancelDispute {
+                client_id: ClientId(client),
+                disputed_transaction: tx.map(TransactionId),
+                dispute_id,
             }),
             TransactionType::Chargeback => AccountAction::Chargeback(Chargeback {
-                disputed_transaction: TransactionId(tx),
+                client_id: ClientId(client),
+                disputed_transaction: tx.map(TransactionId),
+                dispute_id,
+                reason,
+                basis_points,
+            }),
+            TransactionType::Refund => AccountAction::Refund(Refund {
+                client_id: ClientId(client),
+                refunded_transaction: TransactionId(tx.unwrap()),
+            }),
+            TransactionType::Close => AccountAction::Close(Close {
+                client_id: ClientId(client),
+            }),
+            TransactionType::Adjustment => AccountAction::Adjustment(Adjustment {
+                client_id: ClientId(client),
+                transaction_id: TransactionId(tx.unwrap()),
+                signed_amount: amount.unwrap(),
+                memo,
             }),
         })
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "serde"))]
 mod tests {
-    use super::AccountAction;
+    use super::{AccountAction, ActionKind};
     /// ensure the amount field must be present for deposits and withdrawals
     #[test]
     fn amount_present() {
@@ -171,4 +644,275 @@ mod tests {
         assert!(records.next().is_some_and(|x| x.is_ok()));
         assert!(records.next().is_none());
     }
+
+    /// ensure reason codes are read for disputes and chargebacks, and rejected for other actions
+    #[test]
+    fn reason() {
+        let entry = "type,client,tx,amount,reason\ndispute,1,1,,10.4\nchargeback,1,1,,\ndeposit,1,2,1.0,10.4\nresolve,1,1,,4837\n";
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(entry.as_bytes());
+        let mut records = reader.deserialize::<AccountAction>();
+        assert!(matches!(
+            records.next(),
+            Some(Ok(AccountAction::Dispute(dispute))) if dispute.reason().is_some_and(|reason| reason.as_str() == "10.4")
+        ));
+        assert!(matches!(
+            records.next(),
+            Some(Ok(AccountAction::Chargeback(chargeback))) if chargeback.reason().is_none()
+        ));
+        assert!(records.next().is_some_and(|x| x.is_err()));
+        assert!(records.next().is_some_and(|x| x.is_err()));
+        assert!(records.next().is_none());
+    }
+
+    /// ensure refunds are read without an amount or reason
+    #[test]
+    fn refund() {
+        let entry =
+            "type,client,tx,amount,reason\nrefund,1,2,,\nrefund,1,2,1.0,\nrefund,1,2,,10.4\n";
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(entry.as_bytes());
+        let mut records = reader.deserialize::<AccountAction>();
+        assert!(matches!(
+            records.next(),
+            Some(Ok(AccountAction::Refund(refund))) if refund.refunded_transaction.0 == 2
+        ));
+        assert!(records.next().is_some_and(|x| x.is_err()));
+        assert!(records.next().is_some_and(|x| x.is_err()));
+        assert!(records.next().is_none());
+    }
+
+    /// ensure closures are read without a transaction id, which every other action requires
+    #[test]
+    fn close() {
+        let entry = "type,client,tx,amount\nclose,1,,\nclose,1,2,\ndeposit,1,,1.0\n";
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(entry.as_bytes());
+        let mut records = reader.deserialize::<AccountAction>();
+        assert!(matches!(
+            records.next(),
+            Some(Ok(AccountAction::Close(close))) if close.client_id.0 == 1
+        ));
+        assert!(records.next().is_some_and(|x| x.is_err()));
+        assert!(records.next().is_some_and(|x| x.is_err()));
+        assert!(records.next().is_none());
+    }
+
+    /// ensure dispute ids are read for disputes, resolves, and chargebacks, which may then omit the transaction id
+    #[test]
+    fn dispute_id() {
+        let entry = "type,client,tx,amount,dispute_id\ndispute,1,1,,7\nresolve,1,,,7\nchargeback,1,1,,7\nresolve,1,,,\ndeposit,1,2,1.0,7\ndispute,1,,,7\n";
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(entry.as_bytes());
+        let mut records = reader.deserialize::<AccountAction>();
+        assert!(matches!(
+            records.next(),
+            Some(Ok(AccountAction::Dispute(dispute))) if dispute.dispute_id().is_some_and(|id| id.0 == 7)
+        ));
+        assert!(matches!(
+            records.next(),
+            Some(Ok(AccountAction::Resolve(resolve))) if resolve.disputed_transaction.is_none() && resolve.dispute_id().is_some()
+        ));
+        assert!(matches!(
+            records.next(),
+            Some(Ok(AccountAction::Chargeback(chargeback))) if chargeback.disputed_transaction.is_some() && chargeback.dispute_id().is_some()
+        ));
+        assert!(records.next().is_some_and(|x| x.is_err()));
+        assert!(records.next().is_some_and(|x| x.is_err()));
+        // a dispute always names the deposit it disputes
+        assert!(records.next().is_some_and(|x| x.is_err()));
+        assert!(records.next().is_none());
+    }
+
+    /// ensure basis points are read for chargebacks only, and must be a share of at most 100%
+    #[test]
+    fn basis_points() {
+        let entry = "type,client,tx,amount,basis_points\nchargeback,1,1,,5000\nchargeback,1,1,,\nchargeback,1,1,,0\nchargeback,1,1,,10001\ndispute,1,1,,5000\n";
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(entry.as_bytes());
+        let mut records = reader.deserialize::<AccountAction>();
+        assert!(matches!(
+            records.next(),
+            Some(Ok(AccountAction::Chargeback(chargeback))) if chargeback.basis_points() == Some(5_000)
+        ));
+        assert!(matches!(
+            records.next(),
+            Some(Ok(AccountAction::Chargeback(chargeback))) if chargeback.basis_points().is_none()
+        ));
+        assert!(records.next().is_some_and(|x| x.is_err()));
+        assert!(records.next().is_some_and(|x| x.is_err()));
+        assert!(records.next().is_some_and(|x| x.is_err()));
+        assert!(records.next().is_none());
+    }
+
+    /// ensure cancellations are read like resolves, without an amount and with a transaction or dispute id
+    #[test]
+    fn cancel() {
+        let entry = "type,client,tx,amount,dispute_id\ncancel,1,1,,\ncancel,1,,,7\ncancel,1,1,1.0,\ncancel,1,,,\n";
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(entry.as_bytes());
+        let mut records = reader.deserialize::<AccountAction>();
+        assert!(matches!(
+            records.next(),
+            Some(Ok(AccountAction::CancelDispute(cancel))) if cancel.disputed_transaction.is_some_and(|tx| tx.0 == 1)
+        ));
+        assert!(matches!(
+            records.next(),
+            Some(Ok(AccountAction::CancelDispute(cancel))) if cancel.disputed_transaction.is_none() && cancel.dispute_id().is_some()
+        ));
+        assert!(records.next().is_some_and(|x| x.is_err()));
+        assert!(records.next().is_some_and(|x| x.is_err()));
+        assert!(records.next().is_none());
+    }
+
+    /// ensure the ids of every kind of action are found
+    #[test]
+    fn ids() {
+        use super::{Close, Deposit, Dispute, NoOp, Refund, Resolve, Withdrawal};
+        use crate::{Amount, ClientId, DisputeId, TransactionId};
+
+        let actions = [
+            AccountAction::Deposit(Deposit {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(1),
+                amount: Amount(1),
+                memo: None,
+            }),
+            AccountAction::Withdrawal(Withdrawal {
+                client_id: ClientId(2),
+                transaction_id: TransactionId(2),
+                amount: Amount(1),
+                memo: None,
+            }),
+            AccountAction::Dispute(Dispute {
+                client_id: ClientId(3),
+                disputed_transaction: TransactionId(3),
+                dispute_id: None,
+                reason: None,
+            }),
+            AccountAction::Resolve(Resolve {
+                client_id: ClientId(4),
+                disputed_transaction: Some(TransactionId(4)),
+                dispute_id: None,
+            }),
+            AccountAction::Chargeback(super::Chargeback {
+                client_id: ClientId(5),
+                disputed_transaction: None,
+                dispute_id: Some(DisputeId(5)),
+                reason: None,
+                basis_points: None,
+            }),
+            AccountAction::Refund(Refund {
+                client_id: ClientId(6),
+                refunded_transaction: TransactionId(6),
+            }),
+            AccountAction::Close(Close {
+                client_id: ClientId(7),
+            }),
+            AccountAction::NoOp(NoOp::new("heartbeat")),
+        ];
+        let ids = actions
+            .iter()
+            .map(|action| (action.client_id(), action.transaction_id()))
+            .collect::<Vec<_>>();
+        let some = |id| {
+            (
+                Some(ClientId(id)),
+                Some(TransactionId(id as crate::TransactionIdRepr)),
+            )
+        };
+        assert_eq!(
+            ids,
+            [
+                some(1),
+                some(2),
+                some(3),
+                some(4),
+                (Some(ClientId(5)), None),
+                some(6),
+                (Some(ClientId(7)), None),
+                (None, None)
+            ]
+        );
+    }
+
+    /// ensure sorting by the sort key puts transactions before the actions referring to them
+    #[test]
+    fn sort_key() {
+        use crate::{
+            database::tests::{chargeback, close, deposit, dispute, resolve},
+            TransactionId,
+        };
+
+        let mut actions = [
+            close(1),
+            chargeback(1, 1),
+            dispute(1, 2),
+            dispute(1, 1),
+            resolve(1, 2),
+            deposit(1, 2, 1),
+            deposit(1, 1, 1),
+        ];
+        actions.sort_by_key(AccountAction::sort_key);
+        let order = actions
+            .iter()
+            .map(|action| (action.kind(), action.transaction_id()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            order,
+            [
+                (ActionKind::Deposit, Some(TransactionId(1))),
+                (ActionKind::Dispute, Some(TransactionId(1))),
+                (ActionKind::Chargeback, Some(TransactionId(1))),
+                (ActionKind::Deposit, Some(TransactionId(2))),
+                (ActionKind::Dispute, Some(TransactionId(2))),
+                (ActionKind::Resolve, Some(TransactionId(2))),
+                (ActionKind::Close, None),
+            ]
+        );
+    }
+
+    /// ensure adjustments are read with a signed amount, which deposits and withdrawals can't have
+    #[test]
+    fn adjustment() {
+        use crate::{Amount, SignedAmount};
+
+        let entry = "type,client,tx,amount\nadjustment,1,1,2.5\nadjustment,1,2,-1.25\nadjustment,1,3,\ndeposit,1,4,-1.0\nwithdrawal,1,5,-1.0\n";
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(entry.as_bytes());
+        let mut records = reader.deserialize::<AccountAction>();
+        assert!(matches!(
+            records.next(),
+            Some(Ok(AccountAction::Adjustment(adjustment))) if adjustment.signed_amount() == SignedAmount::credit(Amount(2_5000))
+        ));
+        assert!(matches!(
+            records.next(),
+            Some(Ok(AccountAction::Adjustment(adjustment))) if adjustment.signed_amount() == SignedAmount::debit(Amount(1_2500))
+        ));
+        assert!(records.next().is_some_and(|x| x.is_err()));
+        assert!(records.next().is_some_and(|x| x.is_err()));
+        assert!(records.next().is_some_and(|x| x.is_err()));
+        assert!(records.next().is_none());
+    }
 }
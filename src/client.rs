@@ -1,9 +1,9 @@
-use crate::{Amount, Balance, Error, Result};
+use crate::{Amount, Balance, Error, Result, SignedAmount};
 
 /// A client's account.
 ///
-/// keeps track of the available funds, held funds, and if the account is locked.
-#[derive(Debug, Default)]
+/// keeps track of the available funds, held funds, and if the account is locked or closed.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct Client {
     /// The total funds available for withdrawal etc. This can go negative due to disputes.
     pub(crate) available: Balance,
@@ -11,19 +11,111 @@ pub struct Client {
     /// and always be positive
     pub(crate) held: Balance,
     pub(crate) locked: bool,
+    /// A closed account accepts no more actions, where a locked one only blocks withdrawals.
+    pub(crate) closed: bool,
+    /// The number of deposits of this client that have been charged back.
+    pub(crate) chargebacks: u32,
+}
+
+/// Builds a [`Client`] in a specific state for tests, see [`Client::builder`].
+///
+/// all fields start out like a new client's: no funds, not locked or closed, and no chargebacks.
+#[cfg(feature = "test-util")]
+#[derive(Debug, Default, Clone)]
+pub struct ClientBuilder {
+    client: Client,
+    check_total: bool,
+}
+
+#[cfg(feature = "test-util")]
+impl ClientBuilder {
+    /// the funds available for withdrawal, which may be negative.
+    pub fn available(mut self, available: Balance) -> Self {
+        self.client.available = available;
+        self
+    }
+
+    /// the funds held for disputes, which must not be negative.
+    pub fn held(mut self, held: Balance) -> Self {
+        self.client.held = held;
+        self
+    }
+
+    /// whether the account is locked, which blocks withdrawals, e.g. after a chargeback.
+    pub fn locked(mut self, locked: bool) -> Self {
+        self.client.locked = locked;
+        self
+    }
+
+    /// whether the account is closed, which blocks all actions.
+    pub fn closed(mut self, closed: bool) -> Self {
+        self.client.closed = closed;
+        self
+    }
+
+    /// the number of charged back deposits.
+    pub fn chargebacks(mut self, chargebacks: u32) -> Self {
+        self.client.chargebacks = chargebacks;
+        self
+    }
+
+    /// whether to also check the total of the available and held funds can be computed without an overflow,
+    /// so [`Client::total`] won't panic.
+    pub fn check_total(mut self, check_total: bool) -> Self {
+        self.check_total = check_total;
+        self
+    }
+
+    /// the client, or [`Error::NegativeHeld`] if the held funds are negative,
+    /// and [`Error::Overflow`] if the total overflows and [`ClientBuilder::check_total`] is set.
+    pub fn build(self) -> Result<Client> {
+        if self.client.held.is_negative() {
+            return Err(Error::NegativeHeld);
+        }
+        if self.check_total && self.client.checked_total().is_none() {
+            return Err(Error::Overflow);
+        }
+        Ok(self.client)
+    }
 }
 
 impl Client {
+    /// a client with the given balances, e.g. to test code on top of the state machine with a client in a specific state.
+    ///
+    /// the balances are taken as is, without checking that they could have been reached by any sequence of actions.
+    #[cfg(feature = "test-util")]
+    pub fn with_balances(available: Balance, held: Balance, locked: bool) -> Self {
+        Self {
+            available,
+            held,
+            locked,
+            closed: false,
+            chargebacks: 0,
+        }
+    }
+
+    /// a builder for a client in a specific state, that checks the state is consistent unlike [`Client::with_balances`].
+    #[cfg(feature = "test-util")]
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+
     /// Returns the total funds in the account. This is the sum of the available and held funds.
     pub fn total(&self) -> Balance {
         // we don't return an error on overflow here because it should be impossible to even hit this case.
         // if we do manage to overflow here, something has gone _very_ wrong and panicking is the correct response.
-        Balance(
-            self.available
-                .0
-                .checked_add(self.held.0)
-                .expect("i128 overflow occured when adding held balance to the available balance"),
-        )
+        self.checked_total()
+            .expect("i128 overflow occured when adding held balance to the available balance")
+    }
+
+    /// Returns the total funds in the account, or `None` if the sum of the available and held funds overflows.
+    pub fn checked_total(&self) -> Option<Balance> {
+        self.available.0.checked_add(self.held.0).map(Balance)
+    }
+
+    /// Returns the total funds in the account, clamped to the range of a [`Balance`] if the sum overflows.
+    pub fn saturating_total(&self) -> Balance {
+        Balance(self.available.0.saturating_add(self.held.0))
     }
 
     /// Returns the held funds in the account. That is, the funds that are currently held for dispute.
@@ -43,34 +135,96 @@ impl Client {
         self.locked
     }
 
-    /// Deposit funds into the account.
+    /// whether the account is closed.
     ///
-    /// this will fail if an overflow occurs.
-    pub(crate) fn deposit(&mut self, amount: Amount) -> Result<()> {
-        self.available = self.available.try_add(amount)?;
-        Ok(())
+    /// a closed account accepts no more deposits, withdrawals, disputes, or refunds.
+    pub fn is_closed(&self) -> bool {
+        self.closed
     }
 
-    /// Withdraw funds from the account.
+    /// the number of deposits of this client that have been charged back.
+    pub fn chargebacks(&self) -> u32 {
+        self.chargebacks
+    }
+
+    /// the available funds after depositing `amount`, without changing the account.
     ///
-    /// this will fail if the account is locked, there are insufficient funds, or an underflow occurs.
-    pub(crate) fn withdraw(&mut self, amount: Amount) -> Result<()> {
+    /// fails like [`Client::deposit`] would.
+    pub fn available_after_deposit(&self, amount: Amount) -> Result<Balance> {
+        if self.is_closed() {
+            return Err(Error::AccountClosed);
+        }
+        self.available.try_add(amount)
+    }
+
+    /// the available funds after withdrawing `amount`, without changing the account.
+    /// e.g. to show "you will have X left" before confirming a withdrawal.
+    ///
+    /// fails like [`Client::withdraw`] would.
+    pub fn available_after_withdrawal(&self, amount: Amount) -> Result<Balance> {
+        if self.is_closed() {
+            return Err(Error::AccountClosed);
+        }
         if self.is_locked() {
             return Err(Error::AccountLocked);
         }
         if self.available.0 < amount.0 as i128 {
-            return Err(Error::InsufficientFunds);
+            return Err(Error::InsufficientFunds {
+                requested: amount,
+                available: self.available,
+            });
         }
         // this line should never fail because we have already checked that available >= amount
-        self.available = self.available.try_sub(amount)?;
+        self.available.try_sub(amount)
+    }
+
+    /// the available funds after adjusting them by `amount`, without changing the account.
+    ///
+    /// fails like [`Client::adjust`] would.
+    pub fn available_after_adjustment(&self, amount: SignedAmount) -> Result<Balance> {
+        if self.is_closed() {
+            return Err(Error::AccountClosed);
+        }
+        if amount.is_negative() {
+            self.available.try_sub(amount.magnitude())
+        } else {
+            self.available.try_add(amount.magnitude())
+        }
+    }
+
+    /// Deposit funds into the account.
+    ///
+    /// this will fail if the account is closed or an overflow occurs.
+    pub(crate) fn deposit(&mut self, amount: Amount) -> Result<()> {
+        self.available = self.available_after_deposit(amount)?;
+        Ok(())
+    }
+
+    /// Withdraw funds from the account.
+    ///
+    /// this will fail if the account is locked or closed, there are insufficient funds, or an underflow occurs.
+    pub(crate) fn withdraw(&mut self, amount: Amount) -> Result<()> {
+        self.available = self.available_after_withdrawal(amount)?;
+        Ok(())
+    }
+
+    /// Adjust the available funds by a signed amount, as a manual correction.
+    ///
+    /// unlike a withdrawal, a negative adjustment applies to a locked account and can take the available funds below zero.
+    /// this will fail if the account is closed or an over- or underflow occurs.
+    pub(crate) fn adjust(&mut self, amount: SignedAmount) -> Result<()> {
+        self.available = self.available_after_adjustment(amount)?;
         Ok(())
     }
 
     /// Hold funds in the account for dispute.
     /// This will move funds from the available balance to the held balance.
     ///
-    /// This function can fail if an overflow or underflow occurs.
+    /// This function can fail if the account is closed, or if an overflow or underflow occurs.
     pub(crate) fn hold(&mut self, amount: Amount) -> Result<()> {
+        if self.closed {
+            return Err(Error::AccountClosed);
+        }
         let new_held = self.held.try_add(amount);
         let new_available = self.available.try_sub(amount);
         match (new_held, new_available) {
@@ -102,6 +256,21 @@ impl Client {
         }
     }
 
+    /// Close the account, after which it accepts no more actions.
+    ///
+    /// This will fail if the account is already closed or has held funds.
+    /// Disputes of zero held nothing, so the caller has to check for those.
+    pub(crate) fn close(&mut self) -> Result<()> {
+        if self.closed {
+            return Err(Error::AccountClosed);
+        }
+        if self.held != Balance::zero() {
+            return Err(Error::OpenDisputes);
+        }
+        self.closed = true;
+        Ok(())
+    }
+
     /// Chargeback a dispute. Locking the account.
     ///
     /// This function can fail if and underflow occurs, or there are insufficient held funds (If this occurs, there is a bug in the code).
@@ -118,6 +287,7 @@ impl Client {
 }
 
 #[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
 mod tests {
     use super::{Amount, Balance, Client};
 
@@ -175,6 +345,26 @@ mod tests {
         assert!(client.total().0 == -3);
     }
 
+    /// ensure the total variants behave as documented when the sum overflows
+    #[test]
+    fn total_overflow() {
+        let mut client = Client::default();
+        client.available = Balance(i128::MAX);
+        client.held = Balance(0);
+        assert!(client
+            .checked_total()
+            .is_some_and(|total| total.0 == i128::MAX));
+        client.held = Balance(1);
+        assert!(client.checked_total().is_none());
+        assert!(client.saturating_total().0 == i128::MAX);
+        assert!(std::panic::catch_unwind(|| client.total()).is_err());
+
+        client.available = Balance(i128::MIN);
+        client.held = Balance(-1);
+        assert!(client.checked_total().is_none());
+        assert!(client.saturating_total().0 == i128::MIN);
+    }
+
     /// ensure that we can put a hold on a client even if they have negative funds
     #[test]
     fn hold_negative() {
@@ -186,6 +376,49 @@ mod tests {
         assert!(client.held.0 == 5);
     }
 
+    /// ensure the entire available balance can be withdrawn, leaving exactly zero, unless the account is locked
+    #[test]
+    fn exact_withdrawal() {
+        let mut client = Client::default();
+        client.available = Balance(1_2345);
+        client.held = Balance(5);
+        client.locked = true;
+        assert!(client.withdraw(Amount(1_2345)).is_err());
+        assert!(client.available.0 == 1_2345);
+        client.locked = false;
+        assert!(client
+            .available_after_withdrawal(Amount(1_2345))
+            .is_ok_and(|after| after.0 == 0));
+        assert!(client.withdraw(Amount(1_2345)).is_ok());
+        assert!(client.available.0 == 0);
+        assert_eq!(format!("{:?}", client.available), "0.0000");
+        // the held funds are not touched
+        assert!(client.held.0 == 5);
+    }
+
+    /// ensure a withdrawal of one unit more than the available balance fails without changing the account
+    #[test]
+    fn withdrawal_one_over() {
+        let mut client = Client::default();
+        client.available = Balance(1_2345);
+        client.held = Balance(5);
+        assert!(matches!(
+            client.withdraw(Amount(1_2346)),
+            Err(crate::Error::InsufficientFunds {
+                requested: Amount(1_2346),
+                available: Balance(1_2345)
+            })
+        ));
+        assert!(client.available.0 == 1_2345);
+        assert!(client.held.0 == 5);
+        // locked takes precedence over insufficient funds
+        client.locked = true;
+        assert!(matches!(
+            client.withdraw(Amount(1_2346)),
+            Err(crate::Error::AccountLocked)
+        ));
+    }
+
     /// ensure locked accounts can't be withdrawn from
     #[test]
     fn locked_withdraw() {
@@ -239,4 +472,120 @@ mod tests {
         assert!(client.available.0 == 1);
         assert!(client.hold(Amount(1)).is_ok());
     }
+
+    /// ensure clients with the same fields compare equal, regardless of how they got there
+    #[test]
+    fn equality() {
+        let mut deposited = Client::default();
+        assert!(deposited.deposit(Amount(5)).is_ok());
+        assert!(deposited.hold(Amount(2)).is_ok());
+        let expected = Client {
+            available: Balance(3),
+            held: Balance(2),
+            locked: false,
+            closed: false,
+            chargebacks: 0,
+        };
+        assert_eq!(deposited, expected);
+        assert!(deposited.chargeback(Amount(2)).is_ok());
+        assert_ne!(deposited, expected);
+    }
+
+    /// ensure previews return what the mutation would do, without changing the account
+    #[test]
+    fn available_after() {
+        let mut client = Client::default();
+        client.available = Balance(5);
+        assert!(client
+            .available_after_withdrawal(Amount(2))
+            .is_ok_and(|available| available == Balance(3)));
+        assert!(matches!(
+            client.available_after_withdrawal(Amount(6)),
+            Err(crate::Error::InsufficientFunds {
+                requested: Amount(6),
+                available: Balance(5)
+            })
+        ));
+        assert!(client
+            .available_after_deposit(Amount(1))
+            .is_ok_and(|available| available == Balance(6)));
+        assert!(client.available.0 == 5);
+        client.locked = true;
+        assert!(matches!(
+            client.available_after_withdrawal(Amount(1)),
+            Err(crate::Error::AccountLocked)
+        ));
+    }
+
+    /// ensure the test constructor sets exactly the given fields
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn with_balances() {
+        let client = Client::with_balances(Balance::from_raw(-5), Balance::from_raw(7), true);
+        assert_eq!(
+            client,
+            Client {
+                available: Balance(-5),
+                held: Balance(7),
+                locked: true,
+                closed: false,
+                chargebacks: 0,
+            }
+        );
+        assert!(client.total() == Balance(2));
+    }
+
+    /// ensure the builder rejects negative held funds, and an overflowing total only if asked to
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn builder() {
+        let client = Client::builder()
+            .available(Balance::from_raw(-5))
+            .held(Balance::from_raw(7))
+            .locked(true)
+            .chargebacks(1)
+            .build()
+            .unwrap();
+        assert_eq!(
+            client,
+            Client {
+                available: Balance(-5),
+                held: Balance(7),
+                locked: true,
+                closed: false,
+                chargebacks: 1,
+            }
+        );
+        assert!(matches!(
+            Client::builder().held(Balance::from_raw(-1)).build(),
+            Err(crate::Error::NegativeHeld)
+        ));
+
+        let overflowing = Client::builder()
+            .available(Balance(i128::MAX))
+            .held(Balance(1));
+        assert!(overflowing.clone().build().is_ok());
+        assert!(matches!(
+            overflowing.check_total(true).build(),
+            Err(crate::Error::Overflow)
+        ));
+    }
+
+    /// ensure a closed account rejects funds moving in or out, and only accounts without held funds close
+    #[test]
+    fn close() {
+        let mut client = Client::default();
+        client.available = Balance(5);
+        client.held = Balance(1);
+        assert!(matches!(client.close(), Err(crate::Error::OpenDisputes)));
+        assert!(!client.closed);
+        client.held = Balance(0);
+        assert!(client.close().is_ok());
+        assert!(client.is_closed());
+        assert!(matches!(client.close(), Err(crate::Error::AccountClosed)));
+        assert!(client.deposit(Amount(1)).is_err());
+        assert!(client.withdraw(Amount(1)).is_err());
+        assert!(client.hold(Amount(1)).is_err());
+        assert!(client.available.0 == 5);
+    }
 }
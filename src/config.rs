@@ -0,0 +1,135 @@
+use crate::{actions::ActionKind, Amount, ClientId, RoundingMode};
+use std::collections::BTreeSet;
+
+/// How the `total` column of a client snapshot is used, see
+/// [`Database::load_client_snapshot_with_config`](crate::database::Database::load_client_snapshot_with_config).
+///
+/// the total of a client is always the sum of its available and held funds, so a total that disagrees with them
+/// is either rejected, or one of the two is adjusted to the other.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SnapshotTotal {
+    /// a total within [`Config::snapshot_total_tolerance`] is corrected to the sum of the available and held funds,
+    /// a larger difference is an [`Error::SnapshotTotalMismatch`](crate::Error::SnapshotTotalMismatch)
+    #[default]
+    Verify,
+    /// the total is taken as is, and the available funds are derived from it as the total minus the held funds,
+    /// for external systems that round each column separately
+    Trust,
+    /// the total is ignored and recomputed from the available and held funds, however far off it is,
+    /// so a tampered total has no effect
+    Recompute,
+}
+
+/// What a chargeback does if the client holds less than the chargeback removes, see [`Config::chargeback_shortfall`].
+///
+/// the held funds of a client always cover its disputed deposits, so this only happens if the held funds are out of
+/// sync, e.g. because of a bug or a client loaded in that state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChargebackShortfall {
+    /// the chargeback fails with [`Error::InsufficientHeldFunds`](crate::Error::InsufficientHeldFunds),
+    /// but the account is locked anyway and the deposit stays disputed
+    #[default]
+    LockAndFail,
+    /// the chargeback removes the held funds that are left, locks the account, and succeeds.
+    /// the deposit is charged back completely, a partial chargeback leaves no remainder to be resolved later
+    Clamp,
+    /// the chargeback fails with [`Error::InsufficientHeldFunds`](crate::Error::InsufficientHeldFunds)
+    /// without any effect, leaving the account unlocked and the deposit disputed
+    Fail,
+}
+
+/// Configuration of the [`Database`](crate::database::Database).
+///
+/// The default configuration is strict: anything unexpected in the input results in an error.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// whether resolving a known deposit that is not disputed is a no-op instead of an error.
+    ///
+    /// some reconciliation flows send redundant resolves, which should not be reported as failures.
+    /// resolving an unknown transaction is always an error.
+    pub lenient_resolve: bool,
+    /// whether transaction ids are only unique per client instead of globally.
+    ///
+    /// if set, deposits and withdrawals of different clients may share a transaction id,
+    /// and disputes, resolves, and chargebacks only find deposits of the client they name.
+    pub per_client_transaction_ids: bool,
+    /// the kinds of actions the database accepts, all kinds are accepted if `None`.
+    ///
+    /// other actions are rejected with [`Error::ActionNotAllowed`](crate::Error::ActionNotAllowed),
+    /// e.g. to refuse disputes from an untrusted source.
+    pub allowed_actions: Option<BTreeSet<ActionKind>>,
+    /// a fee in basis points (1/100th of a percent) deducted from every deposit, no fee is charged if 0.
+    ///
+    /// the client is credited the deposit minus the fee, and a dispute of the deposit holds only the credited amount.
+    pub deposit_fee_bps: u32,
+    /// how the deposit fee is rounded to four decimal places.
+    pub fee_rounding: RoundingMode,
+    /// the account the deposit fees are credited to, if `None` the fees are not credited anywhere.
+    pub fee_account: Option<ClientId>,
+    /// clients with at least this many charged back deposits are reported by
+    /// [`Database::flagged_clients`](crate::database::Database::flagged_clients), no clients are flagged if `None`.
+    pub chargeback_flag_threshold: Option<u32>,
+    /// how the charged part of a partial chargeback, one with basis points, is rounded to four decimal places.
+    pub chargeback_rounding: RoundingMode,
+    /// whether deposits and withdrawals for a locked account are set aside in the quarantine, for an audit,
+    /// instead of being credited or rejected with [`Error::AccountLocked`](crate::Error::AccountLocked).
+    ///
+    /// a quarantined action fails with [`Error::Quarantined`](crate::Error::Quarantined) without any effect,
+    /// and is listed by [`Database::quarantined`](crate::database::Database::quarantined) with the reason of the lock.
+    pub quarantine_locked_accounts: bool,
+    /// deposits of the same client and amount at most this many actions apart are flagged as possible duplicates
+    /// by [`Database::possible_duplicates`](crate::database::Database::possible_duplicates), nothing is flagged if `None`.
+    ///
+    /// a double submission of a deposit under a new transaction id passes the transaction id check,
+    /// so this catches some of them. flagged deposits are credited as usual.
+    pub duplicate_deposit_window: Option<usize>,
+    /// what a chargeback does if the client holds less than it removes, see [`ChargebackShortfall`].
+    ///
+    /// [`Config::panic_on_invariant_violation`] still panics on the shortfall, unless it is clamped.
+    pub chargeback_shortfall: ChargebackShortfall,
+    /// whether the held funds a partial chargeback leaves are resolved back to the client,
+    /// instead of staying held until a later resolve or chargeback of the deposit.
+    pub resolve_partial_chargeback_remainder: bool,
+    /// the maximum number of clients, unlimited if `None`.
+    ///
+    /// deposits and withdrawals that would create a client beyond the limit are rejected with
+    /// [`Error::ClientLimitExceeded`](crate::Error::ClientLimitExceeded), existing clients are unaffected.
+    pub max_clients: Option<usize>,
+    /// the largest amount of a single deposit or withdrawal, unlimited if `None`.
+    ///
+    /// a business limit to catch fat-finger errors, larger amounts are rejected with
+    /// [`Error::AmountTooLarge`](crate::Error::AmountTooLarge) without using up their transaction id.
+    pub max_transaction_amount: Option<Amount>,
+    /// whether disputing a deposit of zero is ignored, leaving the deposit undisputed.
+    ///
+    /// by default such a dispute marks the deposit as disputed like any other, holding nothing,
+    /// so it can still be resolved or charged back (which locks the account).
+    pub ignore_zero_amount_disputes: bool,
+    /// whether disputing a deposit that is already disputed is an [`Error::AlreadyDisputed`](crate::Error::AlreadyDisputed)
+    /// instead of a no-op, to surface double disputes from a buggy feed.
+    pub strict_disputes: bool,
+    /// whether to panic in debug builds when a resolve or chargeback finds fewer held funds than the deposit,
+    /// instead of returning [`Error::InsufficientHeldFunds`](crate::Error::InsufficientHeldFunds).
+    ///
+    /// that can only happen because of a bug, so this makes it loud during development.
+    /// release builds always return the error.
+    pub panic_on_invariant_violation: bool,
+    /// whether a client with a negative total is an invariant violation reported by
+    /// [`Database::validate`](crate::database::Database::validate).
+    ///
+    /// a chargeback of a deposit that was already withdrawn leaves the client with a negative total,
+    /// which some products accept as arrears and others must never allow.
+    pub forbid_negative_total: bool,
+    /// how far the `total` of a client in a snapshot may be off from the sum of its available and held funds,
+    /// e.g. `Amount::from_raw(1)` for snapshots that rounded each column to four decimals separately.
+    ///
+    /// a total within the tolerance is corrected to the sum, a larger difference is an
+    /// [`Error::SnapshotTotalMismatch`](crate::Error::SnapshotTotalMismatch). zero, i.e. strict, by default.
+    /// see [`Database::load_client_snapshot_with_config`](crate::database::Database::load_client_snapshot_with_config).
+    pub snapshot_total_tolerance: Amount,
+    /// whether the `total` of a client in a snapshot is checked, trusted, or ignored, see [`SnapshotTotal`].
+    pub snapshot_total: SnapshotTotal,
+    /// whether to remember the order in which clients first appeared, for
+    /// [`Database::clients_in_insertion_order`](crate::database::Database::clients_in_insertion_order).
+    pub track_insertion_order: bool,
+}
@@ -1,21 +1,161 @@
 use crate::{
-    actions::{AccountAction, Chargeback, Deposit, Dispute, Resolve, Withdrawal},
+    actions::{
+        AccountAction, ActionKind, Adjustment, CancelDispute, Chargeback, Close, Deposit, Dispute,
+        ReasonCode, Refund, Resolve, Withdrawal,
+    },
     client::Client,
-    Amount, ClientId, Error, Result, TransactionId,
+    config::{ChargebackShortfall, Config},
+    store::{btree_memory_bytes, MemoryStore, TransactionStore},
+    Amount, Balance, ClientId, DisputeId, Error, Result, SignedAmount, TransactionId,
 };
-use serde::Serialize;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use std::io::{BufRead, BufReader, Read};
 use std::{
-    collections::{BTreeMap, BTreeSet},
+    collections::{BTreeMap, BTreeSet, VecDeque},
     ops::Deref,
 };
 
+/// Where a deposit is in the dispute lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepositState {
+    /// not disputed, either never or the dispute was resolved.
+    Normal,
+    /// disputed, the funds are held until the dispute is resolved or charged back.
+    Disputed,
+    /// charged back, the funds have been removed from the client and the deposit can not be disputed again.
+    ChargedBack,
+}
+
 /// A deposit that has been seen by the database.
 /// used to lookup transactions for disputes.
-#[derive(Debug)]
-pub(crate) struct SeenDeposit {
+#[derive(Debug, Clone)]
+pub struct SeenDeposit {
+    client_id: ClientId,
+    state: DepositState,
+    amount: Amount,
+    memo: Option<String>,
+    // the reason code of the current dispute or chargeback
+    reason: Option<ReasonCode>,
+    // whether the deposit has ever been disputed, a resolved deposit is back in the normal state
+    was_disputed: bool,
+    // the part of the amount that has been charged back, all of it once the deposit is charged back in full
+    charged_back: Amount,
+    // the number of disputes of the deposit that were cancelled instead of resolved
+    cancellations: u32,
+}
+
+impl SeenDeposit {
+    /// the client the funds were deposited to.
+    pub fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    /// the amount that was deposited.
+    pub fn amount(&self) -> Amount {
+        self.amount
+    }
+
+    /// where the deposit is in the dispute lifecycle.
+    pub fn state(&self) -> DepositState {
+        self.state
+    }
+
+    /// whether the deposit is currently disputed.
+    pub fn is_disputed(&self) -> bool {
+        self.state == DepositState::Disputed
+    }
+
+    /// whether the deposit is or has been disputed, including resolved and charged back deposits.
+    pub fn was_disputed(&self) -> bool {
+        self.was_disputed
+    }
+
+    /// the number of disputes of the deposit that were cancelled, e.g. withdrawn by the customer, instead of resolved.
+    pub fn cancellations(&self) -> u32 {
+        self.cancellations
+    }
+
+    /// the part of the amount that has been charged back, e.g. by a partial chargeback.
+    pub fn charged_back(&self) -> Amount {
+        self.charged_back
+    }
+
+    /// the part of the amount a dispute holds, which is what has not been charged back yet.
+    fn disputed_amount(&self) -> Amount {
+        self.amount
+            .checked_sub(self.charged_back)
+            .expect("never more than the amount is charged back")
+    }
+
+    /// the free-text memo that came with the deposit, if any.
+    pub fn memo(&self) -> Option<&str> {
+        self.memo.as_deref()
+    }
+
+    /// the reason code of the dispute or chargeback, if the deposit is disputed or charged back and a reason was given.
+    pub fn reason(&self) -> Option<&ReasonCode> {
+        self.reason.as_ref()
+    }
+}
+
+/// A successful withdrawal that has been seen by the database.
+/// used to lookup transactions for refunds.
+#[derive(Debug, Clone)]
+pub struct SeenWithdrawal {
     client_id: ClientId,
-    disputed: bool,
     amount: Amount,
+    memo: Option<String>,
+    refunded: bool,
+}
+
+impl SeenWithdrawal {
+    /// the client the funds were withdrawn from.
+    pub fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    /// the amount that was withdrawn.
+    pub fn amount(&self) -> Amount {
+        self.amount
+    }
+
+    /// the free-text memo that came with the withdrawal, if any.
+    pub fn memo(&self) -> Option<&str> {
+        self.memo.as_deref()
+    }
+
+    /// whether the withdrawal has been refunded.
+    pub fn is_refunded(&self) -> bool {
+        self.refunded
+    }
+}
+
+/// A manual adjustment that has been seen by the database.
+/// kept apart from the deposits and withdrawals, so adjustments can be audited and are never disputed or refunded.
+#[derive(Debug, Clone)]
+pub struct SeenAdjustment {
+    client_id: ClientId,
+    signed_amount: SignedAmount,
+    memo: Option<String>,
+}
+
+impl SeenAdjustment {
+    /// the client whose available funds were adjusted.
+    pub fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    /// the amount the available funds were adjusted by.
+    pub fn signed_amount(&self) -> SignedAmount {
+        self.signed_amount
+    }
+
+    /// the free-text memo that came with the adjustment, if any.
+    pub fn memo(&self) -> Option<&str> {
+        self.memo.as_deref()
+    }
 }
 
 /// A client with an ID.
@@ -40,6 +180,7 @@ impl Deref for ClientWithId<'_> {
     }
 }
 
+#[cfg(feature = "serde")]
 impl Serialize for ClientWithId<'_> {
     fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
     where
@@ -56,24 +197,301 @@ impl Serialize for ClientWithId<'_> {
     }
 }
 
+/// An owned copy of the balances of a client at one point in time, see [`Database::apply_and_get`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientSnapshot {
+    pub id: ClientId,
+    pub available: Balance,
+    pub held: Balance,
+    pub total: Balance,
+    pub locked: bool,
+}
+
+impl From<ClientWithId<'_>> for ClientSnapshot {
+    fn from(client: ClientWithId<'_>) -> Self {
+        Self {
+            id: client.id,
+            available: client.available(),
+            held: client.held(),
+            total: client.total(),
+            locked: client.is_locked(),
+        }
+    }
+}
+
+/// What a successfully applied action did to a client, see [`Database::apply_stream_with`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppliedEvent {
+    /// the index of the action in the stream, counting from 0
+    pub index: usize,
+    /// the kind of the applied action
+    pub kind: ActionKind,
+    /// the affected client before the action
+    pub before: ClientSnapshot,
+    /// the affected client after the action
+    pub after: ClientSnapshot,
+}
+
+impl AppliedEvent {
+    /// the client the action affected, like [`Database::apply_and_get`] reports it.
+    pub fn client(&self) -> ClientId {
+        self.after.id
+    }
+
+    /// how much the available funds changed.
+    pub fn available_delta(&self) -> Balance {
+        Balance(
+            self.after
+                .available
+                .0
+                .saturating_sub(self.before.available.0),
+        )
+    }
+
+    /// how much the held funds changed.
+    pub fn held_delta(&self) -> Balance {
+        Balance(self.after.held.0.saturating_sub(self.before.held.0))
+    }
+}
+
+/// A deposit as listed by [`Database::deposits`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepositRecord {
+    pub transaction_id: TransactionId,
+    pub client_id: ClientId,
+    /// the amount credited to the client, i.e. without the deposit fee
+    pub amount: Amount,
+    pub state: DepositState,
+}
+
+impl DepositRecord {
+    fn new((key, deposit): (&TransactionKey, &SeenDeposit)) -> Self {
+        Self {
+            transaction_id: key.1,
+            client_id: deposit.client_id,
+            amount: deposit.amount,
+            state: deposit.state,
+        }
+    }
+}
+
+/// The key transactions are tracked by.
+/// The client is only part of the key if transaction ids are scoped per client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TransactionKey(Option<ClientId>, TransactionId);
+
+impl TransactionKey {
+    /// the key of a transaction, e.g. to look it up in a [`TransactionStore`].
+    /// pass the client only if transaction ids are scoped per client, see [`Config::per_client_transaction_ids`],
+    /// otherwise the key won't match the key the database uses.
+    pub fn new(client_id: Option<ClientId>, transaction_id: TransactionId) -> Self {
+        Self(client_id, transaction_id)
+    }
+
+    /// the client of the transaction, only set if transaction ids are scoped per client.
+    pub fn client_id(&self) -> Option<ClientId> {
+        self.0
+    }
+
+    /// the id of the transaction.
+    pub fn transaction_id(&self) -> TransactionId {
+        self.1
+    }
+}
+
+/// The sum of the balances of all clients in the database.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LedgerTotals {
+    /// the sum of all available funds
+    pub available: Balance,
+    /// the sum of all held funds
+    pub held: Balance,
+    /// the sum of all funds, available and held
+    pub total: Balance,
+}
+
+/// A client whose balances or locked status differ between two databases, see [`Database::diff`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientDelta {
+    /// the client that differs
+    pub client: ClientId,
+    /// the client in the first database, or a default client if it only exists in the second
+    pub before: Client,
+    /// the client in the second database, or a default client if it only exists in the first
+    pub after: Client,
+}
+
+/// Why the account of a client is locked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockReason {
+    /// a chargeback of the deposit with this id
+    Chargeback(TransactionId),
+    /// [`Database::freeze`]
+    Frozen,
+    /// the account was locked in a loaded snapshot, which doesn't record why
+    Snapshot,
+}
+
+/// An action that was set aside because the account of its client is locked,
+/// see [`Config::quarantine_locked_accounts`].
+#[derive(Debug, Clone)]
+pub struct QuarantinedAction {
+    /// the action, which has not been performed
+    pub action: AccountAction,
+    /// why the account was locked when the action arrived
+    pub reason: LockReason,
+}
+
+/// A deposit with the same client and amount as a recent earlier deposit, which may be submitted twice by accident,
+/// see [`Config::duplicate_deposit_window`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PossibleDuplicate {
+    pub client_id: ClientId,
+    /// the transaction id of the flagged deposit
+    pub transaction_id: TransactionId,
+    /// the transaction id of the most recent earlier deposit with the same amount
+    pub earlier: TransactionId,
+    pub amount: Amount,
+}
+
+/// a dispute id, scoped to the client if transaction ids are scoped per client.
+type DisputeKey = (Option<ClientId>, DisputeId);
+
+/// the position, transaction id, and amount of the recent deposits of a client, see [`Config::duplicate_deposit_window`].
+type RecentDeposits = VecDeque<(usize, TransactionId, Amount)>;
+
+/// The clients, transactions, and dispute ids an action may read or change, see [`Database::available_after`]
+/// and [`Database::try_perform_action`].
+#[derive(Default)]
+struct Involved {
+    clients: Vec<ClientId>,
+    keys: Vec<TransactionKey>,
+    dispute_ids: Vec<DisputeKey>,
+}
+
+/// A client with its lock reason and recent deposits, each `None` if it did not exist, see [`Undo`].
+struct SavedClient {
+    id: ClientId,
+    client: Option<Client>,
+    lock: Option<LockReason>,
+    recent: Option<RecentDeposits>,
+}
+
+/// A transaction with whether its id was used, and its deposit, withdrawal, or adjustment if there was one,
+/// see [`Undo`].
+struct SavedTransaction {
+    key: TransactionKey,
+    seen: bool,
+    deposit: Option<SeenDeposit>,
+    withdrawal: Option<SeenWithdrawal>,
+    adjustment: Option<SeenAdjustment>,
+}
+
+/// The state an action may change, saved before performing it so it can be undone if it panics.
+struct Undo {
+    clients: Vec<SavedClient>,
+    transactions: Vec<SavedTransaction>,
+    disputes: Vec<(DisputeKey, Option<TransactionKey>)>,
+    // the lengths of the append-only lists, and the number of performed actions
+    quarantine: usize,
+    insertion_order: usize,
+    possible_duplicates: usize,
+    performed: usize,
+}
+
 /// The database of clients and transactions.
 /// Keeps track of all seen deposits, transaction ids, and the current state of all clients.
+///
+/// the transactions are kept in a [`TransactionStore`], in memory by default.
 #[derive(Debug, Default)]
-pub struct Database {
+pub struct Database<S = MemoryStore> {
     // stores all the known clients
     clients: BTreeMap<ClientId, Client>,
-    // tracks which transaction ids have been seen
-    // we can assume that transaction ids are unique
-    // but if they aren't for any reason, the code breaks in weird ways so we include a guard rail to be sure
-    //  if this check is implemented in production, we should use a more performant data structure
-    // either roaring or range-set-blaze look like good choices here
-    // https://github.com/CarlKCarlK/range-set-blaze/blob/main/docs/bench.md
-    seen_transactions: BTreeSet<TransactionId>,
-    // TransactionId is said to be globally unique, but disputes/resolves/chargebacks actions include a client id in the CSV.
-    //  it is unclear what the correct behaviour should be if these disagree with the client id in the deposit/withdrawal.
-    // I have opted for ignoring the client id in the dispute/resolve/chargback action, and only using the transaction id.
-    //  in the real world, this would be an important detail to clarify with the product owner / docs / upstream team / partner.
-    deposit_transactions: BTreeMap<TransactionId, SeenDeposit>,
+    // tracks the used transaction ids and the deposits
+    store: S,
+    // the ids of all locked clients with the reason of the lock,
+    // kept in sync with `Client::locked` so they can be counted without a scan.
+    locked_clients: BTreeMap<ClientId, LockReason>,
+    // the actions set aside because their account is locked, only kept if `Config::quarantine_locked_accounts` is set.
+    quarantine: Vec<QuarantinedAction>,
+    // the number of actions performed so far, the position of a deposit in `recent_deposits`.
+    performed: usize,
+    // the position, transaction id, and amount of the deposits of each client within `Config::duplicate_deposit_window`.
+    recent_deposits: BTreeMap<ClientId, RecentDeposits>,
+    // the deposits flagged as possible duplicates, in order.
+    possible_duplicates: Vec<PossibleDuplicate>,
+    // the ids of all clients in the order they were created, only kept if `Config::track_insertion_order` is set.
+    insertion_order: Vec<ClientId>,
+    // the deposit each dispute id refers to, the client is only part of the key if transaction ids are scoped per client.
+    disputes: BTreeMap<DisputeKey, TransactionKey>,
+    // how to treat edge cases in the input
+    config: Config,
+}
+
+/// panic on [`Error::InsufficientHeldFunds`] if configured and in a debug build, see [`Config::panic_on_invariant_violation`].
+fn check_held_invariant(config: &Config, result: Result<()>) -> Result<()> {
+    if matches!(result, Err(Error::InsufficientHeldFunds))
+        && config.panic_on_invariant_violation
+        && cfg!(debug_assertions)
+    {
+        panic!("held funds are less than the disputed deposit, the held balance is out of sync");
+    }
+    result
+}
+
+/// the part of the held funds of a disputed deposit a chargeback with the given basis points charges back,
+/// and the part it leaves, see [`Config::chargeback_rounding`].
+fn chargeback_split(
+    config: &Config,
+    deposit: &SeenDeposit,
+    basis_points: Option<u32>,
+) -> (Amount, Amount) {
+    let held = deposit.disputed_amount();
+    let charged = basis_points.map_or(held, |bps| {
+        held.basis_points(bps, config.chargeback_rounding)
+    });
+    let remainder = held
+        .checked_sub(charged)
+        .expect("never more than the held funds are charged back");
+    (charged, remainder)
+}
+
+/// the 64-bit FNV-1a hash, used by [`Database::state_hash`] because the hashers of the standard library
+/// are not guaranteed to give the same result between versions.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+}
+
+/// emit a debug event with the kind, ids, amount, and outcome of a performed action.
+#[cfg(feature = "tracing")]
+fn trace_action(action: &AccountAction, result: &Result<()>) {
+    let amount = match action {
+        AccountAction::Deposit(deposit) => Some(deposit.amount),
+        AccountAction::Withdrawal(withdrawal) => Some(withdrawal.amount),
+        _ => None,
+    };
+    tracing::debug!(
+        kind = ?action.kind(),
+        client = action.client_id().map(|client| client.0),
+        tx = action.transaction_id().map(|tx| tx.0),
+        amount = amount.map(tracing::field::debug),
+        ok = result.is_ok(),
+        error = result.as_ref().err().map(tracing::field::display),
+        "performed action"
+    );
 }
 
 impl Database {
@@ -82,121 +500,931 @@ impl Database {
         Self::default()
     }
 
+    /// create an empty database with the given configuration.
+    pub fn with_config(config: Config) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// create an empty database, reserving room for roughly the given number of clients and transactions.
+    ///
+    /// the capacity is only a hint, used by stores that override [`TransactionStore::reserve`].
+    /// the clients and the [`MemoryStore`] are kept in ordered maps, which can't reserve, and the default
+    /// configuration does not track the insertion order, so this is the same as [`Database::new`].
+    /// see [`Database::reserve`] to reserve in another store.
+    pub fn with_capacity(clients: usize, transactions: usize) -> Self {
+        let mut db = Self::new();
+        db.reserve(clients, transactions);
+        db
+    }
+
+    /// load the clients from a snapshot in the output format of the binary, i.e. a CSV file with the columns
+    /// `client,available,held,total,locked`, to continue processing where a previous run left off.
+    ///
+    /// the snapshot only contains the balances of the clients, so deposits from before the snapshot can not be disputed
+    /// and their transaction ids are not checked for uniqueness.
+    /// fails if a row can not be parsed, a client appears twice, or its total is not the sum of its available and held funds.
+    #[cfg(feature = "serde")]
+    pub fn load_client_snapshot<R: Read>(r: R) -> Result<Database> {
+        Self::load_client_snapshot_with_config(r, Config::default())
+    }
+
+    /// load the clients from a snapshot like [`Database::load_client_snapshot`], into a database with the given configuration.
+    ///
+    /// a total that is off by at most [`Config::snapshot_total_tolerance`] is corrected to the sum of the available and held funds.
+    /// [`Config::snapshot_total`] can instead trust the total, or ignore it.
+    #[cfg(feature = "serde")]
+    pub fn load_client_snapshot_with_config<R: Read>(r: R, config: Config) -> Result<Database> {
+        use crate::config::SnapshotTotal;
+
+        #[derive(Deserialize)]
+        struct SnapshotRecord {
+            client: crate::ClientIdRepr,
+            available: Balance,
+            held: Balance,
+            total: Balance,
+            locked: bool,
+        }
+
+        let tolerance = config.snapshot_total_tolerance;
+        let snapshot_total = config.snapshot_total;
+        let mut db = Database::with_config(config);
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .trim(csv::Trim::All)
+            .from_reader(r);
+        for record in reader.deserialize::<SnapshotRecord>() {
+            let record = record.map_err(|e| Error::Parse(e.to_string()))?;
+            let id = ClientId(record.client);
+            let mut client = Client {
+                available: record.available,
+                held: record.held,
+                locked: record.locked,
+                ..Client::default()
+            };
+            // the total of a client is always derived, so a total within the tolerance is corrected by not using it
+            let difference = client
+                .checked_total()
+                .and_then(|total| total.0.checked_sub(record.total.0));
+            match snapshot_total {
+                SnapshotTotal::Verify
+                    if difference.is_none_or(|difference| {
+                        difference.unsigned_abs() > tolerance.0 as u128
+                    }) =>
+                {
+                    return Err(Error::SnapshotTotalMismatch(id));
+                }
+                SnapshotTotal::Verify => {}
+                SnapshotTotal::Trust => {
+                    client.available = Balance(
+                        record
+                            .total
+                            .0
+                            .checked_sub(record.held.0)
+                            .ok_or(Error::SnapshotTotalMismatch(id))?,
+                    );
+                }
+                SnapshotTotal::Recompute => {
+                    client
+                        .checked_total()
+                        .ok_or(Error::SnapshotTotalMismatch(id))?;
+                }
+            }
+            if db.clients.contains_key(&id) {
+                return Err(Error::Parse(format!(
+                    "client {id:?} appears more than once"
+                )));
+            }
+            if client.locked {
+                db.locked_clients.insert(id, LockReason::Snapshot);
+            }
+            // through `client_mut`, so the client is also tracked in the insertion order
+            *db.client_mut(id) = client;
+        }
+        Ok(db)
+    }
+}
+
+impl<S: TransactionStore> Database<S> {
+    /// create an empty database with the given configuration, keeping its transactions in `store`.
+    pub fn with_store(config: Config, store: S) -> Self {
+        Self {
+            clients: BTreeMap::new(),
+            store,
+            locked_clients: BTreeMap::new(),
+            quarantine: Vec::new(),
+            performed: 0,
+            recent_deposits: BTreeMap::new(),
+            possible_duplicates: Vec::new(),
+            insertion_order: Vec::new(),
+            disputes: BTreeMap::new(),
+            config,
+        }
+    }
+
+    /// the configuration of the database.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// the store the transactions are kept in.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// the store the transactions are kept in.
+    ///
+    /// changing the store directly can break the invariants of the database, e.g. removing a disputed deposit keeps its funds held forever.
+    pub fn store_mut(&mut self) -> &mut S {
+        &mut self.store
+    }
+
+    /// reserve room for at least the given number of additional clients and transactions, to avoid reallocating while
+    /// processing an input of known size.
+    ///
+    /// the clients are kept in an ordered map, which can't reserve, so only their insertion order is reserved,
+    /// if [`Config::track_insertion_order`] is set. the transactions are reserved in the store
+    /// with [`TransactionStore::reserve`], which only has an effect if the store supports it.
+    pub fn reserve(&mut self, clients: usize, transactions: usize) {
+        if self.config.track_insertion_order {
+            self.insertion_order.reserve(clients);
+        }
+        self.store.reserve(transactions);
+    }
+
+    /// a rough estimate of the heap memory the database uses, in bytes, e.g. to size a machine for an input.
+    ///
+    /// based on the number of clients, transactions, and disputes, and the size of their entries,
+    /// see [`TransactionStore::estimated_memory_bytes`] for the transactions. allocator overhead is not included.
+    pub fn estimated_memory_bytes(&self) -> usize {
+        btree_memory_bytes::<ClientId, Client>(self.clients.len())
+            + btree_memory_bytes::<ClientId, LockReason>(self.locked_clients.len())
+            + self.quarantine.capacity() * std::mem::size_of::<QuarantinedAction>()
+            + self
+                .quarantine
+                .iter()
+                .map(|quarantined| quarantined.action.heap_bytes())
+                .sum::<usize>()
+            + self.possible_duplicates.capacity() * std::mem::size_of::<PossibleDuplicate>()
+            + self.insertion_order.capacity() * std::mem::size_of::<ClientId>()
+            + btree_memory_bytes::<ClientId, RecentDeposits>(self.recent_deposits.len())
+            + self
+                .recent_deposits
+                .values()
+                .map(|recent| {
+                    recent.capacity() * std::mem::size_of::<(usize, TransactionId, Amount)>()
+                })
+                .sum::<usize>()
+            + btree_memory_bytes::<(Option<ClientId>, DisputeId), TransactionKey>(
+                self.disputes.len(),
+            )
+            + self.store.estimated_memory_bytes()
+    }
+
     /// returns an iterator over all clients in the database and their associated id.
     /// this is used for serializing the clients.
-    pub fn clients(&self) -> impl Iterator<Item = ClientWithId> {
+    pub fn clients(&self) -> impl Iterator<Item = ClientWithId<'_>> {
         self.clients
             .iter()
             .map(|(&id, client)| ClientWithId { id, client })
     }
 
+    /// returns an iterator over all clients in the order they first appeared in the actions, instead of ordered by id.
+    ///
+    /// the order is only tracked if [`Config::track_insertion_order`] is set, otherwise the iterator is empty.
+    pub fn clients_in_insertion_order(&self) -> impl Iterator<Item = ClientWithId<'_>> {
+        self.insertion_order.iter().map(|id| ClientWithId {
+            id: *id,
+            client: &self.clients[id],
+        })
+    }
+
+    /// returns an iterator over the clients with at least [`Config::chargeback_flag_threshold`] charged back deposits.
+    /// these are suspicious of fraud, flagging them has no effect on their balances.
+    pub fn flagged_clients(&self) -> impl Iterator<Item = ClientWithId<'_>> {
+        let threshold = self.config.chargeback_flag_threshold;
+        self.clients().filter(move |client| {
+            threshold.is_some_and(|threshold| client.chargebacks() >= threshold)
+        })
+    }
+
+    /// the deposits with the same client and amount as a deposit at most [`Config::duplicate_deposit_window`]
+    /// actions before them, in the order they arrived. always empty unless the window is set.
+    pub fn possible_duplicates(&self) -> &[PossibleDuplicate] {
+        &self.possible_duplicates
+    }
+
+    /// returns an iterator over the clients whose available funds are negative, i.e. clients in arrears
+    /// because a deposit was disputed after (some of) its funds were withdrawn.
+    pub fn negative_available_clients(&self) -> impl Iterator<Item = ClientWithId<'_>> {
+        self.clients()
+            .filter(|client| client.available().is_negative())
+    }
+
+    /// returns an iterator over the clients whose total is negative, e.g. because a deposit was charged back
+    /// after its funds were withdrawn.
+    pub fn negative_total_clients(&self) -> impl Iterator<Item = ClientWithId<'_>> {
+        self.clients()
+            .filter(|client| client.saturating_total().is_negative())
+    }
+
+    /// check the invariants the configuration asks for, failing with the first violation.
+    ///
+    /// for now that is only [`Config::forbid_negative_total`], which fails with [`Error::NegativeTotal`]
+    /// for the client with the lowest id.
+    pub fn validate(&self) -> Result<()> {
+        if self.config.forbid_negative_total {
+            if let Some(client) = self.negative_total_clients().next() {
+                return Err(Error::NegativeTotal(client.id()));
+            }
+        }
+        Ok(())
+    }
+
+    /// returns an iterator over every transaction id the database has seen, in ascending order,
+    /// e.g. to find out why a deposit or withdrawal was rejected as a duplicate.
+    ///
+    /// a deposit, withdrawal, or adjustment uses its id before its amount is applied, so the ids of those that failed
+    /// for any other reason than a duplicate id are listed as well. if transaction ids are scoped per client,
+    /// they are ordered by client first and the same id can be listed once for every client.
+    pub fn seen_transaction_ids(&self) -> impl Iterator<Item = TransactionId> + '_ {
+        self.store.seen().map(TransactionKey::transaction_id)
+    }
+
+    /// returns an iterator over every deposit seen by the database, in any state, ordered by transaction id
+    /// (and by client first if transaction ids are scoped per client).
+    ///
+    /// charged back deposits are kept as tombstones and are listed with [`DepositState::ChargedBack`].
+    /// deposits from before a [`Database::load_client_snapshot`] are not known and not listed.
+    pub fn deposits(&self) -> impl Iterator<Item = DepositRecord> + '_ {
+        self.store.deposits().map(DepositRecord::new)
+    }
+
+    /// returns an iterator over the deposits that are or have been disputed, ordered like [`Database::deposits`].
+    ///
+    /// a deposit whose dispute was resolved is listed with [`DepositState::Normal`].
+    pub fn disputed_deposits(&self) -> impl Iterator<Item = DepositRecord> + '_ {
+        self.store
+            .deposits()
+            .filter(|(_, deposit)| deposit.was_disputed)
+            .map(DepositRecord::new)
+    }
+
+    /// the number of disputed and charged back deposits per reason code, deposits without a reason code are not counted.
+    pub fn dispute_reasons(&self) -> BTreeMap<&ReasonCode, usize> {
+        let mut counts = BTreeMap::new();
+        for (_, deposit) in self.store.deposits() {
+            if let Some(reason) = &deposit.reason {
+                *counts.entry(reason).or_default() += 1;
+            }
+        }
+        counts
+    }
+
+    /// the number of disputes that were cancelled instead of resolved or charged back, over all deposits.
+    pub fn cancelled_disputes(&self) -> usize {
+        self.store
+            .deposits()
+            .map(|(_, deposit)| deposit.cancellations as usize)
+            .sum()
+    }
+
+    /// the number of locked clients, without iterating over all clients.
+    pub fn locked_count(&self) -> usize {
+        debug_assert_eq!(
+            self.locked_clients.len(),
+            self.clients.values().filter(|client| client.locked).count(),
+            "locked client index out of sync"
+        );
+        self.locked_clients.len()
+    }
+
+    /// lock the account of a client, e.g. on request of a fraud team.
+    /// a locked account can no longer make any withdrawals.
+    ///
+    /// returns `false` if the client does not exist.
+    pub fn freeze(&mut self, client_id: ClientId) -> bool {
+        let Some(client) = self.clients.get_mut(&client_id) else {
+            return false;
+        };
+        client.locked = true;
+        // an account that was already locked keeps its original reason
+        self.locked_clients
+            .entry(client_id)
+            .or_insert(LockReason::Frozen);
+        true
+    }
+
+    /// why the account of a client is locked, `None` if it is not locked or does not exist.
+    pub fn lock_reason(&self, client_id: ClientId) -> Option<LockReason> {
+        self.locked_clients.get(&client_id).copied()
+    }
+
+    /// the actions that were set aside because the account of their client was locked, in the order they arrived.
+    /// always empty unless [`Config::quarantine_locked_accounts`] is set.
+    pub fn quarantined(&self) -> &[QuarantinedAction] {
+        &self.quarantine
+    }
+
+    /// unlock the account of a client, whether it was locked by [`Database::freeze`] or by a chargeback.
+    ///
+    /// returns `false` if the client does not exist.
+    pub fn unfreeze(&mut self, client_id: ClientId) -> bool {
+        let Some(client) = self.clients.get_mut(&client_id) else {
+            return false;
+        };
+        client.locked = false;
+        self.locked_clients.remove(&client_id);
+        true
+    }
+
+    /// zero the available funds of every unlocked client whose available funds are positive but below `threshold`.
+    ///
+    /// returns the swept clients and the amounts taken from them, ordered by id.
+    /// the swept funds are not credited anywhere, the caller is expected to book them to a house account.
+    pub fn sweep_dust(&mut self, threshold: Amount) -> Vec<(ClientId, Balance)> {
+        self.clients
+            .iter_mut()
+            .filter(|(_, client)| {
+                !client.locked && client.available.0 > 0 && client.available.0 < threshold.0 as i128
+            })
+            .map(|(&id, client)| (id, std::mem::take(&mut client.available)))
+            .collect()
+    }
+
+    /// list the clients whose available funds, held funds, or locked status differ from `other`, ordered by id.
+    ///
+    /// a client that only exists in one of the databases is compared against a default (empty, unlocked) client.
+    /// used to check that reprocessing a file matches an earlier result.
+    pub fn diff<T>(&self, other: &Database<T>) -> Vec<ClientDelta> {
+        let ids: BTreeSet<ClientId> = self
+            .clients
+            .keys()
+            .chain(other.clients.keys())
+            .copied()
+            .collect();
+        ids.into_iter()
+            .filter_map(|client| {
+                let before = self.clients.get(&client).cloned().unwrap_or_default();
+                let after = other.clients.get(&client).cloned().unwrap_or_default();
+                let differs = before.available != after.available
+                    || before.held != after.held
+                    || before.locked != after.locked;
+                differs.then_some(ClientDelta {
+                    client,
+                    before,
+                    after,
+                })
+            })
+            .collect()
+    }
+
+    /// returns the sum of the balances of all clients.
+    ///
+    /// unlike [`Client::total`] this never panics, it returns [`Error::Overflow`] if any of the sums overflow.
+    pub fn totals(&self) -> Result<LedgerTotals> {
+        let add = |a: Balance, b: Balance| a.0.checked_add(b.0).map(Balance).ok_or(Error::Overflow);
+        self.clients
+            .values()
+            .try_fold(LedgerTotals::default(), |totals, client| {
+                Ok(LedgerTotals {
+                    available: add(totals.available, client.available())?,
+                    held: add(totals.held, client.held())?,
+                    total: add(totals.total, client.checked_total().ok_or(Error::Overflow)?)?,
+                })
+            })
+    }
+
+    /// a checksum of the balances and locked flags of all clients and of all tracked deposits, to check that two runs
+    /// produced identical results with a single value.
+    ///
+    /// the hash is a 64-bit FNV-1a over the state in sorted order, so it is the same on every platform and between versions of rust.
+    /// it only depends on the resulting state, applying the same actions in a different order that leads to the same state gives the same hash.
+    /// it is not a cryptographic hash.
+    pub fn state_hash(&self) -> u64 {
+        let mut hash = Fnv1a::default();
+        hash.write(&(self.clients.len() as u64).to_le_bytes());
+        for (id, client) in &self.clients {
+            hash.write(&id.0.to_le_bytes());
+            hash.write(&client.available.0.to_le_bytes());
+            hash.write(&client.held.0.to_le_bytes());
+            hash.write(&[client.locked as u8]);
+        }
+        for (key, deposit) in self.store.deposits() {
+            match key.0 {
+                Some(client) => {
+                    hash.write(&[1]);
+                    hash.write(&client.0.to_le_bytes());
+                }
+                None => hash.write(&[0]),
+            }
+            hash.write(&key.1 .0.to_le_bytes());
+            hash.write(&deposit.client_id.0.to_le_bytes());
+            hash.write(&deposit.amount.0.to_le_bytes());
+            hash.write(&deposit.charged_back.0.to_le_bytes());
+            hash.write(&deposit.cancellations.to_le_bytes());
+            hash.write(&[deposit.state as u8]);
+        }
+        hash.0
+    }
+
+    /// look up a successful withdrawal by its transaction id, to check whether it has been refunded.
+    /// returns `None` if the withdrawal is unknown or failed.
+    pub fn withdrawal_info(&self, client: ClientId, id: TransactionId) -> Option<&SeenWithdrawal> {
+        self.store
+            .get_withdrawal(&self.key(client, id))
+            .filter(|withdrawal| withdrawal.client_id == client)
+    }
+
+    /// look up a manual adjustment by its transaction id.
+    /// returns `None` if the adjustment is unknown or failed, or the store doesn't keep adjustments.
+    pub fn adjustment_info(&self, client: ClientId, id: TransactionId) -> Option<&SeenAdjustment> {
+        self.store
+            .get_adjustment(&self.key(client, id))
+            .filter(|adjustment| adjustment.client_id == client)
+    }
+
+    /// look up a deposit by its transaction id.
+    /// returns `None` if the deposit is unknown, charged back deposits are kept.
+    ///
+    /// if transaction ids are scoped per client, use [`Database::client_deposit_info`] instead.
+    pub fn deposit_info(&self, id: TransactionId) -> Option<&SeenDeposit> {
+        self.store.get_deposit(&TransactionKey(None, id))
+    }
+
+    /// look up a deposit of a specific client by its transaction id.
+    /// returns `None` if the client has no such deposit.
+    pub fn client_deposit_info(&self, client: ClientId, id: TransactionId) -> Option<&SeenDeposit> {
+        self.store
+            .get_deposit(&self.key(client, id))
+            .filter(|deposit| deposit.client_id == client)
+    }
+
+    /// where the deposit with the given transaction id is in the dispute lifecycle, `None` if the deposit is unknown.
+    ///
+    /// if transaction ids are scoped per client, use [`Database::client_deposit_info`] instead.
+    pub fn deposit_state(&self, id: TransactionId) -> Option<DepositState> {
+        self.deposit_info(id).map(SeenDeposit::state)
+    }
+
+    /// the key a transaction of the given client is tracked by.
+    fn key(&self, client: ClientId, id: TransactionId) -> TransactionKey {
+        TransactionKey(self.config.per_client_transaction_ids.then_some(client), id)
+    }
+
+    /// the deposit a dispute with the given id refers to, if the id is known.
+    fn dispute(&self, client: ClientId, id: DisputeId) -> Option<TransactionKey> {
+        let scope = self.config.per_client_transaction_ids.then_some(client);
+        self.disputes.get(&(scope, id)).copied()
+    }
+
+    /// fails if the dispute id already refers to a deposit other than `key`.
+    fn check_dispute_id(&self, dispute: &Dispute, key: TransactionKey) -> Result<()> {
+        match dispute.dispute_id {
+            Some(id)
+                if self
+                    .dispute(dispute.client_id, id)
+                    .is_some_and(|known| known != key) =>
+            {
+                Err(Error::DisputeIdInUse(id))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// the key of the deposit a resolve or chargeback refers to, by its transaction id, its dispute id, or both.
+    fn disputed_key(
+        &self,
+        client_id: ClientId,
+        disputed_transaction: Option<TransactionId>,
+        dispute_id: Option<DisputeId>,
+    ) -> Result<TransactionKey> {
+        let Some(id) = dispute_id else {
+            let transaction = disputed_transaction.ok_or(Error::TransactionNotFound)?;
+            return Ok(self.key(client_id, transaction));
+        };
+        let key = self.dispute(client_id, id).ok_or(Error::DisputeNotFound)?;
+        match disputed_transaction {
+            Some(transaction) if transaction != key.1 => Err(Error::DisputeIdMismatch {
+                dispute: id,
+                transaction,
+            }),
+            _ => Ok(key),
+        }
+    }
+
+    /// fails if creating the client would exceed [`Config::max_clients`].
+    fn check_client_limit(&self, id: ClientId) -> Result<()> {
+        match self.config.max_clients {
+            Some(max) if !self.clients.contains_key(&id) && self.clients.len() >= max => {
+                Err(Error::ClientLimitExceeded)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// fails if `amount` exceeds [`Config::max_transaction_amount`].
+    fn check_amount_limit(&self, amount: Amount) -> Result<()> {
+        match self.config.max_transaction_amount {
+            Some(max) if amount.0 > max.0 => Err(Error::AmountTooLarge),
+            _ => Ok(()),
+        }
+    }
+
     /// get a muteable reference to a client by id.
-    /// creates the client if it doesn't exist.
+    /// creates the client if it doesn't exist, regardless of [`Config::max_clients`].
     pub fn client_mut(&mut self, id: ClientId) -> &mut Client {
+        if self.config.track_insertion_order && !self.clients.contains_key(&id) {
+            self.insertion_order.push(id);
+        }
         self.clients.entry(id).or_default()
     }
 
-    fn handle_deposit(&mut self, deposit: Deposit) -> Result<()> {
-        let Deposit {
+    fn handle_deposit(&mut self, deposit: &Deposit) -> Result<()> {
+        let &Deposit {
             client_id,
             transaction_id,
             amount,
+            ref memo,
         } = deposit;
-        if !self.seen_transactions.insert(transaction_id) {
+        self.check_amount_limit(amount)?;
+        self.check_client_limit(client_id)?;
+        if let Some(house) = self.config.fee_account {
+            self.check_client_limit(house)?;
+        }
+        let key = self.key(client_id, transaction_id);
+        if !self.store.mark_seen(key) {
             return Err(Error::InvalidTransactionId);
         }
-        self.client_mut(client_id).deposit(amount)?;
-        self.deposit_transactions.insert(
-            transaction_id,
+        let fee = amount.basis_points(self.config.deposit_fee_bps, self.config.fee_rounding);
+        let (credited, fee_account) = match self.config.fee_account {
+            // if the fee goes to the depositing client itself, it nets out
+            Some(house) if house == client_id => (amount, None),
+            fee_account => (
+                amount
+                    .checked_sub(fee)
+                    .expect("the fee is never more than the amount"),
+                fee_account,
+            ),
+        };
+        if let Some(house) = fee_account {
+            // check the fee can be credited before crediting the client, so either both or neither are updated
+            if let Some(house_client) = self.clients.get(&house) {
+                house_client
+                    .available_after_deposit(fee)
+                    .map_err(|e| e.for_client(house))?;
+            }
+        }
+        self.client_mut(client_id)
+            .deposit(credited)
+            .map_err(|e| e.for_client(client_id))?;
+        if let Some(house) = fee_account {
+            self.client_mut(house)
+                .deposit(fee)
+                .map_err(|e| e.for_client(house))?;
+        }
+        self.store.record_deposit(
+            key,
             SeenDeposit {
-                disputed: false,
+                state: DepositState::Normal,
                 client_id,
-                amount,
+                amount: credited,
+                memo: memo.clone(),
+                reason: None,
+                was_disputed: false,
+                charged_back: Amount::zero(),
+                cancellations: 0,
             },
         );
+        self.check_possible_duplicate(client_id, transaction_id, amount);
         Ok(())
     }
 
-    fn handle_withdrawal(&mut self, withdrawal: Withdrawal) -> Result<()> {
-        let Withdrawal {
+    /// flag a deposit with the same amount as a recent deposit of the client, see [`Config::duplicate_deposit_window`].
+    fn check_possible_duplicate(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: Amount,
+    ) {
+        let Some(window) = self.config.duplicate_deposit_window else {
+            return;
+        };
+        // the deposit is the last performed action
+        let position = self.performed - 1;
+        let recent = self.recent_deposits.entry(client_id).or_default();
+        while recent
+            .front()
+            .is_some_and(|&(earlier, _, _)| position - earlier > window)
+        {
+            recent.pop_front();
+        }
+        if let Some(&(_, earlier, _)) = recent.iter().rev().find(|(_, _, a)| *a == amount) {
+            self.possible_duplicates.push(PossibleDuplicate {
+                client_id,
+                transaction_id,
+                earlier,
+                amount,
+            });
+        }
+        recent.push_back((position, transaction_id, amount));
+    }
+
+    fn handle_withdrawal(&mut self, withdrawal: &Withdrawal) -> Result<()> {
+        let &Withdrawal {
             client_id,
             transaction_id,
             amount,
+            ref memo,
         } = withdrawal;
-        if !self.seen_transactions.insert(transaction_id) {
+        self.check_amount_limit(amount)?;
+        self.check_client_limit(client_id)?;
+        let key = self.key(client_id, transaction_id);
+        if !self.store.mark_seen(key) {
             return Err(Error::InvalidTransactionId);
         }
-        self.client_mut(client_id).withdraw(amount)?;
+        self.client_mut(client_id)
+            .withdraw(amount)
+            .map_err(|e| e.for_client(client_id))?;
+        // only withdrawals that took funds can be refunded
+        self.store.record_withdrawal(
+            key,
+            SeenWithdrawal {
+                client_id,
+                amount,
+                memo: memo.clone(),
+                refunded: false,
+            },
+        );
         Ok(())
     }
 
-    fn handle_dispute(&mut self, dispute: Dispute) -> Result<()> {
-        let Dispute {
-            disputed_transaction,
-        } = dispute;
-        let deposit = self
-            .deposit_transactions
-            .get_mut(&disputed_transaction)
+    fn handle_adjustment(&mut self, adjustment: &Adjustment) -> Result<()> {
+        let &Adjustment {
+            client_id,
+            transaction_id,
+            signed_amount,
+            ref memo,
+        } = adjustment;
+        self.check_client_limit(client_id)?;
+        let key = self.key(client_id, transaction_id);
+        if !self.store.mark_seen(key) {
+            return Err(Error::InvalidTransactionId);
+        }
+        self.client_mut(client_id)
+            .adjust(signed_amount)
+            .map_err(|e| e.for_client(client_id))?;
+        self.store.record_adjustment(
+            key,
+            SeenAdjustment {
+                client_id,
+                signed_amount,
+                memo: memo.clone(),
+            },
+        );
+        Ok(())
+    }
+
+    fn handle_refund(&mut self, refund: &Refund) -> Result<()> {
+        let &Refund {
+            client_id,
+            refunded_transaction,
+        } = refund;
+        let key = self.key(client_id, refunded_transaction);
+        let withdrawal = self
+            .store
+            .get_withdrawal_mut(&key)
             .ok_or(Error::TransactionNotFound)?;
-        if deposit.disputed {
+        if withdrawal.refunded {
+            return Err(Error::AlreadyRefunded(refunded_transaction));
+        }
+        // like a deposit, a refund is credited even if the account is locked
+        self.clients
+            .entry(withdrawal.client_id)
+            .or_default()
+            .deposit(withdrawal.amount)
+            .map_err(|e| e.for_client(withdrawal.client_id))?;
+        withdrawal.refunded = true;
+        Ok(())
+    }
+
+    fn handle_dispute(&mut self, dispute: &Dispute) -> Result<()> {
+        let key = self.key(dispute.client_id, dispute.disputed_transaction);
+        self.check_dispute_id(dispute, key)?;
+        self.dispute_deposit(key, dispute)?;
+        // the dispute id is kept after a resolve or chargeback, so it keeps referring to the same deposit
+        if let Some(id) = dispute.dispute_id {
+            let scope = self
+                .config
+                .per_client_transaction_ids
+                .then_some(dispute.client_id);
+            self.disputes.insert((scope, id), key);
+        }
+        Ok(())
+    }
+
+    /// the error for a dispute of a transaction that is not a known deposit.
+    /// a known withdrawal gets its own error, as it is more likely a mistake of the sender than a missing deposit.
+    fn undisputable(&self, key: TransactionKey, id: TransactionId) -> Error {
+        if self.store.get_withdrawal(&key).is_some() {
+            Error::CannotDisputeWithdrawal(id)
+        } else {
+            Error::TransactionNotFound
+        }
+    }
+
+    fn dispute_deposit(&mut self, key: TransactionKey, dispute: &Dispute) -> Result<()> {
+        let &Dispute {
+            disputed_transaction,
+            ref reason,
+            ..
+        } = dispute;
+        let Some(deposit) = self.store.get_deposit_mut(&key) else {
+            return Err(self.undisputable(key, disputed_transaction));
+        };
+        match deposit.state {
+            DepositState::Normal => {}
+            DepositState::Disputed if self.config.strict_disputes => {
+                return Err(Error::AlreadyDisputed(disputed_transaction))
+            }
             // already disputed, nothing to do
+            DepositState::Disputed => return Ok(()),
+            DepositState::ChargedBack => return Err(Error::TransactionChargedBack),
+        }
+        let amount = deposit.disputed_amount();
+        if amount == Amount::zero() && self.config.ignore_zero_amount_disputes {
+            // holding nothing would only make the deposit eligible for a chargeback
             return Ok(());
         }
-        let amount = deposit.amount;
         // we can't use the client function here because of the borrow checker.
         // since Self::client(&mut self) borrows _all_ of self muteable it conflicts with
-        // the borrow of deposit_transactions.
-        // using this one line works because it only borrows self.client, which doesn't conflict with the borrow of deposit_transactions.
+        // the borrow of the store.
+        // using this one line works because it only borrows self.client, which doesn't conflict with the borrow of the store.
         self.clients
             .entry(deposit.client_id)
             .or_default()
-            .hold(amount)?;
-        deposit.disputed = true;
+            .hold(amount)
+            .map_err(|e| e.for_client(deposit.client_id))?;
+        deposit.state = DepositState::Disputed;
+        deposit.reason = reason.clone();
+        deposit.was_disputed = true;
         Ok(())
     }
 
-    fn handle_resolve(&mut self, resolve: Resolve) -> Result<()> {
-        let Resolve {
+    fn handle_resolve(&mut self, resolve: &Resolve) -> Result<()> {
+        let &Resolve {
+            client_id,
             disputed_transaction,
+            dispute_id,
         } = resolve;
+        self.release_dispute(client_id, disputed_transaction, dispute_id)
+            .map(|_| ())
+    }
+
+    fn handle_cancel_dispute(&mut self, cancel: &CancelDispute) -> Result<()> {
+        let &CancelDispute {
+            client_id,
+            disputed_transaction,
+            dispute_id,
+        } = cancel;
+        if let Some(deposit) = self.release_dispute(client_id, disputed_transaction, dispute_id)? {
+            deposit.cancellations = deposit.cancellations.saturating_add(1);
+        }
+        Ok(())
+    }
+
+    /// return the held funds of a disputed deposit to the client, for a resolve or a cancellation.
+    /// returns the deposit, or `None` if it was not disputed and [`Config::lenient_resolve`] is set.
+    fn release_dispute(
+        &mut self,
+        client_id: ClientId,
+        disputed_transaction: Option<TransactionId>,
+        dispute_id: Option<DisputeId>,
+    ) -> Result<Option<&mut SeenDeposit>> {
+        let key = self.disputed_key(client_id, disputed_transaction, dispute_id)?;
         let deposit = self
-            .deposit_transactions
-            .get_mut(&disputed_transaction)
+            .store
+            .get_deposit_mut(&key)
             .ok_or(Error::TransactionNotFound)?;
-        if !deposit.disputed {
-            return Err(Error::TransactionNotDisputed);
+        match deposit.state {
+            DepositState::Disputed => {}
+            // redundant resolve, nothing to do
+            DepositState::Normal if self.config.lenient_resolve => return Ok(None),
+            DepositState::Normal => return Err(Error::TransactionNotDisputed),
+            DepositState::ChargedBack => return Err(Error::TransactionChargedBack),
         }
-        self.clients
+        let result = self
+            .clients
             .entry(deposit.client_id)
             .or_default()
-            .resolve(deposit.amount)?;
-        // a resolved transaction can be disputed again, so we only change the flag
-        // and don't remove it from the list of deposits
-        deposit.disputed = false;
-        Ok(())
+            .resolve(deposit.disputed_amount());
+        check_held_invariant(&self.config, result).map_err(|e| e.for_client(deposit.client_id))?;
+        // a resolved transaction can be disputed again
+        deposit.state = DepositState::Normal;
+        deposit.reason = None;
+        Ok(Some(deposit))
     }
 
-    fn handle_chargeback(&mut self, chargeback: Chargeback) -> Result<()> {
-        let Chargeback {
+    fn handle_chargeback(&mut self, chargeback: &Chargeback) -> Result<()> {
+        let &Chargeback {
+            client_id,
             disputed_transaction,
+            dispute_id,
+            ref reason,
+            basis_points,
         } = chargeback;
+        let key = self.disputed_key(client_id, disputed_transaction, dispute_id)?;
         let deposit = self
-            .deposit_transactions
-            .get_mut(&disputed_transaction)
+            .store
+            .get_deposit_mut(&key)
             .ok_or(Error::TransactionNotFound)?;
-        if !deposit.disputed {
-            return Err(Error::TransactionNotDisputed);
+        match deposit.state {
+            DepositState::Disputed => {}
+            DepositState::Normal => return Err(Error::TransactionNotDisputed),
+            DepositState::ChargedBack => return Err(Error::TransactionChargedBack),
         }
-        self.clients
+        let (mut charged, mut remainder) = chargeback_split(&self.config, deposit, basis_points);
+        let client = self.clients.entry(deposit.client_id).or_default();
+        if client.held.0 < charged.0 as i128 {
+            match self.config.chargeback_shortfall {
+                ChargebackShortfall::LockAndFail => {}
+                ChargebackShortfall::Clamp => {
+                    charged = Amount(client.held.0.max(0) as u64);
+                    remainder = Amount::zero();
+                }
+                ChargebackShortfall::Fail => {
+                    return check_held_invariant(&self.config, Err(Error::InsufficientHeldFunds))
+                        .map_err(|e| e.for_client(deposit.client_id));
+                }
+            }
+        }
+        let result = client.chargeback(charged);
+        // the account is locked even if the chargeback itself fails.
+        // a chargeback on an account that is already locked, e.g. by an earlier chargeback, still removes the held funds.
+        self.locked_clients
             .entry(deposit.client_id)
-            .or_default()
-            .chargeback(deposit.amount)?;
-        // when a transaction has been charged back, we remove it from the list of deposits
-        // to prevent it from being disputed again.
-        self.deposit_transactions.remove(&disputed_transaction);
+            .or_insert(LockReason::Chargeback(key.1));
+        check_held_invariant(&self.config, result).map_err(|e| e.for_client(deposit.client_id))?;
+        if reason.is_some() {
+            deposit.reason = reason.clone();
+        }
+        // a deposit is counted once, however many partial chargebacks it takes
+        if deposit.charged_back == Amount::zero() {
+            client.chargebacks = client.chargebacks.saturating_add(1);
+        }
+        deposit.charged_back = Amount(deposit.charged_back.0 + charged.0);
+        if remainder != Amount::zero() {
+            if !self.config.resolve_partial_chargeback_remainder {
+                // the rest stays held and disputed, to be resolved or charged back later
+                return Ok(());
+            }
+            let result = client.resolve(remainder);
+            check_held_invariant(&self.config, result)
+                .map_err(|e| e.for_client(deposit.client_id))?;
+        }
+        // when a transaction has been charged back, we keep it as a tombstone
+        // so it can be reported on, but it can't be disputed again.
+        deposit.state = DepositState::ChargedBack;
         Ok(())
     }
 
+    /// the reason of the lock if `action` is a deposit or withdrawal for a locked account that must be quarantined.
+    fn quarantine_reason(&self, action: &AccountAction) -> Option<LockReason> {
+        if !self.config.quarantine_locked_accounts {
+            return None;
+        }
+        let client_id = match action {
+            AccountAction::Deposit(deposit) => deposit.client_id,
+            AccountAction::Withdrawal(withdrawal) => withdrawal.client_id,
+            _ => return None,
+        };
+        self.lock_reason(client_id)
+    }
+
+    /// the client, if it exists and could be closed now.
+    fn closable(&self, client_id: ClientId) -> Result<&Client> {
+        let client = self.clients.get(&client_id).ok_or(Error::ClientNotFound)?;
+        if client.closed {
+            return Err(Error::AccountClosed);
+        }
+        // a dispute of zero holds nothing, so held funds alone don't show every open dispute
+        let open_dispute = self
+            .store
+            .deposits()
+            .any(|(_, deposit)| deposit.client_id == client_id && deposit.is_disputed());
+        if client.held != Balance::zero() || open_dispute {
+            return Err(Error::OpenDisputes);
+        }
+        Ok(client)
+    }
+
+    fn handle_close(&mut self, close: &Close) -> Result<()> {
+        self.closable(close.client_id)?;
+        self.client_mut(close.client_id).close()
+    }
+
     /// perform an action on the database.
     ///
     /// for deposits and withdrawals, this will check that the transaction id is unique, or return an error then try to update the client's balance.
@@ -204,20 +1432,583 @@ impl Database {
     ///
     /// for disputes, resolves, and chargebacks, this will look up the transaction in the list of deposits and if it exists will try and perform the action returning an error if it fails.
     /// updates to the client's balance are atomic. They will either fully succeed or fully fail.
+    ///
+    /// actions not in the configured [`Config::allowed_actions`] are rejected without any effect, and deposits and
+    /// withdrawals for a locked account are quarantined if [`Config::quarantine_locked_accounts`] is set.
     pub fn perform_action(&mut self, action: AccountAction) -> Result<()> {
+        self.perform_action_ref(&action)
+    }
+
+    /// perform an action like [`Database::perform_action_ref`], but wrap a failure in [`Error::Action`] with `index`,
+    /// e.g. the number of the record the action was read from, as the binary reports failures for a CSV file.
+    pub fn apply_action_at(&mut self, index: usize, action: &AccountAction) -> Result<()> {
+        self.perform_action_ref(action)
+            .map_err(|source| Error::Action {
+                index,
+                source: Box::new(source),
+            })
+    }
+
+    /// perform an action like [`Database::perform_action`], but turn a panic into [`Error::Panicked`]
+    /// so a server can keep serving other clients.
+    ///
+    /// panics should never happen, they indicate a bug, e.g. a violated invariant with
+    /// [`Config::panic_on_invariant_violation`]. the database (and its store) are not
+    /// [`UnwindSafe`](std::panic::UnwindSafe), which is asserted here rather than guaranteed. instead, the clients,
+    /// transactions, and dispute ids the action involves are saved before it is performed, and restored if it
+    /// panics: the clients with their locks, the deposits, withdrawals, and adjustments of the involved transaction
+    /// ids, and whether those ids were used. this undoes the action only if the store is itself unwind safe,
+    /// i.e. a panic never leaves its [`TransactionStore::forget`] or `record_*` methods half done, as for the
+    /// [`MemoryStore`]. anything else the store keeps is not restored.
+    /// the panic is still reported by the panic hook, and is not caught if panics abort.
+    pub fn try_perform_action(&mut self, action: AccountAction) -> Result<()> {
+        let undo = self.save_for_undo(&action);
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            self.perform_action_ref(&action)
+        }))
+        .unwrap_or_else(|payload| {
+            self.undo(undo);
+            let message = match payload.downcast::<String>() {
+                Ok(message) => *message,
+                Err(payload) => match payload.downcast::<&str>() {
+                    Ok(message) => message.to_string(),
+                    Err(_) => "unknown panic".to_string(),
+                },
+            };
+            Err(Error::Panicked(message))
+        })
+    }
+
+    /// the available funds of the affected client after performing `action`, without changing the database.
+    /// fails with the error performing the action would fail with.
+    ///
+    /// the action is performed on a copy of the clients, deposits, and withdrawals it involves, so the preview
+    /// can't disagree with [`Database::perform_action`].
+    /// for disputes, resolves, and chargebacks the affected client is the client of the disputed deposit.
+    /// no-op actions affect no client, they fail with [`Error::ActionNotAllowed`].
+    /// the uniqueness of transaction ids is not checked, nor is [`Config::max_clients`].
+    pub fn available_after(&self, action: &AccountAction) -> Result<Balance> {
+        let mut scratch = self.scratch_for(action);
+        scratch.dispatch(action)?;
+        let client_id = scratch
+            .affected_client(action)
+            .ok_or(Error::ActionNotAllowed(ActionKind::NoOp))?;
+        Ok(scratch
+            .clients
+            .get(&client_id)
+            .map_or(Balance::zero(), |client| client.available))
+    }
+
+    /// the clients, transactions, and dispute ids `action` may read or change.
+    fn involved(&self, action: &AccountAction) -> Involved {
+        let mut involved = Involved {
+            clients: Vec::from_iter(self.config.fee_account),
+            ..Involved::default()
+        };
+        let scope = |client_id| self.config.per_client_transaction_ids.then_some(client_id);
+        match action {
+            AccountAction::Deposit(Deposit {
+                client_id,
+                transaction_id,
+                ..
+            })
+            | AccountAction::Withdrawal(Withdrawal {
+                client_id,
+                transaction_id,
+                ..
+            })
+            | AccountAction::Adjustment(Adjustment {
+                client_id,
+                transaction_id,
+                ..
+            }) => {
+                involved.clients.push(*client_id);
+                involved.keys.push(self.key(*client_id, *transaction_id));
+            }
+            AccountAction::Dispute(dispute) => {
+                involved
+                    .keys
+                    .push(self.key(dispute.client_id, dispute.disputed_transaction));
+                involved
+                    .dispute_ids
+                    .extend(dispute.dispute_id.map(|id| (scope(dispute.client_id), id)));
+            }
+            AccountAction::Resolve(Resolve {
+                client_id,
+                disputed_transaction,
+                dispute_id,
+            })
+            | AccountAction::CancelDispute(CancelDispute {
+                client_id,
+                disputed_transaction,
+                dispute_id,
+            })
+            | AccountAction::Chargeback(Chargeback {
+                client_id,
+                disputed_transaction,
+                dispute_id,
+                ..
+            }) => {
+                involved
+                    .keys
+                    .extend(disputed_transaction.map(|tx| self.key(*client_id, tx)));
+                if let Some(id) = *dispute_id {
+                    involved.dispute_ids.push((scope(*client_id), id));
+                    involved.keys.extend(self.dispute(*client_id, id));
+                }
+            }
+            AccountAction::Refund(refund) => {
+                involved
+                    .keys
+                    .push(self.key(refund.client_id, refund.refunded_transaction));
+            }
+            AccountAction::Close(close) => {
+                involved.clients.push(close.client_id);
+                // the open disputes keep an account from closing
+                involved.keys.extend(
+                    self.store
+                        .deposits()
+                        .filter(|(_, deposit)| {
+                            deposit.client_id == close.client_id && deposit.is_disputed()
+                        })
+                        .map(|(key, _)| *key),
+                );
+            }
+            AccountAction::NoOp(_) => {}
+        }
+        for key in &involved.keys {
+            if let Some(deposit) = self.store.get_deposit(key) {
+                involved.clients.push(deposit.client_id);
+            }
+            if let Some(withdrawal) = self.store.get_withdrawal(key) {
+                involved.clients.push(withdrawal.client_id);
+            }
+        }
+        involved
+    }
+
+    /// a database with a copy of the clients, deposits, withdrawals, and dispute ids `action` involves,
+    /// to preview the action on.
+    fn scratch_for(&self, action: &AccountAction) -> Database {
+        let mut scratch = Database::with_config(Config {
+            // a preview reports a violated invariant like a release build does, instead of panicking
+            panic_on_invariant_violation: false,
+            max_clients: None,
+            duplicate_deposit_window: None,
+            track_insertion_order: false,
+            ..self.config.clone()
+        });
+        let involved = self.involved(action);
+        for id in involved.dispute_ids {
+            if let Some(key) = self.disputes.get(&id) {
+                scratch.disputes.insert(id, *key);
+            }
+        }
+        for key in involved.keys {
+            if let Some(deposit) = self.store.get_deposit(&key) {
+                scratch.store.record_deposit(key, deposit.clone());
+            }
+            if let Some(withdrawal) = self.store.get_withdrawal(&key) {
+                scratch.store.record_withdrawal(key, withdrawal.clone());
+            }
+        }
+        for id in involved.clients {
+            if let Some(client) = self.clients.get(&id) {
+                scratch.clients.insert(id, client.clone());
+            }
+            if let Some(reason) = self.locked_clients.get(&id) {
+                scratch.locked_clients.insert(id, *reason);
+            }
+        }
+        scratch
+    }
+
+    /// save the state `action` may change, to undo the action with [`Database::undo`].
+    fn save_for_undo(&self, action: &AccountAction) -> Undo {
+        let involved = self.involved(action);
+        Undo {
+            clients: involved
+                .clients
+                .iter()
+                .map(|&id| SavedClient {
+                    id,
+                    client: self.clients.get(&id).cloned(),
+                    lock: self.locked_clients.get(&id).copied(),
+                    recent: self.recent_deposits.get(&id).cloned(),
+                })
+                .collect(),
+            transactions: involved
+                .keys
+                .iter()
+                .map(|&key| SavedTransaction {
+                    key,
+                    seen: self.store.is_seen(&key),
+                    deposit: self.store.get_deposit(&key).cloned(),
+                    withdrawal: self.store.get_withdrawal(&key).cloned(),
+                    adjustment: self.store.get_adjustment(&key).cloned(),
+                })
+                .collect(),
+            disputes: involved
+                .dispute_ids
+                .iter()
+                .map(|id| (*id, self.disputes.get(id).copied()))
+                .collect(),
+            quarantine: self.quarantine.len(),
+            insertion_order: self.insertion_order.len(),
+            possible_duplicates: self.possible_duplicates.len(),
+            performed: self.performed,
+        }
+    }
+
+    /// restore the state saved before an action, see [`Database::try_perform_action`].
+    fn undo(&mut self, undo: Undo) {
+        for SavedClient {
+            id,
+            client,
+            lock,
+            recent,
+        } in undo.clients
+        {
+            match client {
+                Some(client) => self.clients.insert(id, client),
+                None => self.clients.remove(&id),
+            };
+            match lock {
+                Some(reason) => self.locked_clients.insert(id, reason),
+                None => self.locked_clients.remove(&id),
+            };
+            match recent {
+                Some(recent) => self.recent_deposits.insert(id, recent),
+                None => self.recent_deposits.remove(&id),
+            };
+        }
+        for saved in undo.transactions {
+            let key = saved.key;
+            if !saved.seen {
+                self.store.forget(&key);
+            }
+            if let Some(deposit) = saved.deposit {
+                self.store.record_deposit(key, deposit);
+            }
+            if let Some(withdrawal) = saved.withdrawal {
+                self.store.record_withdrawal(key, withdrawal);
+            }
+            if let Some(adjustment) = saved.adjustment {
+                self.store.record_adjustment(key, adjustment);
+            }
+        }
+        for (id, key) in undo.disputes {
+            match key {
+                Some(key) => self.disputes.insert(id, key),
+                None => self.disputes.remove(&id),
+            };
+        }
+        self.quarantine.truncate(undo.quarantine);
+        self.insertion_order.truncate(undo.insertion_order);
+        self.possible_duplicates.truncate(undo.possible_duplicates);
+        self.performed = undo.performed;
+    }
+
+    /// perform an action and return the resulting state of the client it affected.
+    ///
+    /// for disputes, resolves, and chargebacks this is the client of the disputed deposit.
+    /// no-op actions affect no client, they fail with [`Error::ActionNotAllowed`] without being performed.
+    /// a deposit fee credited to the fee account is not reflected in the snapshot.
+    pub fn apply_and_get(&mut self, action: AccountAction) -> Result<ClientSnapshot> {
+        self.perform_action_ref(&action)?;
+        // a successful action always affects a known client, except for no-ops
+        let client_id = self
+            .affected_client(&action)
+            .ok_or(Error::ActionNotAllowed(ActionKind::NoOp))?;
+        Ok(self.snapshot(client_id))
+    }
+
+    /// apply actions like [`Database::apply_async`], calling `on_event` after every action that was applied,
+    /// e.g. to publish the updated balances to a queue without keeping a history.
+    ///
+    /// the event holds the affected client before and after the action, like [`Database::apply_and_get`];
+    /// no-op actions affect no client and have no event. returns the failed items with their errors.
+    pub fn apply_stream_with<I, F>(&mut self, actions: I, mut on_event: F) -> Vec<(usize, Error)>
+    where
+        I: IntoIterator<Item = Result<AccountAction>>,
+        F: FnMut(&AppliedEvent),
+    {
+        let mut failures = Vec::new();
+        for (index, action) in actions.into_iter().enumerate() {
+            let action = match action {
+                Ok(action) => action,
+                Err(e) => {
+                    failures.push((index, e));
+                    continue;
+                }
+            };
+            // the affected client is known before the action, unless the action is going to fail
+            let before = self
+                .affected_client(&action)
+                .map(|client_id| self.snapshot(client_id));
+            if let Err(e) = self.perform_action_ref(&action) {
+                failures.push((index, e));
+                continue;
+            }
+            if let Some(before) = before {
+                on_event(&AppliedEvent {
+                    index,
+                    kind: action.kind(),
+                    before,
+                    after: self.snapshot(before.id),
+                });
+            }
+        }
+        failures
+    }
+
+    /// the client whose balances an action changes, if the transaction or dispute it refers to is known.
+    ///
+    /// for disputes, resolves, cancellations, and chargebacks this is the client of the disputed deposit, for refunds that of the
+    /// withdrawal. `None` for no-op actions, which affect no client.
+    fn affected_client(&self, action: &AccountAction) -> Option<ClientId> {
+        match action {
+            AccountAction::Deposit(deposit) => Some(deposit.client_id),
+            AccountAction::Withdrawal(withdrawal) => Some(withdrawal.client_id),
+            AccountAction::Dispute(dispute) => {
+                self.deposit_client(self.key(dispute.client_id, dispute.disputed_transaction))
+            }
+            AccountAction::Resolve(Resolve {
+                client_id,
+                disputed_transaction,
+                dispute_id,
+            })
+            | AccountAction::CancelDispute(CancelDispute {
+                client_id,
+                disputed_transaction,
+                dispute_id,
+            })
+            | AccountAction::Chargeback(Chargeback {
+                client_id,
+                disputed_transaction,
+                dispute_id,
+                ..
+            }) => self
+                .disputed_key(*client_id, *disputed_transaction, *dispute_id)
+                .ok()
+                .and_then(|key| self.deposit_client(key)),
+            AccountAction::Refund(refund) => self
+                .store
+                .get_withdrawal(&self.key(refund.client_id, refund.refunded_transaction))
+                .map(|withdrawal| withdrawal.client_id),
+            AccountAction::Close(close) => Some(close.client_id),
+            AccountAction::Adjustment(adjustment) => Some(adjustment.client_id),
+            AccountAction::NoOp(_) => None,
+        }
+    }
+
+    /// the client of a deposit, if the deposit is known.
+    fn deposit_client(&self, key: TransactionKey) -> Option<ClientId> {
+        self.store
+            .get_deposit(&key)
+            .map(|deposit| deposit.client_id)
+    }
+
+    /// the balances of a client, which are all zero if the client doesn't exist (yet).
+    fn snapshot(&self, id: ClientId) -> ClientSnapshot {
+        let default = Client::default();
+        ClientWithId {
+            id,
+            client: self.clients.get(&id).unwrap_or(&default),
+        }
+        .into()
+    }
+
+    /// perform an action on the database without consuming it, e.g. to keep it around for logging.
+    ///
+    /// behaves exactly like [`Database::perform_action`].
+    pub fn perform_action_ref(&mut self, action: &AccountAction) -> Result<()> {
+        let result = self.dispatch(action);
+        #[cfg(feature = "tracing")]
+        trace_action(action, &result);
+        result
+    }
+
+    fn dispatch(&mut self, action: &AccountAction) -> Result<()> {
+        self.performed += 1;
+        if let Some(allowed) = &self.config.allowed_actions {
+            if !allowed.contains(&action.kind()) {
+                return Err(Error::ActionNotAllowed(action.kind()));
+            }
+        }
+        if let Some(reason) = self.quarantine_reason(action) {
+            self.quarantine.push(QuarantinedAction {
+                action: action.clone(),
+                reason,
+            });
+            return Err(Error::Quarantined);
+        }
         match action {
             AccountAction::Deposit(deposit) => self.handle_deposit(deposit),
             AccountAction::Withdrawal(withdrawal) => self.handle_withdrawal(withdrawal),
             AccountAction::Dispute(dispute) => self.handle_dispute(dispute),
             AccountAction::Resolve(resolve) => self.handle_resolve(resolve),
+            AccountAction::CancelDispute(cancel) => self.handle_cancel_dispute(cancel),
             AccountAction::Chargeback(chargeback) => self.handle_chargeback(chargeback),
+            AccountAction::Refund(refund) => self.handle_refund(refund),
+            AccountAction::Close(close) => self.handle_close(close),
+            AccountAction::Adjustment(adjustment) => self.handle_adjustment(adjustment),
+            // control rows are accepted without any effect
+            AccountAction::NoOp(_) => Ok(()),
+        }
+    }
+
+    /// apply actions as they arrive on an async stream, e.g. from a socket.
+    ///
+    /// like the CSV input, an item that is an error or fails to apply does not stop the processing.
+    /// returns the failed items (counting from 0) with their errors once the stream ends.
+    #[cfg(feature = "async")]
+    pub async fn apply_async<St>(&mut self, stream: St) -> Vec<(usize, Error)>
+    where
+        St: futures::Stream<Item = Result<AccountAction>>,
+    {
+        use futures::StreamExt;
+        let mut failures = Vec::new();
+        let mut stream = std::pin::pin!(stream.enumerate());
+        while let Some((n, action)) = stream.next().await {
+            if let Err(e) = action.and_then(|action| self.perform_action(action)) {
+                failures.push((n, e));
+            }
+        }
+        failures
+    }
+
+    /// apply newline-delimited JSON, one action per line, e.g. `{"type":"deposit","client":1,"tx":1,"amount":"1.0"}`.
+    ///
+    /// like the CSV input, a line that fails to parse or apply does not stop the processing.
+    /// returns the failed lines (counting from 0) with their errors, blank lines are skipped.
+    /// only fails if the input could not be read.
+    #[cfg(feature = "serde")]
+    pub fn apply_jsonl<R: Read>(&mut self, r: R) -> std::io::Result<Vec<(usize, Error)>> {
+        let mut failures = Vec::new();
+        for (n, line) in BufReader::new(r).lines().enumerate() {
+            let line = line?;
+            // skip the byte order mark some Windows tools put at the start of a file
+            let line = if n == 0 {
+                line.strip_prefix('\u{feff}').unwrap_or(&line)
+            } else {
+                &line
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let result = serde_json::from_str::<AccountAction>(line)
+                .map_err(|e| Error::Parse(e.to_string()))
+                .and_then(|action| self.perform_action(action));
+            if let Err(e) = result {
+                failures.push((n, e));
+            }
         }
+        Ok(failures)
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use crate::actions::NoOp;
+    use crate::{ClientIdRepr, TransactionIdRepr};
+
+    /// a deposit without a memo.
+    pub(crate) fn deposit(
+        client: ClientIdRepr,
+        tx: TransactionIdRepr,
+        amount: u64,
+    ) -> AccountAction {
+        AccountAction::Deposit(Deposit {
+            client_id: ClientId(client),
+            transaction_id: TransactionId(tx),
+            amount: Amount(amount),
+            memo: None,
+        })
+    }
+
+    /// a withdrawal without a memo.
+    pub(crate) fn withdrawal(
+        client: ClientIdRepr,
+        tx: TransactionIdRepr,
+        amount: u64,
+    ) -> AccountAction {
+        AccountAction::Withdrawal(Withdrawal {
+            client_id: ClientId(client),
+            transaction_id: TransactionId(tx),
+            amount: Amount(amount),
+            memo: None,
+        })
+    }
+
+    /// a dispute of a deposit, without a dispute id or a reason.
+    pub(crate) fn dispute(client: ClientIdRepr, tx: TransactionIdRepr) -> AccountAction {
+        AccountAction::Dispute(Dispute {
+            client_id: ClientId(client),
+            disputed_transaction: TransactionId(tx),
+            dispute_id: None,
+            reason: None,
+        })
+    }
+
+    /// a resolve of the dispute of a deposit, by its transaction id.
+    pub(crate) fn resolve(client: ClientIdRepr, tx: TransactionIdRepr) -> AccountAction {
+        AccountAction::Resolve(Resolve {
+            client_id: ClientId(client),
+            disputed_transaction: Some(TransactionId(tx)),
+            dispute_id: None,
+        })
+    }
+
+    /// a chargeback of all held funds of a disputed deposit, by its transaction id and without a reason.
+    pub(crate) fn chargeback(client: ClientIdRepr, tx: TransactionIdRepr) -> AccountAction {
+        AccountAction::Chargeback(Chargeback {
+            client_id: ClientId(client),
+            disputed_transaction: Some(TransactionId(tx)),
+            dispute_id: None,
+            reason: None,
+            basis_points: None,
+        })
+    }
+
+    /// a cancellation of the dispute of a deposit, by its transaction id.
+    pub(crate) fn cancel(client: ClientIdRepr, tx: TransactionIdRepr) -> AccountAction {
+        AccountAction::CancelDispute(CancelDispute {
+            client_id: ClientId(client),
+            disputed_transaction: Some(TransactionId(tx)),
+            dispute_id: None,
+        })
+    }
+
+    /// a refund of a withdrawal.
+    pub(crate) fn refund(client: ClientIdRepr, tx: TransactionIdRepr) -> AccountAction {
+        AccountAction::Refund(Refund {
+            client_id: ClientId(client),
+            refunded_transaction: TransactionId(tx),
+        })
+    }
+
+    /// a closure of the account of a client.
+    pub(crate) fn close(client: ClientIdRepr) -> AccountAction {
+        AccountAction::Close(Close {
+            client_id: ClientId(client),
+        })
+    }
+
+    /// an adjustment without a memo.
+    pub(crate) fn adjustment(
+        client: ClientIdRepr,
+        tx: TransactionIdRepr,
+        signed_amount: SignedAmount,
+    ) -> AccountAction {
+        AccountAction::Adjustment(Adjustment {
+            client_id: ClientId(client),
+            transaction_id: TransactionId(tx),
+            signed_amount,
+            memo: None,
+        })
+    }
 
     /// ensure disputes can only target deposits
     #[test]
@@ -227,20 +2018,52 @@ mod tests {
             client_id: ClientId(1),
             transaction_id: TransactionId(1),
             amount: Amount(1),
+            memo: None,
         };
         let withdrawal = Withdrawal {
             client_id: ClientId(1),
             transaction_id: TransactionId(2),
             amount: Amount(1),
+            memo: None,
         };
         let dispute = Dispute {
+            client_id: ClientId(1),
             disputed_transaction: TransactionId(2),
+            dispute_id: None,
+            reason: None,
         };
         assert!(db.perform_action(AccountAction::Deposit(deposit)).is_ok());
         assert!(db
             .perform_action(AccountAction::Withdrawal(withdrawal))
             .is_ok());
-        assert!(db.perform_action(AccountAction::Dispute(dispute)).is_err());
+        assert!(matches!(
+            db.available_after(&AccountAction::Dispute(dispute.clone())),
+            Err(Error::CannotDisputeWithdrawal(TransactionId(2)))
+        ));
+        assert!(matches!(
+            db.perform_action(AccountAction::Dispute(dispute)),
+            Err(Error::CannotDisputeWithdrawal(TransactionId(2)))
+        ));
+        // failed withdrawals are not kept, so they are as unknown as transaction ids that were never used
+        assert!(db
+            .perform_action(AccountAction::Withdrawal(Withdrawal {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(3),
+                amount: Amount(1),
+                memo: None,
+            }))
+            .is_err());
+        for tx in [3, 4] {
+            assert!(matches!(
+                db.perform_action(AccountAction::Dispute(Dispute {
+                    client_id: ClientId(1),
+                    disputed_transaction: TransactionId(tx),
+                    dispute_id: None,
+                    reason: None,
+                })),
+                Err(Error::TransactionNotFound)
+            ));
+        }
     }
 
     /// ensure that transactions can't be processed twice
@@ -253,6 +2076,7 @@ mod tests {
                 client_id: ClientId(1),
                 transaction_id: TransactionId(1),
                 amount: Amount(1),
+                memo: None,
             }))
             .is_ok());
         assert!(db
@@ -260,6 +2084,7 @@ mod tests {
                 client_id: ClientId(1),
                 transaction_id: TransactionId(1),
                 amount: Amount(1),
+                memo: None,
             }))
             .is_err());
         assert!(db
@@ -267,6 +2092,7 @@ mod tests {
                 client_id: ClientId(1),
                 transaction_id: TransactionId(1),
                 amount: Amount(1),
+                memo: None,
             }))
             .is_err());
         assert!(db
@@ -274,6 +2100,7 @@ mod tests {
                 client_id: ClientId(1),
                 transaction_id: TransactionId(2),
                 amount: Amount(1),
+                memo: None,
             }))
             .is_ok());
     }
@@ -287,21 +2114,33 @@ mod tests {
                 client_id: ClientId(1),
                 transaction_id: TransactionId(1),
                 amount: Amount(1),
+                memo: None,
             }))
             .is_ok());
         assert!(db
             .perform_action(AccountAction::Dispute(Dispute {
+                client_id: ClientId(1),
                 disputed_transaction: TransactionId(1),
+                dispute_id: None,
+                reason: None,
             }))
             .is_ok());
         assert!(db
             .perform_action(AccountAction::Chargeback(Chargeback {
-                disputed_transaction: TransactionId(1),
+                client_id: ClientId(1),
+                disputed_transaction: Some(TransactionId(1)),
+                dispute_id: None,
+                reason: None,
+                basis_points: None,
             }))
             .is_ok());
         assert!(db
             .perform_action(AccountAction::Chargeback(Chargeback {
-                disputed_transaction: TransactionId(1),
+                client_id: ClientId(1),
+                disputed_transaction: Some(TransactionId(1)),
+                dispute_id: None,
+                reason: None,
+                basis_points: None,
             }))
             .is_err());
     }
@@ -315,15 +2154,47 @@ mod tests {
                 client_id: ClientId(1),
                 transaction_id: TransactionId(1),
                 amount: Amount(1),
+                memo: None,
             }))
             .is_ok());
         assert!(db
             .perform_action(AccountAction::Chargeback(Chargeback {
-                disputed_transaction: TransactionId(1),
+                client_id: ClientId(1),
+                disputed_transaction: Some(TransactionId(1)),
+                dispute_id: None,
+                reason: None,
+                basis_points: None,
             }))
             .is_err());
     }
 
+    /// ensure balance overflows carry the client and expose the underlying overflow as the error source
+    #[test]
+    fn overflow_source() {
+        use std::error::Error as _;
+        let mut db = Database::new();
+        db.client_mut(ClientId(1)).available = crate::Balance(i128::MAX);
+        let err = db
+            .perform_action(AccountAction::Deposit(Deposit {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(1),
+                amount: Amount(1),
+                memo: None,
+            }))
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::BalanceUpdate {
+                client: ClientId(1),
+                ..
+            }
+        ));
+        let source = err
+            .source()
+            .and_then(|source| source.downcast_ref::<crate::ArithmeticError>());
+        assert_eq!(source, Some(&crate::ArithmeticError::Overflow));
+    }
+
     /// ensure that we can't "resolve" a deposit if it hasn't been disputed
     #[test]
     fn resolve_no_dispute() {
@@ -333,12 +2204,2307 @@ mod tests {
                 client_id: ClientId(1),
                 transaction_id: TransactionId(1),
                 amount: Amount(1),
+                memo: None,
             }))
             .is_ok());
         assert!(db
             .perform_action(AccountAction::Resolve(Resolve {
-                disputed_transaction: TransactionId(1),
+                client_id: ClientId(1),
+                disputed_transaction: Some(TransactionId(1)),
+                dispute_id: None,
+            }))
+            .is_err());
+    }
+
+    /// ensure that a lenient database accepts resolving a known, undisputed deposit without changing balances
+    #[test]
+    fn resolve_no_dispute_lenient() {
+        let mut db = Database::with_config(Config {
+            lenient_resolve: true,
+            ..Config::default()
+        });
+        assert!(db
+            .perform_action(AccountAction::Deposit(Deposit {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(1),
+                amount: Amount(1),
+                memo: None,
+            }))
+            .is_ok());
+        assert!(db
+            .perform_action(AccountAction::Resolve(Resolve {
+                client_id: ClientId(1),
+                disputed_transaction: Some(TransactionId(1)),
+                dispute_id: None,
+            }))
+            .is_ok());
+        let client = db.client_mut(ClientId(1));
+        assert!(client.available().0 == 1);
+        assert!(client.held().0 == 0);
+        // unknown transactions are still an error
+        assert!(matches!(
+            db.perform_action(AccountAction::Resolve(Resolve {
+                client_id: ClientId(1),
+                disputed_transaction: Some(TransactionId(2)),
+                dispute_id: None,
+            })),
+            Err(Error::TransactionNotFound)
+        ));
+    }
+
+    /// ensure the memo of a deposit is kept for reporting
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deposit_memo() {
+        let entry = "type,client,tx,amount,memo\ndeposit,1,1,1.0,invoice 42\ndeposit,1,2,1.0,\n";
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(entry.as_bytes());
+        let mut db = Database::new();
+        for action in reader.deserialize::<AccountAction>() {
+            assert!(db.perform_action(action.unwrap()).is_ok());
+        }
+        let deposit = db.deposit_info(TransactionId(1)).unwrap();
+        assert_eq!(deposit.memo(), Some("invoice 42"));
+        assert_eq!(deposit.client_id(), ClientId(1));
+        assert!(db.deposit_info(TransactionId(2)).unwrap().memo().is_none());
+    }
+
+    /// ensure that with per-client transaction ids, the same id can be used by different clients
+    #[test]
+    fn per_client_transaction_ids() {
+        let mut db = Database::with_config(Config {
+            per_client_transaction_ids: true,
+            ..Config::default()
+        });
+        for client in [1, 2] {
+            assert!(db
+                .perform_action(AccountAction::Deposit(Deposit {
+                    client_id: ClientId(client),
+                    transaction_id: TransactionId(1),
+                    amount: Amount(client as u64),
+                    memo: None,
+                }))
+                .is_ok());
+        }
+        // still unique within a client
+        assert!(db
+            .perform_action(AccountAction::Withdrawal(Withdrawal {
+                client_id: ClientId(2),
+                transaction_id: TransactionId(1),
+                amount: Amount(1),
+                memo: None,
             }))
             .is_err());
+        assert!(db
+            .perform_action(AccountAction::Dispute(Dispute {
+                client_id: ClientId(2),
+                disputed_transaction: TransactionId(1),
+                dispute_id: None,
+                reason: None,
+            }))
+            .is_ok());
+        let first = db
+            .client_deposit_info(ClientId(1), TransactionId(1))
+            .unwrap();
+        assert!(!first.is_disputed());
+        assert_eq!(first.amount(), Amount(1));
+        let second = db
+            .client_deposit_info(ClientId(2), TransactionId(1))
+            .unwrap();
+        assert!(second.is_disputed());
+        assert_eq!(second.amount(), Amount(2));
+        assert!(db.client_mut(ClientId(2)).held() == Balance(2));
+        assert!(db.client_mut(ClientId(1)).held() == Balance(0));
+    }
+
+    /// ensure newline-delimited JSON is parsed and applied, including the string amounts
+    #[cfg(feature = "serde")]
+    #[test]
+    fn apply_jsonl() {
+        let input = r#"{"type":"deposit","client":1,"tx":1,"amount":"1.5"}
+{"type":"deposit","client":1,"tx":2,"amount":"2.0000","memo":"second"}
+
+{"type":"dispute","client":1,"tx":1}
+{"type":"withdrawal","client":1,"tx":3,"amount":1.0}
+{"type":"withdrawal","client":1,"tx":4,"amount":"5.0"}
+"#;
+        let mut db = Database::new();
+        let failures = db.apply_jsonl(input.as_bytes()).unwrap();
+        // numeric amounts are rejected, and the last withdrawal is larger than the available funds
+        assert!(matches!(
+            failures.as_slice(),
+            [(4, Error::Parse(_)), (5, Error::InsufficientFunds { .. })]
+        ));
+        let client = db.client_mut(ClientId(1));
+        assert!(client.available() == Balance(2_0000));
+        assert!(client.held() == Balance(1_5000));
+        assert_eq!(
+            db.deposit_info(TransactionId(2)).unwrap().memo(),
+            Some("second")
+        );
+    }
+
+    /// ensure actions outside of the allow-list are rejected
+    #[test]
+    fn allowed_actions() {
+        let mut db = Database::with_config(Config {
+            allowed_actions: Some(BTreeSet::from([
+                ActionKind::Deposit,
+                ActionKind::Withdrawal,
+            ])),
+            ..Config::default()
+        });
+        assert!(db
+            .perform_action(AccountAction::Deposit(Deposit {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(1),
+                amount: Amount(1),
+                memo: None,
+            }))
+            .is_ok());
+        assert!(matches!(
+            db.perform_action(AccountAction::Dispute(Dispute {
+                client_id: ClientId(1),
+                disputed_transaction: TransactionId(1),
+                dispute_id: None,
+                reason: None,
+            })),
+            Err(Error::ActionNotAllowed(ActionKind::Dispute))
+        ));
+        assert!(!db.deposit_info(TransactionId(1)).unwrap().is_disputed());
+    }
+
+    /// ensure summing adversarial balances returns an error instead of panicking
+    #[test]
+    fn totals_overflow() {
+        let mut db = Database::new();
+        db.client_mut(ClientId(0)).available = Balance(1);
+        db.client_mut(ClientId(0)).held = Balance(2);
+        let totals = db.totals().unwrap();
+        assert!(
+            totals.available == Balance(1)
+                && totals.held == Balance(2)
+                && totals.total == Balance(3)
+        );
+
+        for id in 1..100 {
+            db.client_mut(ClientId(id)).available = Balance(i128::MAX / 2);
+        }
+        assert!(matches!(db.totals(), Err(Error::Overflow)));
+        // a single client whose own total overflows
+        let mut db = Database::new();
+        db.client_mut(ClientId(0)).available = Balance(i128::MAX);
+        db.client_mut(ClientId(0)).held = Balance(i128::MAX);
+        assert!(matches!(db.totals(), Err(Error::Overflow)));
+    }
+
+    /// ensure a snapshot can be loaded and processing continues on top of it
+    #[cfg(feature = "serde")]
+    #[test]
+    fn load_client_snapshot() {
+        let snapshot = "client,available,held,total,locked\n1,1.5000,0.5000,2.0000,false\n2,-1.0000,0.0000,-1.0000,true\n";
+        let mut db = Database::load_client_snapshot(snapshot.as_bytes()).unwrap();
+        assert!(db.client_mut(ClientId(2)).is_locked());
+        assert_eq!(db.locked_count(), 1);
+        assert!(db
+            .perform_action(AccountAction::Deposit(Deposit {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(1),
+                amount: Amount(1_0000),
+                memo: None,
+            }))
+            .is_ok());
+        let client = db.client_mut(ClientId(1));
+        assert!(client.available() == Balance(2_5000));
+        assert!(client.total() == Balance(3_0000));
+        assert!(db.totals().unwrap().total == Balance(2_0000));
+
+        let mismatch = "client,available,held,total,locked\n1,1.5000,0.5000,2.0001,false\n";
+        assert!(matches!(
+            Database::load_client_snapshot(mismatch.as_bytes()),
+            Err(Error::SnapshotTotalMismatch(ClientId(1)))
+        ));
+    }
+
+    /// ensure clients loaded from a snapshot keep their order in the snapshot, before clients created afterwards
+    #[cfg(feature = "serde")]
+    #[test]
+    fn load_client_snapshot_insertion_order() {
+        let snapshot = "client,available,held,total,locked\n3,1.0000,0.0000,1.0000,false\n1,2.0000,0.0000,2.0000,false\n";
+        let config = Config {
+            track_insertion_order: true,
+            ..Config::default()
+        };
+        let mut db =
+            Database::load_client_snapshot_with_config(snapshot.as_bytes(), config).unwrap();
+        db.perform_action(AccountAction::Deposit(Deposit {
+            client_id: ClientId(2),
+            transaction_id: TransactionId(1),
+            amount: Amount(1),
+            memo: None,
+        }))
+        .unwrap();
+        let order = db
+            .clients_in_insertion_order()
+            .map(|client| client.id())
+            .collect::<Vec<_>>();
+        assert_eq!(order, [ClientId(3), ClientId(1), ClientId(2)]);
+
+        let duplicate = "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n1,1.0000,0.0000,1.0000,false\n";
+        assert!(matches!(
+            Database::load_client_snapshot(duplicate.as_bytes()),
+            Err(Error::Parse(_))
+        ));
+    }
+
+    /// ensure a snapshot total off by one unit is corrected within the tolerance, and rejected without it
+    #[cfg(feature = "serde")]
+    #[test]
+    fn snapshot_total_tolerance() {
+        let lenient = Config {
+            snapshot_total_tolerance: Amount(1),
+            ..Config::default()
+        };
+        for total in ["1.9999", "2.0001"] {
+            let snapshot =
+                format!("client,available,held,total,locked\n1,1.5000,0.5000,{total},false\n");
+            assert!(matches!(
+                Database::load_client_snapshot_with_config(snapshot.as_bytes(), Config::default()),
+                Err(Error::SnapshotTotalMismatch(ClientId(1)))
+            ));
+            let mut db =
+                Database::load_client_snapshot_with_config(snapshot.as_bytes(), lenient.clone())
+                    .unwrap();
+            assert!(db.client_mut(ClientId(1)).total() == Balance(2_0000));
+            assert_eq!(db.config().snapshot_total_tolerance, Amount(1));
+        }
+        let snapshot = "client,available,held,total,locked\n1,1.5000,0.5000,2.0002,false\n";
+        assert!(matches!(
+            Database::load_client_snapshot_with_config(snapshot.as_bytes(), lenient),
+            Err(Error::SnapshotTotalMismatch(ClientId(1)))
+        ));
+    }
+
+    /// ensure a snapshot total that disagrees is taken as is when trusted, and corrected when recomputed
+    #[cfg(feature = "serde")]
+    #[test]
+    fn snapshot_total() {
+        use crate::config::SnapshotTotal;
+
+        let snapshot = "client,available,held,total,locked\n1,1.5000,0.5000,2.5000,false\n";
+        let load = |snapshot_total| {
+            Database::load_client_snapshot_with_config(
+                snapshot.as_bytes(),
+                Config {
+                    snapshot_total,
+                    ..Config::default()
+                },
+            )
+        };
+        assert!(matches!(
+            load(SnapshotTotal::Verify),
+            Err(Error::SnapshotTotalMismatch(ClientId(1)))
+        ));
+
+        let mut db = load(SnapshotTotal::Trust).unwrap();
+        let client = db.client_mut(ClientId(1));
+        assert!(client.total() == Balance(2_5000));
+        assert!(client.available() == Balance(2_0000));
+        assert!(client.held() == Balance(5000));
+
+        let mut db = load(SnapshotTotal::Recompute).unwrap();
+        let client = db.client_mut(ClientId(1));
+        assert!(client.total() == Balance(2_0000));
+        assert!(client.available() == Balance(1_5000));
+        assert!(client.held() == Balance(5000));
+    }
+
+    /// ensure a deposit fee is deducted from the credited amount and paid to the fee account
+    #[test]
+    fn deposit_fee() {
+        let mut db = Database::with_config(Config {
+            deposit_fee_bps: 25,
+            fee_account: Some(ClientId(0)),
+            ..Config::default()
+        });
+        assert!(db
+            .perform_action(AccountAction::Deposit(Deposit {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(1),
+                amount: Amount(100_0000),
+                memo: None,
+            }))
+            .is_ok());
+        assert!(db.client_mut(ClientId(1)).available() == Balance(99_7500));
+        assert!(db.client_mut(ClientId(0)).available() == Balance(2500));
+        assert_eq!(
+            db.deposit_info(TransactionId(1)).unwrap().amount(),
+            Amount(99_7500)
+        );
+        // disputing holds the credited amount, not the original one
+        assert!(db
+            .perform_action(AccountAction::Dispute(Dispute {
+                client_id: ClientId(1),
+                disputed_transaction: TransactionId(1),
+                dispute_id: None,
+                reason: None,
+            }))
+            .is_ok());
+        assert!(db.client_mut(ClientId(1)).held() == Balance(99_7500));
+        assert!(db.client_mut(ClientId(1)).available() == Balance(0));
+    }
+
+    /// ensure a deposit moves through the dispute lifecycle states
+    #[test]
+    fn deposit_state() {
+        let mut db = Database::new();
+        assert_eq!(db.deposit_state(TransactionId(1)), None);
+        assert!(db
+            .perform_action(AccountAction::Deposit(Deposit {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(1),
+                amount: Amount(1),
+                memo: None,
+            }))
+            .is_ok());
+        assert_eq!(
+            db.deposit_state(TransactionId(1)),
+            Some(DepositState::Normal)
+        );
+        assert!(db.perform_action(dispute(1, 1)).is_ok());
+        assert_eq!(
+            db.deposit_state(TransactionId(1)),
+            Some(DepositState::Disputed)
+        );
+        assert!(db
+            .perform_action(AccountAction::Resolve(Resolve {
+                client_id: ClientId(1),
+                disputed_transaction: Some(TransactionId(1)),
+                dispute_id: None,
+            }))
+            .is_ok());
+        assert_eq!(
+            db.deposit_state(TransactionId(1)),
+            Some(DepositState::Normal)
+        );
+        assert!(db.perform_action(dispute(1, 1)).is_ok());
+        assert!(db
+            .perform_action(AccountAction::Chargeback(Chargeback {
+                client_id: ClientId(1),
+                disputed_transaction: Some(TransactionId(1)),
+                dispute_id: None,
+                reason: None,
+                basis_points: None,
+            }))
+            .is_ok());
+        assert_eq!(
+            db.deposit_state(TransactionId(1)),
+            Some(DepositState::ChargedBack)
+        );
+        // a charged back deposit can't be disputed again
+        assert!(matches!(
+            db.perform_action(dispute(1, 1)),
+            Err(Error::TransactionChargedBack)
+        ));
+        assert_eq!(
+            db.deposit_state(TransactionId(1)),
+            Some(DepositState::ChargedBack)
+        );
+    }
+
+    /// ensure clients reaching the chargeback threshold are flagged
+    #[test]
+    fn flagged_clients() {
+        let mut db = Database::with_config(Config {
+            chargeback_flag_threshold: Some(3),
+            ..Config::default()
+        });
+        let mut tx = 0;
+        for (client, chargebacks) in [(1, 3), (2, 2)] {
+            for _ in 0..chargebacks {
+                tx += 1;
+                assert!(db
+                    .perform_action(AccountAction::Deposit(Deposit {
+                        client_id: ClientId(client),
+                        transaction_id: TransactionId(tx),
+                        amount: Amount(1),
+                        memo: None,
+                    }))
+                    .is_ok());
+                assert!(db
+                    .perform_action(AccountAction::Dispute(Dispute {
+                        client_id: ClientId(client),
+                        disputed_transaction: TransactionId(tx),
+                        dispute_id: None,
+                        reason: None,
+                    }))
+                    .is_ok());
+                assert!(db
+                    .perform_action(AccountAction::Chargeback(Chargeback {
+                        client_id: ClientId(client),
+                        disputed_transaction: Some(TransactionId(tx)),
+                        dispute_id: None,
+                        reason: None,
+                        basis_points: None,
+                    }))
+                    .is_ok());
+            }
+        }
+        let flagged: Vec<_> = db.flagged_clients().map(|client| client.id()).collect();
+        assert_eq!(flagged, [ClientId(1)]);
+        assert_eq!(db.client_mut(ClientId(2)).chargebacks(), 2);
+    }
+
+    /// ensure applying an async stream gives the same result as applying the actions one by one
+    #[cfg(feature = "async")]
+    #[test]
+    fn apply_async() {
+        let actions = || {
+            vec![
+                Ok(AccountAction::Deposit(Deposit {
+                    client_id: ClientId(1),
+                    transaction_id: TransactionId(1),
+                    amount: Amount(5),
+                    memo: None,
+                })),
+                Err(Error::Parse("garbage".to_string())),
+                Ok(AccountAction::Withdrawal(Withdrawal {
+                    client_id: ClientId(1),
+                    transaction_id: TransactionId(2),
+                    amount: Amount(10),
+                    memo: None,
+                })),
+                Ok(AccountAction::Dispute(Dispute {
+                    client_id: ClientId(1),
+                    disputed_transaction: TransactionId(1),
+                    dispute_id: None,
+                    reason: None,
+                })),
+            ]
+        };
+        let mut sync = Database::new();
+        for action in actions().into_iter().flatten() {
+            let _ = sync.perform_action(action);
+        }
+        let mut db = Database::new();
+        let failures =
+            futures::executor::block_on(db.apply_async(futures::stream::iter(actions())));
+        assert!(matches!(
+            failures.as_slice(),
+            [(1, Error::Parse(_)), (2, Error::InsufficientFunds { .. })]
+        ));
+        assert_eq!(sync.clients, db.clients);
+    }
+
+    /// ensure a dispute never picks the wrong transaction when a deposit and a withdrawal share a transaction id
+    #[test]
+    fn dispute_id_collision() {
+        // globally unique ids: the withdrawal is rejected, so the id unambiguously refers to the deposit,
+        // whichever client the dispute names.
+        let mut db = Database::new();
+        assert!(db.perform_action(deposit(1, 1, 5)).is_ok());
+        assert!(db.perform_action(deposit(2, 2, 5)).is_ok());
+        assert!(matches!(
+            db.perform_action(withdrawal(2, 1, 1)),
+            Err(Error::InvalidTransactionId)
+        ));
+        assert!(db.perform_action(dispute(2, 1)).is_ok());
+        assert!(db.client_mut(ClientId(1)).held() == Balance(5));
+        assert!(db.client_mut(ClientId(2)).held() == Balance(0));
+
+        // per-client ids: the withdrawal of client 2 is accepted, but disputing it does not touch the deposit of client 1.
+        let mut db = Database::with_config(Config {
+            per_client_transaction_ids: true,
+            ..Config::default()
+        });
+        assert!(db.perform_action(deposit(1, 1, 5)).is_ok());
+        assert!(db.perform_action(deposit(2, 2, 5)).is_ok());
+        assert!(db.perform_action(withdrawal(2, 1, 1)).is_ok());
+        assert!(matches!(
+            db.perform_action(dispute(2, 1)),
+            Err(Error::CannotDisputeWithdrawal(TransactionId(1)))
+        ));
+        assert!(db.client_mut(ClientId(1)).held() == Balance(0));
+        assert!(db.client_mut(ClientId(2)).held() == Balance(0));
+        assert!(db.perform_action(dispute(1, 1)).is_ok());
+        assert!(db.client_mut(ClientId(1)).held() == Balance(5));
+        // within a client a deposit and withdrawal can never share an id
+        assert!(matches!(
+            db.perform_action(withdrawal(1, 1, 1)),
+            Err(Error::InvalidTransactionId)
+        ));
+    }
+
+    /// ensure performing actions by reference gives the same results as performing them by value
+    #[test]
+    fn perform_action_ref() {
+        let actions = vec![
+            AccountAction::Deposit(Deposit {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(1),
+                amount: Amount(5),
+                memo: Some("kept".to_string()),
+            }),
+            AccountAction::Withdrawal(Withdrawal {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(2),
+                amount: Amount(10),
+                memo: None,
+            }),
+            AccountAction::Dispute(Dispute {
+                client_id: ClientId(1),
+                disputed_transaction: TransactionId(1),
+                dispute_id: None,
+                reason: None,
+            }),
+            AccountAction::Chargeback(Chargeback {
+                client_id: ClientId(1),
+                disputed_transaction: Some(TransactionId(1)),
+                dispute_id: None,
+                reason: None,
+                basis_points: None,
+            }),
+        ];
+        let mut by_ref = Database::new();
+        let results: Vec<_> = actions
+            .iter()
+            .map(|action| by_ref.perform_action_ref(action).is_ok())
+            .collect();
+        let mut by_value = Database::new();
+        let expected: Vec<_> = actions
+            .into_iter()
+            .map(|action| by_value.perform_action(action).is_ok())
+            .collect();
+        assert_eq!(results, expected);
+        assert_eq!(by_ref.clients, by_value.clients);
+        assert_eq!(
+            by_ref.deposit_info(TransactionId(1)).unwrap().memo(),
+            Some("kept")
+        );
+    }
+
+    /// ensure no clients are created beyond the configured limit
+    #[test]
+    fn max_clients() {
+        let mut db = Database::with_config(Config {
+            max_clients: Some(2),
+            ..Config::default()
+        });
+        assert!(db.perform_action(deposit(1, 1, 1)).is_ok());
+        assert!(db.perform_action(deposit(2, 2, 1)).is_ok());
+        assert!(matches!(
+            db.perform_action(deposit(3, 3, 1)),
+            Err(Error::ClientLimitExceeded)
+        ));
+        assert!(matches!(
+            db.perform_action(AccountAction::Withdrawal(Withdrawal {
+                client_id: ClientId(3),
+                transaction_id: TransactionId(4),
+                amount: Amount(1),
+                memo: None,
+            })),
+            Err(Error::ClientLimitExceeded)
+        ));
+        assert_eq!(db.clients().count(), 2);
+        // the rejected deposit did not use up its transaction id, and existing clients still accept deposits
+        assert!(db.perform_action(deposit(1, 3, 1)).is_ok());
+        assert!(db.client_mut(ClientId(1)).available() == Balance(2));
+    }
+
+    /// ensure the diff of two databases lists exactly the changed client
+    #[test]
+    fn diff() {
+        let mut first = Database::new();
+        let mut second = Database::new();
+        for db in [&mut first, &mut second] {
+            assert!(db.perform_action(deposit(1, 1, 5)).is_ok());
+            assert!(db.perform_action(deposit(2, 2, 5)).is_ok());
+        }
+        assert!(first.diff(&second).is_empty());
+        assert!(second.perform_action(deposit(2, 3, 1)).is_ok());
+        let diff = first.diff(&second);
+        assert_eq!(diff.len(), 1);
+        assert_eq!(diff[0].client, ClientId(2));
+        assert!(diff[0].before.available() == Balance(5));
+        assert!(diff[0].after.available() == Balance(6));
+        // a client missing on one side is compared against an empty client
+        assert!(second.perform_action(deposit(3, 4, 1)).is_ok());
+        let diff = first.diff(&second);
+        assert_eq!(diff.len(), 2);
+        assert_eq!(diff[1].before, Client::default());
+    }
+
+    /// ensure a zero-amount deposit goes through the whole dispute lifecycle without touching the balances
+    #[test]
+    fn zero_amount_dispute() {
+        let unchanged = |db: &mut Database| {
+            let client = db.client_mut(ClientId(1));
+            client.available() == Balance(5) && client.held() == Balance(0)
+        };
+
+        // by default the dispute is tracked like any other
+        let mut db = Database::new();
+        assert!(db.perform_action(deposit(1, 1, 5)).is_ok());
+        assert!(db.perform_action(deposit(1, 2, 0)).is_ok());
+        assert!(db.perform_action(dispute(1, 2)).is_ok());
+        assert!(unchanged(&mut db));
+        assert_eq!(
+            db.deposit_state(TransactionId(2)),
+            Some(DepositState::Disputed)
+        );
+        assert!(db.perform_action(resolve(1, 2)).is_ok());
+        assert!(unchanged(&mut db));
+        assert_eq!(
+            db.deposit_state(TransactionId(2)),
+            Some(DepositState::Normal)
+        );
+        assert!(db.perform_action(dispute(1, 2)).is_ok());
+        assert!(db.perform_action(chargeback(1, 2)).is_ok());
+        assert!(unchanged(&mut db));
+        assert!(db.client_mut(ClientId(1)).is_locked());
+        assert_eq!(
+            db.deposit_state(TransactionId(2)),
+            Some(DepositState::ChargedBack)
+        );
+        assert!(matches!(
+            db.perform_action(dispute(1, 2)),
+            Err(Error::TransactionChargedBack)
+        ));
+
+        // if configured, the dispute is ignored and nothing can follow it
+        let mut db = Database::with_config(Config {
+            ignore_zero_amount_disputes: true,
+            ..Config::default()
+        });
+        assert!(db.perform_action(deposit(1, 1, 5)).is_ok());
+        assert!(db.perform_action(deposit(1, 2, 0)).is_ok());
+        assert!(db.perform_action(dispute(1, 2)).is_ok());
+        assert!(unchanged(&mut db));
+        assert_eq!(
+            db.deposit_state(TransactionId(2)),
+            Some(DepositState::Normal)
+        );
+        assert!(matches!(
+            db.perform_action(resolve(1, 2)),
+            Err(Error::TransactionNotDisputed)
+        ));
+        assert!(matches!(
+            db.perform_action(chargeback(1, 2)),
+            Err(Error::TransactionNotDisputed)
+        ));
+        assert!(!db.client_mut(ClientId(1)).is_locked());
+        // deposits with an amount are still disputed as usual
+        assert!(db.perform_action(dispute(1, 1)).is_ok());
+        assert_eq!(
+            db.deposit_state(TransactionId(1)),
+            Some(DepositState::Disputed)
+        );
+    }
+
+    /// ensure the locked count follows chargebacks, freezes, and unfreezes
+    #[test]
+    fn locked_count() {
+        let mut db = Database::new();
+        for client in 1..=3 {
+            assert!(db
+                .perform_action(AccountAction::Deposit(Deposit {
+                    client_id: ClientId(client),
+                    transaction_id: TransactionId(client.into()),
+                    amount: Amount(1),
+                    memo: None,
+                }))
+                .is_ok());
+        }
+        assert_eq!(db.locked_count(), 0);
+        assert!(db
+            .perform_action(AccountAction::Dispute(Dispute {
+                client_id: ClientId(1),
+                disputed_transaction: TransactionId(1),
+                dispute_id: None,
+                reason: None,
+            }))
+            .is_ok());
+        assert!(db
+            .perform_action(AccountAction::Chargeback(Chargeback {
+                client_id: ClientId(1),
+                disputed_transaction: Some(TransactionId(1)),
+                dispute_id: None,
+                reason: None,
+                basis_points: None,
+            }))
+            .is_ok());
+        assert_eq!(db.locked_count(), 1);
+        assert!(db.freeze(ClientId(2)));
+        // freezing twice doesn't count twice
+        assert!(db.freeze(ClientId(2)));
+        assert!(!db.freeze(ClientId(4)));
+        assert_eq!(db.locked_count(), 2);
+        assert!(db.unfreeze(ClientId(1)));
+        assert!(!db.client_mut(ClientId(1)).is_locked());
+        assert_eq!(db.locked_count(), 1);
+        assert!(db.unfreeze(ClientId(3)));
+        assert_eq!(db.locked_count(), 1);
+        assert_eq!(
+            db.locked_count(),
+            db.clients().filter(|client| client.is_locked()).count()
+        );
+    }
+
+    /// ensure only unlocked clients with positive available funds below the threshold are swept
+    #[test]
+    fn sweep_dust() {
+        let mut db = Database::new();
+        for (client, amount) in [(1, 5), (2, 10), (3, 0), (4, 3), (5, 1)] {
+            assert!(db
+                .perform_action(AccountAction::Deposit(Deposit {
+                    client_id: ClientId(client),
+                    transaction_id: TransactionId(client.into()),
+                    amount: Amount(amount),
+                    memo: None,
+                }))
+                .is_ok());
+        }
+        assert!(db.freeze(ClientId(4)));
+        // client 5 has all of its funds held
+        assert!(db
+            .perform_action(AccountAction::Dispute(Dispute {
+                client_id: ClientId(5),
+                disputed_transaction: TransactionId(5),
+                dispute_id: None,
+                reason: None,
+            }))
+            .is_ok());
+        let swept = db.sweep_dust(Amount(10));
+        assert_eq!(swept.len(), 1);
+        assert_eq!(swept[0].0, ClientId(1));
+        assert!(swept[0].1 == Balance(5));
+        let available = |db: &mut Database, client| db.client_mut(ClientId(client)).available();
+        assert!(available(&mut db, 1) == Balance(0));
+        assert!(available(&mut db, 2) == Balance(10));
+        assert!(available(&mut db, 4) == Balance(3));
+        assert!(db.client_mut(ClientId(5)).held() == Balance(1));
+        assert!(db.sweep_dust(Amount(10)).is_empty());
+    }
+
+    /// ensure a byte order mark at the start of a JSON lines input is skipped
+    #[cfg(feature = "serde")]
+    #[test]
+    fn apply_jsonl_bom() {
+        let input = "\u{feff}{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"1.5\"}\n";
+        let mut db = Database::new();
+        assert!(db.apply_jsonl(input.as_bytes()).unwrap().is_empty());
+        assert!(db.client_mut(ClientId(1)).available() == Balance(1_5000));
+    }
+
+    /// ensure applying an action returns the new state of the affected client
+    #[test]
+    fn apply_and_get() {
+        let mut db = Database::new();
+        let snapshot = db
+            .apply_and_get(AccountAction::Deposit(Deposit {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(1),
+                amount: Amount(5),
+                memo: None,
+            }))
+            .unwrap();
+        assert_eq!(
+            snapshot,
+            ClientSnapshot {
+                id: ClientId(1),
+                available: Balance(5),
+                held: Balance(0),
+                total: Balance(5),
+                locked: false,
+            }
+        );
+        // the dispute names another client, but affects the client of the deposit
+        let snapshot = db
+            .apply_and_get(AccountAction::Dispute(Dispute {
+                client_id: ClientId(2),
+                disputed_transaction: TransactionId(1),
+                dispute_id: None,
+                reason: None,
+            }))
+            .unwrap();
+        assert_eq!(snapshot.id, ClientId(1));
+        assert!(snapshot.held == Balance(5));
+        assert!(db
+            .apply_and_get(AccountAction::Withdrawal(Withdrawal {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(2),
+                amount: Amount(1),
+                memo: None,
+            }))
+            .is_err());
+    }
+
+    /// ensure reason codes are stored with the dispute, replaced by the chargeback, and counted
+    #[test]
+    fn dispute_reasons() {
+        let mut db = Database::new();
+        for tx in 1..=3 {
+            assert!(db
+                .perform_action(AccountAction::Deposit(Deposit {
+                    client_id: ClientId(1),
+                    transaction_id: TransactionId(tx),
+                    amount: Amount(1),
+                    memo: None,
+                }))
+                .is_ok());
+            assert!(db
+                .perform_action(AccountAction::Dispute(Dispute {
+                    client_id: ClientId(1),
+                    disputed_transaction: TransactionId(tx),
+                    dispute_id: None,
+                    reason: Some(ReasonCode::new("10.4")),
+                }))
+                .is_ok());
+        }
+        assert_eq!(
+            db.deposit_info(TransactionId(1)).unwrap().reason(),
+            Some(&ReasonCode::new("10.4"))
+        );
+        assert!(db
+            .perform_action(AccountAction::Resolve(Resolve {
+                client_id: ClientId(1),
+                disputed_transaction: Some(TransactionId(2)),
+                dispute_id: None,
+            }))
+            .is_ok());
+        assert!(db
+            .deposit_info(TransactionId(2))
+            .unwrap()
+            .reason()
+            .is_none());
+        assert!(db
+            .perform_action(AccountAction::Chargeback(Chargeback {
+                client_id: ClientId(1),
+                disputed_transaction: Some(TransactionId(3)),
+                dispute_id: None,
+                reason: Some(ReasonCode::new("13.1")),
+                basis_points: None,
+            }))
+            .is_ok());
+        assert_eq!(
+            db.dispute_reasons(),
+            BTreeMap::from([(&ReasonCode::new("10.4"), 1), (&ReasonCode::new("13.1"), 1)])
+        );
+        // the balances are not affected by the reason
+        assert!(db.client_mut(ClientId(1)).held() == Balance(1));
+    }
+
+    /// ensure every deposit is listed with its current state after a mixed workload
+    #[test]
+    fn deposits() {
+        let mut db = Database::new();
+        for (client, tx) in [(1, 1), (2, 2), (1, 3), (2, 4)] {
+            assert!(db
+                .perform_action(AccountAction::Deposit(Deposit {
+                    client_id: ClientId(client),
+                    transaction_id: TransactionId(tx as TransactionIdRepr),
+                    amount: Amount(tx),
+                    memo: None,
+                }))
+                .is_ok());
+        }
+        assert!(db
+            .perform_action(AccountAction::Withdrawal(Withdrawal {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(5),
+                amount: Amount(1),
+                memo: None,
+            }))
+            .is_ok());
+        for tx in [2, 3] {
+            assert!(db
+                .perform_action(AccountAction::Dispute(Dispute {
+                    client_id: ClientId(1),
+                    disputed_transaction: TransactionId(tx),
+                    dispute_id: None,
+                    reason: None,
+                }))
+                .is_ok());
+        }
+        assert!(db
+            .perform_action(AccountAction::Chargeback(Chargeback {
+                client_id: ClientId(2),
+                disputed_transaction: Some(TransactionId(2)),
+                dispute_id: None,
+                reason: None,
+                basis_points: None,
+            }))
+            .is_ok());
+        let record = |tx, client, state| DepositRecord {
+            transaction_id: TransactionId(tx as TransactionIdRepr),
+            client_id: ClientId(client),
+            amount: Amount(tx),
+            state,
+        };
+        assert_eq!(
+            db.deposits().collect::<Vec<_>>(),
+            [
+                record(1, 1, DepositState::Normal),
+                record(2, 2, DepositState::ChargedBack),
+                record(3, 1, DepositState::Disputed),
+                record(4, 2, DepositState::Normal),
+            ]
+        );
+    }
+
+    /// ensure previewing an action reports the new available funds or the error, without changing the database
+    #[test]
+    fn available_after() {
+        let mut db = Database::new();
+        assert!(db.perform_action(deposit(1, 1, 5)).is_ok());
+        assert!(db
+            .available_after(&withdrawal(1, 2, 2))
+            .is_ok_and(|available| available == Balance(3)));
+        assert!(matches!(
+            db.available_after(&withdrawal(1, 2, 6)),
+            Err(Error::InsufficientFunds {
+                requested: Amount(6),
+                available: Balance(5)
+            })
+        ));
+        assert!(db
+            .available_after(&dispute(1, 1))
+            .is_ok_and(|available| available == Balance(0)));
+        // nothing changed, and the preview matches the real action
+        assert!(db.client_mut(ClientId(1)).available() == Balance(5));
+        assert!(db
+            .deposits()
+            .all(|deposit| deposit.state == DepositState::Normal));
+        let expected = db.available_after(&withdrawal(1, 2, 2)).unwrap();
+        assert!(db.apply_and_get(withdrawal(1, 2, 2)).unwrap().available == expected);
+    }
+
+    /// ensure the preview of every kind of action agrees with performing it, also for closed and locked accounts
+    #[test]
+    fn available_after_matches_perform_action() {
+        // client 1 is open with a disputed deposit and a withdrawal, client 2 is closed, client 3 is locked
+        let setup = || {
+            let mut db = Database::new();
+            for action in [
+                deposit(1, 1, 10),
+                deposit(1, 2, 5),
+                dispute(1, 2),
+                withdrawal(1, 3, 2),
+                deposit(2, 4, 3),
+                close(2),
+                deposit(3, 5, 4),
+                deposit(3, 6, 1),
+                withdrawal(3, 7, 1),
+                dispute(3, 5),
+                chargeback(3, 5),
+                dispute(3, 6),
+            ] {
+                db.perform_action(action).unwrap();
+            }
+            db
+        };
+        let actions = [
+            deposit(1, 10, 1),
+            deposit(2, 11, 1),
+            deposit(3, 12, 1),
+            withdrawal(1, 13, 1),
+            withdrawal(1, 14, 100),
+            withdrawal(2, 15, 1),
+            withdrawal(3, 16, 1),
+            dispute(1, 1),
+            dispute(1, 2),
+            dispute(1, 3),
+            dispute(2, 4),
+            dispute(3, 5),
+            resolve(1, 1),
+            resolve(1, 2),
+            resolve(3, 6),
+            cancel(1, 2),
+            cancel(3, 6),
+            chargeback(1, 1),
+            chargeback(1, 2),
+            chargeback(3, 5),
+            chargeback(3, 6),
+            refund(1, 3),
+            refund(1, 1),
+            refund(3, 7),
+            close(1),
+            close(2),
+            close(3),
+            close(4),
+            adjustment(1, 17, SignedAmount::debit(Amount(20))),
+            adjustment(2, 18, SignedAmount::credit(Amount(1))),
+            adjustment(3, 19, SignedAmount::debit(Amount(1))),
+            AccountAction::NoOp(NoOp::new("heartbeat")),
+        ];
+        for action in actions {
+            let preview = setup().available_after(&action);
+            let performed = setup().apply_and_get(action.clone());
+            match (preview, performed) {
+                (Ok(available), Ok(client)) => {
+                    assert!(available == client.available, "{action:?}")
+                }
+                (Err(preview), Err(performed)) => {
+                    assert_eq!(preview.to_string(), performed.to_string(), "{action:?}")
+                }
+                (preview, performed) => {
+                    panic!("{action:?} previewed as {preview:?} but performed as {performed:?}")
+                }
+            }
+        }
+    }
+
+    /// ensure disputing an already disputed deposit is a no-op by default and an error in strict mode
+    #[test]
+    fn strict_disputes() {
+        for strict in [false, true] {
+            let mut db = Database::with_config(Config {
+                strict_disputes: strict,
+                ..Config::default()
+            });
+            assert!(db
+                .perform_action(AccountAction::Deposit(Deposit {
+                    client_id: ClientId(1),
+                    transaction_id: TransactionId(1),
+                    amount: Amount(5),
+                    memo: None,
+                }))
+                .is_ok());
+            assert!(db.perform_action(dispute(1, 1)).is_ok());
+            let result = db.perform_action(dispute(1, 1));
+            if strict {
+                assert!(matches!(
+                    result,
+                    Err(Error::AlreadyDisputed(TransactionId(1)))
+                ));
+            } else {
+                assert!(result.is_ok());
+            }
+            // the funds are only held once either way
+            assert!(db.client_mut(ClientId(1)).held() == Balance(5));
+            assert_eq!(
+                db.deposit_state(TransactionId(1)),
+                Some(DepositState::Disputed)
+            );
+        }
+    }
+
+    /// ensure a client disputed below zero is listed as negative and a positive client is not
+    #[test]
+    fn negative_available_clients() {
+        let mut db = Database::new();
+        for client in [1, 2] {
+            assert!(db
+                .perform_action(AccountAction::Deposit(Deposit {
+                    client_id: ClientId(client),
+                    transaction_id: TransactionId(client.into()),
+                    amount: Amount(5),
+                    memo: None,
+                }))
+                .is_ok());
+        }
+        assert!(db
+            .perform_action(AccountAction::Withdrawal(Withdrawal {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(3),
+                amount: Amount(3),
+                memo: None,
+            }))
+            .is_ok());
+        assert_eq!(db.negative_available_clients().count(), 0);
+        assert!(db
+            .perform_action(AccountAction::Dispute(Dispute {
+                client_id: ClientId(1),
+                disputed_transaction: TransactionId(1),
+                dispute_id: None,
+                reason: None,
+            }))
+            .is_ok());
+        let negative: Vec<_> = db
+            .negative_available_clients()
+            .map(|client| client.id())
+            .collect();
+        assert_eq!(negative, [ClientId(1)]);
+        assert!(db.client_mut(ClientId(1)).available() == Balance(-3));
+    }
+
+    /// ensure a no-op action succeeds without any effect
+    #[test]
+    fn noop() {
+        let mut db = Database::new();
+        assert!(db
+            .perform_action(AccountAction::NoOp(NoOp::new("heartbeat")))
+            .is_ok());
+        assert_eq!(db.clients().count(), 0);
+        assert_eq!(db.deposits().count(), 0);
+        assert!(matches!(
+            db.apply_and_get(AccountAction::NoOp(NoOp::new("heartbeat"))),
+            Err(Error::ActionNotAllowed(ActionKind::NoOp))
+        ));
+    }
+
+    /// ensure a resolve with too few held funds returns the error by default, and panics in debug builds if configured
+    #[test]
+    fn held_invariant() {
+        let setup = |config| {
+            let mut db = Database::with_config(config);
+            assert!(db.perform_action(deposit(1, 1, 5)).is_ok());
+            assert!(db.perform_action(dispute(1, 1)).is_ok());
+            // break the invariant behind the database's back
+            db.client_mut(ClientId(1)).held = Balance(1);
+            db
+        };
+
+        let mut db = setup(Config::default());
+        assert!(matches!(
+            db.perform_action(resolve(1, 1)),
+            Err(Error::InsufficientHeldFunds)
+        ));
+        assert!(matches!(
+            db.perform_action(chargeback(1, 1)),
+            Err(Error::InsufficientHeldFunds)
+        ));
+
+        let config = Config {
+            panic_on_invariant_violation: true,
+            ..Config::default()
+        };
+        let mut db = setup(config.clone());
+        let panicked = std::panic::catch_unwind(move || db.perform_action(resolve(1, 1))).is_err();
+        assert_eq!(panicked, cfg!(debug_assertions));
+        let mut db = setup(config);
+        let panicked =
+            std::panic::catch_unwind(move || db.perform_action(chargeback(1, 1))).is_err();
+        assert_eq!(panicked, cfg!(debug_assertions));
+    }
+
+    /// ensure every performed action is reported as a tracing event with its ids and outcome
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn tracing_events() {
+        use std::sync::{Arc, Mutex};
+        use tracing::{
+            field::{Field, Visit},
+            span, Event, Metadata, Subscriber,
+        };
+
+        /// collects the fields of every event as `name=value` strings
+        #[derive(Clone, Default)]
+        struct Capture(Arc<Mutex<Vec<String>>>);
+
+        struct Fields(Vec<String>);
+
+        impl Visit for Fields {
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                self.0.push(format!("{}={:?}", field.name(), value));
+            }
+        }
+
+        impl Subscriber for Capture {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+                span::Id::from_u64(1)
+            }
+            fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+            fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+            fn event(&self, event: &Event<'_>) {
+                let mut fields = Fields(Vec::new());
+                event.record(&mut fields);
+                self.0.lock().unwrap().push(fields.0.join(" "));
+            }
+            fn enter(&self, _: &span::Id) {}
+            fn exit(&self, _: &span::Id) {}
+        }
+
+        let capture = Capture::default();
+        tracing::subscriber::with_default(capture.clone(), || {
+            let mut db = Database::new();
+            let _ = db.perform_action(AccountAction::Deposit(Deposit {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(1),
+                amount: Amount(1_5000),
+                memo: None,
+            }));
+            let _ = db.perform_action(AccountAction::Dispute(Dispute {
+                client_id: ClientId(1),
+                disputed_transaction: TransactionId(2),
+                dispute_id: None,
+                reason: None,
+            }));
+        });
+        let events = capture.0.lock().unwrap();
+        assert_eq!(
+            *events,
+            [
+                "message=performed action kind=Deposit client=1 tx=1 amount=1.5000 ok=true",
+                "message=performed action kind=Dispute client=1 tx=2 ok=false error=transaction id not found",
+            ]
+        );
+    }
+
+    /// ensure the state hash only depends on the resulting state
+    #[test]
+    fn state_hash() {
+        let hash = |actions: Vec<AccountAction>| {
+            let mut db = Database::new();
+            for action in actions {
+                assert!(db.perform_action(action).is_ok());
+            }
+            db.state_hash()
+        };
+        let expected = hash(vec![deposit(1, 1, 5), deposit(2, 2, 3), dispute(2, 2)]);
+        // deposits to different clients commute
+        assert_eq!(
+            hash(vec![deposit(2, 2, 3), deposit(1, 1, 5), dispute(2, 2)]),
+            expected
+        );
+        assert_eq!(
+            hash(vec![deposit(2, 2, 3), dispute(2, 2), deposit(1, 1, 5)]),
+            expected
+        );
+        // a different amount, a missing dispute, or a different transaction id all change the hash
+        assert_ne!(
+            hash(vec![deposit(1, 1, 4), deposit(2, 2, 3), dispute(2, 2)]),
+            expected
+        );
+        assert_ne!(hash(vec![deposit(1, 1, 5), deposit(2, 2, 3)]), expected);
+        assert_ne!(
+            hash(vec![deposit(1, 3, 5), deposit(2, 2, 3), dispute(2, 2)]),
+            expected
+        );
+        assert_ne!(Database::new().state_hash(), expected);
+    }
+
+    /// ensure a refund restores the funds of a withdrawal, once
+    #[test]
+    fn refund_once() {
+        let mut db = Database::new();
+        assert!(db
+            .perform_action(AccountAction::Deposit(Deposit {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(1),
+                amount: Amount(5),
+                memo: None,
+            }))
+            .is_ok());
+        for (tx, amount) in [(2, 3), (3, 10)] {
+            let _ = db.perform_action(AccountAction::Withdrawal(Withdrawal {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(tx),
+                amount: Amount(amount),
+                memo: Some("payout".to_string()),
+            }));
+        }
+        assert!(db.client_mut(ClientId(1)).available() == Balance(2));
+        assert!(db
+            .withdrawal_info(ClientId(1), TransactionId(2))
+            .is_some_and(
+                |withdrawal| !withdrawal.is_refunded() && withdrawal.memo() == Some("payout")
+            ));
+        assert!(matches!(db.available_after(&refund(1, 2)), Ok(Balance(5))));
+
+        assert!(db.perform_action(refund(1, 2)).is_ok());
+        assert!(db.client_mut(ClientId(1)).available() == Balance(5));
+        assert!(db
+            .withdrawal_info(ClientId(1), TransactionId(2))
+            .is_some_and(SeenWithdrawal::is_refunded));
+        assert!(matches!(
+            db.perform_action(refund(1, 2)),
+            Err(Error::AlreadyRefunded(TransactionId(2)))
+        ));
+        // failed withdrawals, deposits, and unknown transactions can't be refunded
+        for tx in [3, 1, 4] {
+            assert!(matches!(
+                db.perform_action(refund(1, tx)),
+                Err(Error::TransactionNotFound)
+            ));
+        }
+        assert!(db.client_mut(ClientId(1)).available() == Balance(5));
+    }
+
+    /// ensure clients are listed in the order they first appeared if tracked
+    #[test]
+    fn insertion_order() {
+        let actions = || {
+            [5, 2, 9, 2, 1]
+                .into_iter()
+                .enumerate()
+                .map(|(tx, client)| deposit(client, tx as TransactionIdRepr, 1))
+        };
+        let mut db = Database::with_config(Config {
+            track_insertion_order: true,
+            ..Config::default()
+        });
+        for action in actions() {
+            assert!(db.perform_action(action).is_ok());
+        }
+        let ids = |clients: Vec<ClientWithId>| -> Vec<ClientIdRepr> {
+            clients.into_iter().map(|client| client.id().0).collect()
+        };
+        assert_eq!(ids(db.clients_in_insertion_order().collect()), [5, 2, 9, 1]);
+        assert_eq!(ids(db.clients().collect()), [1, 2, 5, 9]);
+        assert!(db
+            .clients_in_insertion_order()
+            .all(|client| client.available().0 == if client.id().0 == 2 { 2 } else { 1 }));
+
+        let mut untracked = Database::new();
+        for action in actions() {
+            assert!(untracked.perform_action(action).is_ok());
+        }
+        assert_eq!(untracked.clients_in_insertion_order().count(), 0);
+    }
+
+    /// ensure deposits and withdrawals over the configured maximum are rejected, and ones at the maximum pass
+    #[test]
+    fn max_transaction_amount() {
+        let mut db = Database::with_config(Config {
+            max_transaction_amount: Some(Amount(100)),
+            ..Config::default()
+        });
+        assert!(matches!(
+            db.available_after(&deposit(1, 1, 101)),
+            Err(Error::AmountTooLarge)
+        ));
+        assert!(matches!(
+            db.perform_action(deposit(1, 1, 101)),
+            Err(Error::AmountTooLarge)
+        ));
+        // the rejected deposit didn't use up its transaction id
+        assert!(db.perform_action(deposit(1, 1, 100)).is_ok());
+        assert!(db.perform_action(deposit(1, 2, 100)).is_ok());
+        assert!(matches!(
+            db.perform_action(withdrawal(1, 3, 101)),
+            Err(Error::AmountTooLarge)
+        ));
+        assert!(db.perform_action(withdrawal(1, 3, 100)).is_ok());
+        assert!(db.client_mut(ClientId(1)).available() == Balance(100));
+    }
+
+    /// ensure a second chargeback on an account locked by the first still removes its held funds
+    #[test]
+    fn double_chargeback() {
+        let mut db = Database::new();
+        for tx in [1, 2] {
+            assert!(db
+                .perform_action(AccountAction::Deposit(Deposit {
+                    client_id: ClientId(1),
+                    transaction_id: TransactionId(tx as TransactionIdRepr),
+                    amount: Amount(tx),
+                    memo: None,
+                }))
+                .is_ok());
+            assert!(db
+                .perform_action(AccountAction::Dispute(Dispute {
+                    client_id: ClientId(1),
+                    disputed_transaction: TransactionId(tx as TransactionIdRepr),
+                    dispute_id: None,
+                    reason: None,
+                }))
+                .is_ok());
+        }
+        assert!(db.client_mut(ClientId(1)).held() == Balance(3));
+        for (tx, held) in [(1, 2), (2, 0)] {
+            assert!(db
+                .perform_action(AccountAction::Chargeback(Chargeback {
+                    client_id: ClientId(1),
+                    disputed_transaction: Some(TransactionId(tx)),
+                    dispute_id: None,
+                    reason: None,
+                    basis_points: None,
+                }))
+                .is_ok());
+            let client = db.client_mut(ClientId(1));
+            assert!(client.is_locked());
+            assert!(client.held() == Balance(held));
+        }
+        let client = db.client_mut(ClientId(1));
+        assert!(client.available() == Balance(0));
+        assert_eq!(client.chargebacks(), 2);
+        assert_eq!(db.locked_count(), 1);
+        assert_eq!(
+            db.deposit_state(TransactionId(2)),
+            Some(DepositState::ChargedBack)
+        );
+    }
+
+    /// ensure only accounts without held funds or open disputes can be closed, and closed accounts accept no more actions
+    #[test]
+    fn close_account() {
+        let mut db = Database::new();
+        assert!(matches!(
+            db.perform_action(close(1)),
+            Err(Error::ClientNotFound)
+        ));
+        assert!(db.perform_action(deposit(1, 1, 5)).is_ok());
+        assert!(db.perform_action(deposit(1, 2, 0)).is_ok());
+        assert!(db.perform_action(dispute(1, 1)).is_ok());
+        assert!(matches!(
+            db.available_after(&close(1)),
+            Err(Error::OpenDisputes)
+        ));
+        assert!(matches!(
+            db.perform_action(close(1)),
+            Err(Error::OpenDisputes)
+        ));
+        assert!(db.perform_action(resolve(1, 1)).is_ok());
+        // a dispute of zero holds nothing, but is still open
+        assert!(db.perform_action(dispute(1, 2)).is_ok());
+        assert!(matches!(
+            db.perform_action(close(1)),
+            Err(Error::OpenDisputes)
+        ));
+        assert!(db.perform_action(resolve(1, 2)).is_ok());
+
+        assert!(matches!(db.available_after(&close(1)), Ok(Balance(5))));
+        assert!(db.perform_action(close(1)).is_ok());
+        assert!(db.client_mut(ClientId(1)).is_closed());
+        assert!(matches!(
+            db.perform_action(close(1)),
+            Err(Error::AccountClosed)
+        ));
+        assert!(matches!(
+            db.perform_action(deposit(1, 3, 1)),
+            Err(Error::AccountClosed)
+        ));
+        assert!(matches!(
+            db.perform_action(AccountAction::Withdrawal(Withdrawal {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(4),
+                amount: Amount(1),
+                memo: None,
+            })),
+            Err(Error::AccountClosed)
+        ));
+        assert!(matches!(
+            db.perform_action(dispute(1, 1)),
+            Err(Error::AccountClosed)
+        ));
+        let client = db.client_mut(ClientId(1));
+        assert!(client.available() == Balance(5));
+        assert!(!client.is_locked());
+    }
+
+    /// ensure a panic while performing an action is returned as an error, and the database can still be used
+    #[test]
+    fn try_perform_action() {
+        let mut db = Database::with_config(Config {
+            panic_on_invariant_violation: true,
+            ..Config::default()
+        });
+        assert!(db
+            .try_perform_action(AccountAction::Deposit(Deposit {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(1),
+                amount: Amount(5),
+                memo: None,
+            }))
+            .is_ok());
+        assert!(db
+            .try_perform_action(AccountAction::Dispute(Dispute {
+                client_id: ClientId(1),
+                disputed_transaction: TransactionId(1),
+                dispute_id: None,
+                reason: None,
+            }))
+            .is_ok());
+        db.client_mut(ClientId(1)).held = Balance(1);
+        let result = db.try_perform_action(AccountAction::Resolve(Resolve {
+            client_id: ClientId(1),
+            disputed_transaction: Some(TransactionId(1)),
+            dispute_id: None,
+        }));
+        // only debug builds panic on the violated invariant
+        if cfg!(debug_assertions) {
+            assert!(
+                matches!(result, Err(Error::Panicked(message)) if message.contains("held funds"))
+            );
+        } else {
+            assert!(matches!(result, Err(Error::InsufficientHeldFunds)));
+        }
+        assert_eq!(db.clients().count(), 1);
+        assert_eq!(
+            db.deposit_state(TransactionId(1)),
+            Some(DepositState::Disputed)
+        );
+        assert!(db
+            .try_perform_action(AccountAction::Deposit(Deposit {
+                client_id: ClientId(2),
+                transaction_id: TransactionId(2),
+                amount: Amount(1),
+                memo: None,
+            }))
+            .is_ok());
+        assert_eq!(db.clients().count(), 2);
+    }
+
+    /// ensure an action that panics halfway has no effect, like the chargeback that locks the account before it panics
+    #[test]
+    fn try_perform_action_rollback() {
+        let mut db = Database::with_config(Config {
+            panic_on_invariant_violation: true,
+            ..Config::default()
+        });
+        db.perform_action(AccountAction::Deposit(Deposit {
+            client_id: ClientId(1),
+            transaction_id: TransactionId(1),
+            amount: Amount(5),
+            memo: None,
+        }))
+        .unwrap();
+        db.perform_action(AccountAction::Dispute(Dispute {
+            client_id: ClientId(1),
+            disputed_transaction: TransactionId(1),
+            dispute_id: Some(DisputeId(7)),
+            reason: None,
+        }))
+        .unwrap();
+        db.client_mut(ClientId(1)).held = Balance(1);
+        let hash = db.state_hash();
+        let result = db.try_perform_action(AccountAction::Chargeback(Chargeback {
+            client_id: ClientId(1),
+            disputed_transaction: None,
+            dispute_id: Some(DisputeId(7)),
+            reason: Some(ReasonCode::new("fraud")),
+            basis_points: None,
+        }));
+        // only debug builds panic on the violated invariant, release builds lock the account and fail
+        if !cfg!(debug_assertions) {
+            assert!(matches!(result, Err(Error::InsufficientHeldFunds)));
+            return;
+        }
+        assert!(matches!(result, Err(Error::Panicked(_))));
+        assert_eq!(db.state_hash(), hash);
+        assert_eq!(db.locked_count(), 0);
+        assert_eq!(db.lock_reason(ClientId(1)), None);
+        let client = db.client_mut(ClientId(1));
+        assert!(!client.is_locked());
+        assert!(client.held() == Balance(1));
+        assert_eq!(client.chargebacks(), 0);
+        let deposit = db.deposit_info(TransactionId(1)).unwrap();
+        assert_eq!(deposit.state(), DepositState::Disputed);
+        assert_eq!(deposit.reason(), None);
+    }
+
+    /// ensure resolves and chargebacks can refer to a dispute by its id instead of by the disputed deposit
+    #[test]
+    fn dispute_id() {
+        let mut db = Database::new();
+        for (tx, amount) in [(1, 10), (2, 20)] {
+            assert!(db.perform_action(deposit(1, tx, amount)).is_ok());
+        }
+        let dispute = |tx, id| {
+            AccountAction::Dispute(Dispute {
+                client_id: ClientId(1),
+                disputed_transaction: TransactionId(tx),
+                dispute_id: Some(DisputeId(id)),
+                reason: None,
+            })
+        };
+        let resolve = |tx: Option<TransactionIdRepr>, id| {
+            AccountAction::Resolve(Resolve {
+                client_id: ClientId(1),
+                disputed_transaction: tx.map(TransactionId),
+                dispute_id: Some(DisputeId(id)),
+            })
+        };
+        assert!(db.perform_action(dispute(1, 7)).is_ok());
+        assert!(db.perform_action(dispute(2, 8)).is_ok());
+        // a dispute id refers to a single deposit
+        assert!(matches!(
+            db.perform_action(dispute(2, 7)),
+            Err(Error::DisputeIdInUse(DisputeId(7)))
+        ));
+        assert!(matches!(
+            db.perform_action(resolve(None, 9)),
+            Err(Error::DisputeNotFound)
+        ));
+        // the transaction id, if given, must be that of the disputed deposit
+        assert!(matches!(
+            db.perform_action(resolve(Some(1), 8)),
+            Err(Error::DisputeIdMismatch {
+                dispute: DisputeId(8),
+                transaction: TransactionId(1)
+            })
+        ));
+        assert_eq!(db.available_after(&resolve(None, 8)).unwrap(), Balance(20));
+
+        assert!(db.perform_action(resolve(None, 8)).is_ok());
+        assert_eq!(
+            db.deposit_state(TransactionId(1)),
+            Some(DepositState::Disputed)
+        );
+        assert_eq!(
+            db.deposit_state(TransactionId(2)),
+            Some(DepositState::Normal)
+        );
+        assert!(db.client_mut(ClientId(1)).held() == Balance(10));
+
+        let snapshot = db
+            .apply_and_get(AccountAction::Chargeback(Chargeback {
+                client_id: ClientId(1),
+                disputed_transaction: Some(TransactionId(1)),
+                dispute_id: Some(DisputeId(7)),
+                reason: None,
+                basis_points: None,
+            }))
+            .unwrap();
+        assert!(snapshot.locked);
+        assert_eq!(
+            db.deposit_state(TransactionId(1)),
+            Some(DepositState::ChargedBack)
+        );
+        assert!(db.client_mut(ClientId(1)).total() == Balance(20));
+    }
+
+    /// ensure the estimated memory grows with every client, transaction, and dispute
+    #[test]
+    fn estimated_memory_bytes() {
+        let mut db = Database::new();
+        let mut estimate = db.estimated_memory_bytes();
+        assert_eq!(estimate, 0);
+        for tx in 1..=20 {
+            assert!(db
+                .perform_action(AccountAction::Deposit(Deposit {
+                    client_id: ClientId(tx as ClientIdRepr % 5),
+                    transaction_id: TransactionId(tx),
+                    amount: Amount(10),
+                    memo: (tx % 2 == 0).then(|| "invoice".to_string()),
+                }))
+                .is_ok());
+            let grown = db.estimated_memory_bytes();
+            assert!(grown > estimate);
+            estimate = grown;
+        }
+        assert!(db
+            .perform_action(AccountAction::Dispute(Dispute {
+                client_id: ClientId(1),
+                disputed_transaction: TransactionId(1),
+                dispute_id: Some(DisputeId(1)),
+                reason: None,
+            }))
+            .is_ok());
+        assert!(db.estimated_memory_bytes() > estimate);
+    }
+
+    /// ensure the estimated memory includes the recent deposits kept for the duplicate deposit window
+    #[test]
+    fn estimated_memory_bytes_recent_deposits() {
+        let mut db = Database::new();
+        let mut windowed = Database::with_config(Config {
+            duplicate_deposit_window: Some(10),
+            ..Config::default()
+        });
+        for tx in 1..=5 {
+            let deposit = AccountAction::Deposit(Deposit {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(tx),
+                amount: Amount(10),
+                memo: None,
+            });
+            assert!(db.perform_action_ref(&deposit).is_ok());
+            assert!(windowed.perform_action(deposit).is_ok());
+        }
+        assert!(windowed.estimated_memory_bytes() > db.estimated_memory_bytes());
+    }
+
+    /// ensure the estimated memory includes the memos of the quarantined actions
+    #[test]
+    fn estimated_memory_bytes_quarantine() {
+        let quarantined = |memo: Option<&str>| {
+            let mut db = Database::with_config(Config {
+                quarantine_locked_accounts: true,
+                ..Config::default()
+            });
+            assert!(db.perform_action(deposit(1, 1, 10)).is_ok());
+            assert!(db.freeze(ClientId(1)));
+            let deposit = AccountAction::Deposit(Deposit {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(2),
+                amount: Amount(10),
+                memo: memo.map(str::to_string),
+            });
+            assert!(matches!(
+                db.perform_action(deposit),
+                Err(Error::Quarantined)
+            ));
+            db.estimated_memory_bytes()
+        };
+        let memo = "x".repeat(1_000);
+        assert!(quarantined(Some(&memo)) >= quarantined(None) + memo.len());
+    }
+
+    /// ensure a failed action reports the index it was applied at, and the underlying error as its source
+    #[test]
+    fn apply_action_at() {
+        let mut db = Database::new();
+        let error = db.apply_action_at(41, &withdrawal(1, 1, 1)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "failed to perform action 41: insufficient funds, requested 0.0001 but only 0.0000 is available"
+        );
+        assert!(matches!(
+            &error,
+            Error::Action { index: 41, source } if matches!(**source, Error::InsufficientFunds { .. })
+        ));
+        assert!(std::error::Error::source(&error).is_some());
+        db.client_mut(ClientId(1)).deposit(Amount(1)).unwrap();
+        assert!(db.apply_action_at(42, &withdrawal(1, 2, 1)).is_ok());
+    }
+
+    /// ensure resolving releases exactly the disputed amount, so repeated disputes of an odd amount leave no dust
+    #[test]
+    fn resolve_no_dust() {
+        let mut db = Database::new();
+        assert!(db
+            .perform_action(AccountAction::Deposit(Deposit {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(1),
+                amount: Amount(1_0001),
+                memo: None,
+            }))
+            .is_ok());
+        for _ in 0..3 {
+            assert!(db
+                .perform_action(AccountAction::Dispute(Dispute {
+                    client_id: ClientId(1),
+                    disputed_transaction: TransactionId(1),
+                    dispute_id: None,
+                    reason: None,
+                }))
+                .is_ok());
+            assert!(db.client_mut(ClientId(1)).held() == Balance(1_0001));
+            assert!(db
+                .perform_action(AccountAction::Resolve(Resolve {
+                    client_id: ClientId(1),
+                    disputed_transaction: Some(TransactionId(1)),
+                    dispute_id: None,
+                }))
+                .is_ok());
+            assert!(db.client_mut(ClientId(1)).held() == Balance(0));
+            assert!(db.client_mut(ClientId(1)).available() == Balance(1_0001));
+        }
+    }
+
+    /// ensure a chargeback of a withdrawn deposit leaves a negative total, which is flagged if configured
+    #[test]
+    fn negative_total() {
+        let actions = || {
+            [
+                deposit(1, 1, 10),
+                withdrawal(1, 2, 8),
+                dispute(1, 1),
+                chargeback(1, 1),
+            ]
+        };
+        let mut db = Database::new();
+        for action in actions() {
+            assert!(db.perform_action(action).is_ok());
+        }
+        assert!(db.client_mut(ClientId(1)).total() == Balance(-8));
+        let negative = db
+            .negative_total_clients()
+            .map(|client| client.id())
+            .collect::<Vec<_>>();
+        assert_eq!(negative, [ClientId(1)]);
+        // accepted by default
+        assert!(db.validate().is_ok());
+
+        let mut db = Database::with_config(Config {
+            forbid_negative_total: true,
+            ..Config::default()
+        });
+        let mut actions = actions().into_iter();
+        for action in actions.by_ref().take(3) {
+            assert!(db.perform_action(action).is_ok());
+            assert!(db.validate().is_ok());
+        }
+        assert!(db.perform_action(actions.next().unwrap()).is_ok());
+        assert!(matches!(
+            db.validate(),
+            Err(Error::NegativeTotal(ClientId(1)))
+        ));
+    }
+
+    /// ensure adjustments credit and debit the available funds, even of a locked account, and can't be disputed
+    #[test]
+    fn adjustment_balances() {
+        let mut db = Database::new();
+        let credit = AccountAction::Adjustment(Adjustment {
+            client_id: ClientId(1),
+            transaction_id: TransactionId(1),
+            signed_amount: SignedAmount::credit(Amount(5)),
+            memo: Some("correction".to_string()),
+        });
+        assert!(db.perform_action(credit).is_ok());
+        assert!(db.client_mut(ClientId(1)).available() == Balance(5));
+        assert!(db
+            .adjustment_info(ClientId(1), TransactionId(1))
+            .is_some_and(|adjustment| adjustment.memo() == Some("correction")));
+
+        assert!(db.freeze(ClientId(1)));
+        assert_eq!(db.locked_count(), 1);
+        let debit = adjustment(1, 2, SignedAmount::debit(Amount(7)));
+        assert!(matches!(db.available_after(&debit), Ok(Balance(-2))));
+        assert!(db.perform_action(debit).is_ok());
+        assert!(db.client_mut(ClientId(1)).available() == Balance(-2));
+        assert!(db.client_mut(ClientId(1)).held() == Balance(0));
+
+        // the transaction id is used, but the adjustment is neither a deposit nor a withdrawal
+        assert!(matches!(
+            db.perform_action(adjustment(1, 2, SignedAmount::credit(Amount(1)))),
+            Err(Error::InvalidTransactionId)
+        ));
+        assert!(matches!(
+            db.perform_action(dispute(1, 1)),
+            Err(Error::TransactionNotFound)
+        ));
+        assert!(matches!(
+            db.perform_action(refund(1, 2)),
+            Err(Error::TransactionNotFound)
+        ));
+
+        db.client_mut(ClientId(2)).available = Balance(i128::MIN + 1);
+        assert!(matches!(
+            db.perform_action(adjustment(2, 3, SignedAmount::debit(Amount(2)))),
+            Err(Error::BalanceUpdate {
+                client: ClientId(2),
+                source: crate::ArithmeticError::Underflow
+            })
+        ));
+        assert!(db.adjustment_info(ClientId(2), TransactionId(3)).is_none());
+    }
+
+    /// ensure the seen transaction ids are listed once each and in order, including those of failed withdrawals
+    #[test]
+    fn seen_transaction_ids() {
+        let mut db = Database::new();
+        for tx in [3, 1, 3] {
+            let _ = db.perform_action(AccountAction::Deposit(Deposit {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(tx),
+                amount: Amount(1),
+                memo: None,
+            }));
+        }
+        assert!(db
+            .perform_action(AccountAction::Withdrawal(Withdrawal {
+                client_id: ClientId(2),
+                transaction_id: TransactionId(2),
+                amount: Amount(1),
+                memo: None,
+            }))
+            .is_err());
+        // disputes refer to an id, they don't use one
+        assert!(db
+            .perform_action(AccountAction::Dispute(Dispute {
+                client_id: ClientId(1),
+                disputed_transaction: TransactionId(1),
+                dispute_id: None,
+                reason: None,
+            }))
+            .is_ok());
+        assert_eq!(
+            db.seen_transaction_ids().collect::<Vec<_>>(),
+            [TransactionId(1), TransactionId(2), TransactionId(3)]
+        );
+    }
+
+    /// ensure the callback fires once per applied action, with the deltas of the affected client
+    #[test]
+    fn apply_stream_with() {
+        let actions = vec![
+            Ok(AccountAction::Deposit(Deposit {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(1),
+                amount: Amount(5),
+                memo: None,
+            })),
+            Err(Error::Parse("garbage".to_string())),
+            // insufficient funds
+            Ok(AccountAction::Withdrawal(Withdrawal {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(2),
+                amount: Amount(10),
+                memo: None,
+            })),
+            // names another client, but affects the client of the deposit
+            Ok(AccountAction::Dispute(Dispute {
+                client_id: ClientId(2),
+                disputed_transaction: TransactionId(1),
+                dispute_id: None,
+                reason: None,
+            })),
+            Ok(AccountAction::NoOp(NoOp::new("heartbeat"))),
+            Ok(AccountAction::Chargeback(Chargeback {
+                client_id: ClientId(1),
+                disputed_transaction: Some(TransactionId(1)),
+                dispute_id: None,
+                reason: None,
+                basis_points: None,
+            })),
+            // unknown deposit
+            Ok(AccountAction::Resolve(Resolve {
+                client_id: ClientId(1),
+                disputed_transaction: Some(TransactionId(3)),
+                dispute_id: None,
+            })),
+        ];
+        let mut db = Database::new();
+        let mut events = Vec::new();
+        let failures = db.apply_stream_with(actions, |event| events.push(*event));
+        assert!(matches!(
+            failures.as_slice(),
+            [
+                (1, Error::Parse(_)),
+                (2, Error::InsufficientFunds { .. }),
+                (6, Error::TransactionNotFound)
+            ]
+        ));
+        let deltas = events
+            .iter()
+            .map(|event| {
+                (
+                    event.index,
+                    event.kind,
+                    event.client(),
+                    event.available_delta(),
+                    event.held_delta(),
+                    event.after.locked,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            deltas,
+            [
+                (
+                    0,
+                    ActionKind::Deposit,
+                    ClientId(1),
+                    Balance(5),
+                    Balance(0),
+                    false
+                ),
+                (
+                    3,
+                    ActionKind::Dispute,
+                    ClientId(1),
+                    Balance(-5),
+                    Balance(5),
+                    false
+                ),
+                (
+                    5,
+                    ActionKind::Chargeback,
+                    ClientId(1),
+                    Balance(0),
+                    Balance(-5),
+                    true
+                ),
+            ]
+        );
+        assert!(events[0].before.id == ClientId(1) && events[0].before.total == Balance(0));
+        assert_eq!(events[2].after, db.snapshot(ClientId(1)));
+    }
+
+    /// ensure a partial chargeback charges back its share of the held funds and leaves the rest disputed
+    #[test]
+    fn partial_chargeback() {
+        let setup = |config| {
+            let mut db = Database::with_config(config);
+            for action in [deposit(1, 1, 10_0000), dispute(1, 1)] {
+                assert!(db.perform_action(action).is_ok());
+            }
+            db
+        };
+        let chargeback = |basis_points| {
+            AccountAction::Chargeback(Chargeback {
+                client_id: ClientId(1),
+                disputed_transaction: Some(TransactionId(1)),
+                dispute_id: None,
+                reason: None,
+                basis_points,
+            })
+        };
+
+        let mut db = setup(Config::default());
+        assert!(db.perform_action(chargeback(Some(5_000))).is_ok());
+        let client = db.client_mut(ClientId(1));
+        assert!(client.available() == Balance(0));
+        assert!(client.held() == Balance(5_0000));
+        assert!(client.total() == Balance(5_0000));
+        assert!(client.locked);
+        let deposit = db.deposit_info(TransactionId(1)).unwrap();
+        assert!(deposit.is_disputed());
+        assert_eq!(deposit.charged_back(), Amount(5_0000));
+        // the rest is released by a resolve, and a new dispute only holds what was not charged back
+        assert!(db.perform_action(resolve(1, 1)).is_ok());
+        assert!(db.client_mut(ClientId(1)).available() == Balance(5_0000));
+        assert!(db.perform_action(dispute(1, 1)).is_ok());
+        assert!(db.client_mut(ClientId(1)).held() == Balance(5_0000));
+        // a third of what is left, and a deposit is counted once however often it is charged back
+        assert!(db.perform_action(chargeback(Some(3_333))).is_ok());
+        assert!(db.client_mut(ClientId(1)).held() == Balance(3_3335));
+        assert!(db.perform_action(chargeback(None)).is_ok());
+        assert!(db.client_mut(ClientId(1)).total() == Balance(0));
+        assert_eq!(db.client_mut(ClientId(1)).chargebacks, 1);
+        assert_eq!(
+            db.deposit_info(TransactionId(1)).unwrap().state(),
+            DepositState::ChargedBack
+        );
+
+        // 100% is a full chargeback
+        let mut db = setup(Config::default());
+        assert!(db.perform_action(chargeback(Some(10_000))).is_ok());
+        assert!(db.client_mut(ClientId(1)).total() == Balance(0));
+        assert_eq!(
+            db.deposit_info(TransactionId(1)).unwrap().state(),
+            DepositState::ChargedBack
+        );
+
+        let mut db = setup(Config {
+            resolve_partial_chargeback_remainder: true,
+            ..Config::default()
+        });
+        let partial = chargeback(Some(3_333));
+        assert!(matches!(db.available_after(&partial), Ok(Balance(6_6670))));
+        assert!(db.perform_action(partial).is_ok());
+        let client = db.client_mut(ClientId(1));
+        assert!(client.available() == Balance(6_6670));
+        assert!(client.held() == Balance(0));
+        assert_eq!(
+            db.deposit_info(TransactionId(1)).unwrap().state(),
+            DepositState::ChargedBack
+        );
+    }
+
+    /// ensure deposits and withdrawals for a locked account are quarantined with the reason of the lock, if configured
+    #[test]
+    fn quarantine() {
+        let chargeback = [dispute(1, 1), chargeback(1, 1)];
+
+        let mut db = Database::with_config(Config {
+            quarantine_locked_accounts: true,
+            ..Config::default()
+        });
+        assert!(db.perform_action(deposit(1, 1, 10)).is_ok());
+        assert!(db.perform_action(deposit(1, 2, 10)).is_ok());
+        for action in chargeback.clone() {
+            assert!(db.perform_action(action).is_ok());
+        }
+        assert_eq!(
+            db.lock_reason(ClientId(1)),
+            Some(LockReason::Chargeback(TransactionId(1)))
+        );
+        assert!(matches!(
+            db.available_after(&withdrawal(1, 3, 1)),
+            Err(Error::Quarantined)
+        ));
+        assert!(matches!(
+            db.perform_action(withdrawal(1, 3, 1)),
+            Err(Error::Quarantined)
+        ));
+        assert!(matches!(
+            db.perform_action(deposit(1, 4, 10)),
+            Err(Error::Quarantined)
+        ));
+        // a freeze of an account locked by a chargeback keeps the original reason
+        assert!(db.perform_action(deposit(2, 5, 10)).is_ok());
+        assert!(db.freeze(ClientId(1)));
+        assert!(db.freeze(ClientId(2)));
+        assert!(matches!(
+            db.perform_action(withdrawal(2, 6, 1)),
+            Err(Error::Quarantined)
+        ));
+        let quarantined = db
+            .quarantined()
+            .iter()
+            .map(|quarantined| {
+                (
+                    quarantined.action.client_id(),
+                    quarantined.action.transaction_id(),
+                    quarantined.reason,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            quarantined,
+            [
+                (
+                    Some(ClientId(1)),
+                    Some(TransactionId(3)),
+                    LockReason::Chargeback(TransactionId(1))
+                ),
+                (
+                    Some(ClientId(1)),
+                    Some(TransactionId(4)),
+                    LockReason::Chargeback(TransactionId(1))
+                ),
+                (
+                    Some(ClientId(2)),
+                    Some(TransactionId(6)),
+                    LockReason::Frozen
+                ),
+            ]
+        );
+        // quarantined actions have no effect, not even using up their transaction id
+        assert!(db.client_mut(ClientId(1)).available() == Balance(10));
+        assert!(db.seen_transaction_ids().all(|id| id != TransactionId(3)));
+
+        // by default the deposit is credited and the withdrawal rejected
+        let mut db = Database::new();
+        assert!(db.perform_action(deposit(1, 1, 10)).is_ok());
+        for action in chargeback {
+            assert!(db.perform_action(action).is_ok());
+        }
+        assert!(matches!(
+            db.perform_action(withdrawal(1, 2, 1)),
+            Err(Error::AccountLocked)
+        ));
+        assert!(db.perform_action(deposit(1, 3, 10)).is_ok());
+        assert!(db.quarantined().is_empty());
+    }
+
+    /// ensure a cancelled dispute returns the held funds like a resolve, but is counted as a cancellation
+    #[test]
+    fn cancel_dispute() {
+        let mut db = Database::new();
+        assert!(db.perform_action(deposit(1, 1, 10)).is_ok());
+        assert!(db.perform_action(dispute(1, 1)).is_ok());
+        let cancel = cancel(1, 1);
+        assert!(matches!(db.available_after(&cancel), Ok(Balance(10))));
+        assert!(db.perform_action(cancel.clone()).is_ok());
+        let client = db.client_mut(ClientId(1));
+        assert!(client.available() == Balance(10));
+        assert!(client.held() == Balance(0));
+        let deposit = db.deposit_info(TransactionId(1)).unwrap();
+        assert_eq!(deposit.state(), DepositState::Normal);
+        assert_eq!(deposit.cancellations(), 1);
+        assert_eq!(db.cancelled_disputes(), 1);
+
+        // a resolve is not a cancellation, and only an open dispute can be cancelled
+        assert!(db.perform_action(dispute(1, 1)).is_ok());
+        assert!(db.perform_action(resolve(1, 1)).is_ok());
+        assert_eq!(db.cancelled_disputes(), 1);
+        assert!(matches!(
+            db.perform_action(cancel),
+            Err(Error::TransactionNotDisputed)
+        ));
+    }
+
+    /// ensure deposits of the same client and amount within the window are flagged, and still credited
+    #[test]
+    fn possible_duplicates() {
+        let actions = [
+            deposit(1, 1, 10),
+            deposit(1, 2, 10),
+            // another client, or another amount, is not a duplicate
+            deposit(2, 3, 10),
+            deposit(1, 4, 20),
+            deposit(1, 5, 30),
+            deposit(1, 6, 30),
+            // more than three actions after the last deposit of 10
+            deposit(1, 7, 10),
+        ];
+        let mut db = Database::with_config(Config {
+            duplicate_deposit_window: Some(3),
+            ..Config::default()
+        });
+        for action in actions.clone() {
+            db.perform_action(action).unwrap();
+        }
+        assert_eq!(
+            db.possible_duplicates(),
+            [
+                PossibleDuplicate {
+                    client_id: ClientId(1),
+                    transaction_id: TransactionId(2),
+                    earlier: TransactionId(1),
+                    amount: Amount(10),
+                },
+                PossibleDuplicate {
+                    client_id: ClientId(1),
+                    transaction_id: TransactionId(6),
+                    earlier: TransactionId(5),
+                    amount: Amount(30),
+                },
+            ]
+        );
+        assert!(db.client_mut(ClientId(1)).available() == Balance(110));
+
+        // a deposit that fails is neither flagged nor remembered
+        assert!(db.perform_action(deposit(1, 7, 10)).is_err());
+        assert_eq!(db.possible_duplicates().len(), 2);
+
+        let mut db = Database::new();
+        for action in actions {
+            db.perform_action(action).unwrap();
+        }
+        assert!(db.possible_duplicates().is_empty());
+    }
+
+    /// ensure a chargeback of more than the held funds is clamped, or fails with or without locking the account
+    #[test]
+    fn chargeback_shortfall() {
+        let perform = |shortfall| {
+            let mut db = Database::with_config(Config {
+                chargeback_shortfall: shortfall,
+                ..Config::default()
+            });
+            db.perform_action(deposit(1, 1, 5)).unwrap();
+            db.perform_action(dispute(1, 1)).unwrap();
+            // break the invariant behind the database's back
+            db.client_mut(ClientId(1)).held = Balance(2);
+            let chargeback = chargeback(1, 1);
+            // the preview agrees with the chargeback under every policy
+            let preview = db.available_after(&chargeback);
+            let result = db.perform_action(chargeback);
+            match (&preview, &result) {
+                (Ok(available), Ok(())) => {
+                    assert!(*available == db.client_mut(ClientId(1)).available())
+                }
+                (Err(preview), Err(e)) => assert_eq!(preview.to_string(), e.to_string()),
+                _ => panic!("previewed as {preview:?} but performed as {result:?}"),
+            }
+            (db, result)
+        };
+
+        let (mut db, result) = perform(ChargebackShortfall::LockAndFail);
+        assert!(matches!(result, Err(Error::InsufficientHeldFunds)));
+        assert!(db.client_mut(ClientId(1)).locked);
+        assert!(db.client_mut(ClientId(1)).held() == Balance(2));
+        assert_eq!(
+            db.deposit_state(TransactionId(1)),
+            Some(DepositState::Disputed)
+        );
+
+        let (mut db, result) = perform(ChargebackShortfall::Clamp);
+        assert!(result.is_ok());
+        let client = db.client_mut(ClientId(1));
+        assert!(client.locked);
+        assert!(client.held() == Balance(0));
+        assert_eq!(client.chargebacks(), 1);
+        assert_eq!(
+            db.deposit_state(TransactionId(1)),
+            Some(DepositState::ChargedBack)
+        );
+        assert_eq!(
+            db.deposit_info(TransactionId(1)).unwrap().charged_back(),
+            Amount(2)
+        );
+
+        let (mut db, result) = perform(ChargebackShortfall::Fail);
+        assert!(matches!(result, Err(Error::InsufficientHeldFunds)));
+        assert!(!db.client_mut(ClientId(1)).locked);
+        assert_eq!(db.lock_reason(ClientId(1)), None);
+        assert!(db.client_mut(ClientId(1)).held() == Balance(2));
+        assert_eq!(
+            db.deposit_state(TransactionId(1)),
+            Some(DepositState::Disputed)
+        );
     }
 }
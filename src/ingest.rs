@@ -0,0 +1,869 @@
+use crate::{
+    actions::{AccountAction, NoOp, COLUMNS, TYPES},
+    database::Database,
+    store::TransactionStore,
+    Amount, Error, InputPosition, Result,
+};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+};
+
+/// How to read a CSV file of actions.
+#[derive(Debug, Clone)]
+pub struct ReadOptions {
+    /// whether the file starts with a header row, otherwise the columns are in the order of [`COLUMNS`]
+    pub has_headers: bool,
+    /// lines starting with this byte are skipped, comments are disabled if `None`
+    pub comment: Option<u8>,
+    /// records of these types are read as [`AccountAction::NoOp`] instead of failing to deserialize
+    pub ignore_types: Vec<String>,
+    /// whether the `amount` column holds integer minor units (0.0001ths) instead of decimals, e.g. `12345` for `1.2345`.
+    /// like decimals, minor units may be negative for adjustments, e.g. `-5000` for `-0.5`.
+    pub minor_units: bool,
+    /// whether the `amount` column uses a comma as the decimal separator, e.g. `1,2345` for `1.2345`.
+    /// such amounts must be quoted, or the fields separated by another [`ReadOptions::delimiter`].
+    /// thousands separators are not supported, an amount with a `.` or more than one `,` is invalid.
+    pub decimal_comma: bool,
+    /// whether amounts must be written with exactly four decimal places, e.g. `1.5000` but not `1.5` or `1`,
+    /// like [`Amount::deserialize_strict`](crate::Amount::deserialize_strict), for strict feeds.
+    /// checked after a [`ReadOptions::decimal_comma`] is converted, minor units always pass.
+    pub strict_amounts: bool,
+    /// the byte separating the fields of a record
+    pub delimiter: u8,
+    /// stop after this many records (not counting the header and comments), e.g. to preview a huge file
+    pub limit: Option<usize>,
+    /// abort with [`Error::InputTooLarge`] after this many records, for untrusted input.
+    /// unlike [`ReadOptions::limit`], more records are an error rather than skipped.
+    pub max_records: Option<usize>,
+    /// abort with [`Error::InputTooLarge`] once more than this many bytes are read, for untrusted input.
+    /// the input is never read much further than this, so a huge record can't exhaust the memory either.
+    pub max_bytes: Option<u64>,
+    /// whether records of an unknown type are read as [`AccountAction::NoOp`] instead of failing to deserialize,
+    /// and counted per type in [`IngestSummary::unhandled`]. this tells new types of an upstream feed apart from
+    /// corrupt records. a record without a type is still invalid.
+    pub unknown_types_unhandled: bool,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            has_headers: true,
+            comment: Some(b'#'),
+            ignore_types: Vec::new(),
+            minor_units: false,
+            decimal_comma: false,
+            strict_amounts: false,
+            delimiter: b',',
+            limit: None,
+            max_records: None,
+            max_bytes: None,
+            unknown_types_unhandled: false,
+        }
+    }
+}
+
+/// What happened while ingesting a file, see [`Database::ingest`].
+#[derive(Debug, Default)]
+pub struct IngestSummary {
+    /// the number of records read, including invalid ones
+    pub records: usize,
+    /// the number of records that could not be deserialized
+    pub invalid: usize,
+    /// the number of actions that could not be performed
+    pub failed: usize,
+    /// the number of records of an ignored type
+    pub ignored: usize,
+    /// the number of records of each unknown type, if [`ReadOptions::unknown_types_unhandled`] is set
+    pub unhandled: BTreeMap<String, usize>,
+    /// the number of accounts that were locked by the ingested actions, accounts that were locked before are not counted
+    pub locked: usize,
+    /// the number of deposits flagged as possible duplicates by the ingested actions,
+    /// see [`Config::duplicate_deposit_window`](crate::config::Config::duplicate_deposit_window)
+    pub possible_duplicates: usize,
+    /// the invalid records (counting from 0) with their errors, in order.
+    /// invalid records are reported as [`Error::InvalidRecord`] with their position in the input,
+    /// or as [`Error::Parse`] if the input could not be read at all.
+    /// if the input exceeds [`ReadOptions::max_records`] or [`ReadOptions::max_bytes`], the last error is
+    /// [`Error::InputTooLarge`] for the first record that was not applied.
+    pub errors: Vec<(usize, Error)>,
+    /// the actions that could not be performed (counting from 0) with their errors, in order,
+    /// e.g. to fix and resubmit them. the errors are wrapped in [`Error::Action`] with the number of the record,
+    /// see [`Database::apply_action_at`].
+    pub failed_actions: Vec<(usize, AccountAction, Error)>,
+}
+
+impl IngestSummary {
+    /// the errors of the invalid records and of the failed actions, ordered by the record they belong to.
+    pub fn all_errors(&self) -> Vec<(usize, &Error)> {
+        let mut errors = self
+            .errors
+            .iter()
+            .map(|(n, e)| (*n, e))
+            .chain(self.failed_actions.iter().map(|(n, _, e)| (*n, e)))
+            .collect::<Vec<_>>();
+        errors.sort_by_key(|(n, _)| *n);
+        errors
+    }
+
+    /// the number of failed actions that reused a transaction id, which are included in [`IngestSummary::failed`].
+    pub fn duplicates(&self) -> usize {
+        self.failed_actions
+            .iter()
+            .filter(|(_, _, e)| {
+                matches!(e, Error::Action { source, .. } if matches!(**source, Error::InvalidTransactionId))
+            })
+            .count()
+    }
+}
+
+/// a one line report, e.g. `processed 1000 records, 2 failed (1 duplicate), 1 account locked`.
+/// counts of zero are left out.
+impl std::fmt::Display for IngestSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        write!(
+            f,
+            "processed {} record{}",
+            self.records,
+            plural(self.records)
+        )?;
+        if self.invalid > 0 {
+            write!(f, ", {} invalid", self.invalid)?;
+        }
+        if self.failed > 0 {
+            write!(f, ", {} failed", self.failed)?;
+            let duplicates = self.duplicates();
+            if duplicates > 0 {
+                write!(f, " ({duplicates} duplicate{})", plural(duplicates))?;
+            }
+        }
+        if self.ignored > 0 {
+            write!(f, ", {} ignored", self.ignored)?;
+        }
+        let unhandled = self.unhandled.values().sum::<usize>();
+        if unhandled > 0 {
+            write!(f, ", {unhandled} unhandled")?;
+        }
+        if self.locked > 0 {
+            write!(f, ", {} account{} locked", self.locked, plural(self.locked))?;
+        }
+        if self.possible_duplicates > 0 {
+            write!(
+                f,
+                ", {} possible duplicate deposit{}",
+                self.possible_duplicates,
+                plural(self.possible_duplicates)
+            )?;
+        }
+        if matches!(self.errors.last(), Some((_, Error::InputTooLarge))) {
+            f.write_str(", stopped early because the input is too large")?;
+        }
+        Ok(())
+    }
+}
+
+/// the error a [`LimitedReader`] fails with once its limit is exceeded.
+#[derive(Debug)]
+struct LimitExceeded;
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("input exceeds the configured maximum number of bytes")
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// a reader that fails with [`LimitExceeded`] once more than `remaining` bytes would be read.
+struct LimitedReader<R> {
+    inner: R,
+    remaining: u64,
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // read one byte past the limit, to tell an input of exactly the limit from a larger one
+        let len = buf.len().min(
+            self.remaining
+                .saturating_add(1)
+                .try_into()
+                .unwrap_or(usize::MAX),
+        );
+        let n = self.inner.read(&mut buf[..len])?;
+        if n as u64 > self.remaining {
+            self.remaining = 0;
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                LimitExceeded,
+            ));
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+/// whether reading failed because the input exceeds [`ReadOptions::max_bytes`].
+fn limit_exceeded(e: &csv::Error) -> bool {
+    match e.kind() {
+        csv::ErrorKind::Io(e) => e.get_ref().is_some_and(|e| e.is::<LimitExceeded>()),
+        _ => false,
+    }
+}
+
+/// skip the UTF-8 byte order mark some Windows tools put at the start of a file,
+/// it would otherwise end up in the first field.
+fn skip_bom(reader: &mut impl BufRead) -> std::io::Result<()> {
+    if reader.fill_buf()?.starts_with(b"\xEF\xBB\xBF") {
+        reader.consume(3);
+    }
+    Ok(())
+}
+
+/// read the actions from a CSV file, without applying them.
+pub fn records<R: Read + 'static>(
+    r: R,
+    options: &ReadOptions,
+) -> Box<dyn Iterator<Item = csv::Result<AccountAction>>> {
+    Box::new(positioned_records(r, options).map(|(_, record)| record))
+}
+
+/// read the actions from a CSV file like [`records`], along with the position of each record if it is known.
+///
+/// the position is taken before the record is deserialized, so it is known for every record that could be read,
+/// even if its amount can't be converted.
+fn positioned_records<R: Read + 'static>(
+    r: R,
+    options: &ReadOptions,
+) -> Box<dyn Iterator<Item = (Option<csv::Position>, csv::Result<AccountAction>)>> {
+    let mut reader = BufReader::new(LimitedReader {
+        inner: r,
+        remaining: options.max_bytes.unwrap_or(u64::MAX),
+    });
+    if let Err(e) = skip_bom(&mut reader) {
+        return Box::new(std::iter::once((None, Err(e.into()))));
+    }
+    let mut reader = csv::ReaderBuilder::new()
+        // we usually have headers in the CSV
+        .has_headers(options.has_headers)
+        .delimiter(options.delimiter)
+        // allow for comments in the CSV, using # unless configured otherwise
+        .comment(options.comment)
+        // dispute, resolve, and chargeback actions don't have an amount field
+        .flexible(true)
+        // allow for whitespaces in the CSV
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let headers = if options.has_headers {
+        match reader.headers() {
+            Ok(headers) => headers.clone(),
+            Err(e) => return Box::new(std::iter::once((e.position().cloned(), Err(e)))),
+        }
+    } else {
+        // the reader only matches fields by name if it read the header itself,
+        // so we match the records against the expected columns ourselves.
+        csv::StringRecord::from(COLUMNS.to_vec())
+    };
+    // we look at the type of each record before deserializing it, to turn ignored types into no-ops
+    let type_column = headers.iter().position(|header| header == "type");
+    let amount_column = headers.iter().position(|header| header == "amount");
+    let ignore_types = options.ignore_types.clone();
+    let minor_units = options.minor_units;
+    let decimal_comma = options.decimal_comma;
+    let strict_amounts = options.strict_amounts;
+    let limit = options.limit.unwrap_or(usize::MAX);
+    let unknown_types_unhandled = options.unknown_types_unhandled;
+    let read = move |mut record: csv::StringRecord| {
+        if let Some(column) = amount_column {
+            if decimal_comma {
+                record = map_amount(&record, column, from_decimal_comma)?;
+            }
+            if minor_units {
+                record = map_amount(&record, column, from_minor_units)?;
+            }
+            if strict_amounts {
+                record = map_amount(&record, column, four_decimals)?;
+            }
+        }
+        let kind = type_column.and_then(|column| record.get(column));
+        match kind {
+            Some(kind) if ignore_types.iter().any(|ignored| ignored == kind) => {
+                Ok(AccountAction::NoOp(NoOp::new(kind)))
+            }
+            Some(kind) if unknown_types_unhandled && !kind.is_empty() && !TYPES.contains(&kind) => {
+                Ok(AccountAction::NoOp(NoOp::new(kind)))
+            }
+            _ => record.deserialize(Some(&headers)),
+        }
+    };
+    Box::new(
+        reader
+            .into_records()
+            .take(limit)
+            .map(move |record| match record {
+                Ok(record) => (record.position().cloned(), read(record)),
+                Err(e) => (e.position().cloned(), Err(e)),
+            }),
+    )
+}
+
+/// rewrite the amount in `column` of the record into the decimal form the deserializer of [`Amount`] reads.
+/// a missing amount is left as is.
+fn map_amount(
+    record: &csv::StringRecord,
+    column: usize,
+    convert: fn(&str) -> std::result::Result<String, String>,
+) -> csv::Result<csv::StringRecord> {
+    let mut converted = csv::StringRecord::new();
+    for (i, field) in record.iter().enumerate() {
+        if i == column && !field.is_empty() {
+            let field = convert(field)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            converted.push_field(&field);
+        } else {
+            converted.push_field(field);
+        }
+    }
+    converted.set_position(record.position().cloned());
+    Ok(converted)
+}
+
+/// an amount in minor units, e.g. `12345` for `1.2345`, or `-12345` for `-1.2345` as in a debit adjustment.
+fn from_minor_units(field: &str) -> std::result::Result<String, String> {
+    let (sign, magnitude) = match field.strip_prefix('-') {
+        Some(magnitude) => ("-", magnitude),
+        None => ("", field),
+    };
+    let invalid = || format!("amount `{field}` is not an integer number of minor units");
+    // `u64::from_str` accepts a leading `+`, which must not follow the `-`
+    if magnitude.starts_with(['+', '-']) {
+        return Err(invalid());
+    }
+    let units: u64 = magnitude.parse().map_err(|_| invalid())?;
+    Ok(format!("{sign}{:?}", Amount::from_raw(units)))
+}
+
+/// an amount with exactly four decimal places, unchanged.
+fn four_decimals(field: &str) -> std::result::Result<String, String> {
+    crate::check_four_decimals(field)?;
+    Ok(field.to_string())
+}
+
+/// an amount with a decimal comma, e.g. `1,2345` for `1.2345`.
+fn from_decimal_comma(field: &str) -> std::result::Result<String, String> {
+    // `1.234,5` or `1,234,5` would be read with a thousands separator in some locales and not in others, so we don't guess
+    if field.contains('.') || field.matches(',').count() > 1 {
+        return Err(format!(
+            "amount `{field}` has a thousands separator, which is not supported with a decimal comma"
+        ));
+    }
+    Ok(field.replace(',', "."))
+}
+
+impl<S: TransactionStore> Database<S> {
+    /// apply all actions of a CSV file.
+    ///
+    /// like [`Database::apply_jsonl`], a record that fails to parse or apply does not stop the processing.
+    pub fn ingest<R: Read + 'static>(&mut self, r: R, options: &ReadOptions) -> IngestSummary {
+        let mut summary = IngestSummary::default();
+        let locked = self.locked_count();
+        let possible_duplicates = self.possible_duplicates().len();
+        for (n, (position, record)) in positioned_records(r, options).enumerate() {
+            let too_large = match &record {
+                Err(e) => limit_exceeded(e),
+                Ok(_) => options.max_records.is_some_and(|max| n >= max),
+            };
+            if too_large {
+                summary.errors.push((n, Error::InputTooLarge));
+                break;
+            }
+            summary.records += 1;
+            match record {
+                Err(e) => {
+                    summary.invalid += 1;
+                    let error = match position {
+                        Some(position) => Error::InvalidRecord {
+                            position: InputPosition {
+                                byte: position.byte(),
+                                line: position.line(),
+                                record: position.record(),
+                            },
+                            message: e.to_string(),
+                        },
+                        None => Error::Parse(e.to_string()),
+                    };
+                    summary.errors.push((n, error));
+                }
+                Ok(action) => self.ingest_action(&mut summary, n, action, &options.ignore_types),
+            }
+        }
+        // no action unlocks an account, so this is the number of newly locked accounts
+        summary.locked = self.locked_count() - locked;
+        summary.possible_duplicates = self.possible_duplicates().len() - possible_duplicates;
+        summary
+    }
+
+    /// perform the `n`th action of an input, counting it in the summary.
+    fn ingest_action(
+        &mut self,
+        summary: &mut IngestSummary,
+        n: usize,
+        action: AccountAction,
+        ignore_types: &[String],
+    ) {
+        if let AccountAction::NoOp(noop) = &action {
+            if ignore_types.iter().any(|ignored| ignored == noop.kind()) {
+                summary.ignored += 1;
+            } else {
+                *summary
+                    .unhandled
+                    .entry(noop.kind().to_string())
+                    .or_default() += 1;
+            }
+        }
+        if let Err(e) = self.apply_action_at(n, &action) {
+            summary.failed += 1;
+            summary.failed_actions.push((n, action, e));
+        }
+    }
+}
+
+impl Database {
+    /// apply a CSV file with a header row to a new database with the default configuration.
+    ///
+    /// only fails if the file can't be opened, invalid records and failed actions are collected in the summary.
+    pub fn process_file(path: &Path) -> Result<(Database, IngestSummary)> {
+        let file = File::open(path)?;
+        let mut db = Database::new();
+        let summary = db.ingest(file, &ReadOptions::default());
+        Ok((db, summary))
+    }
+
+    /// apply in-memory actions to a new database with the default configuration, e.g. to replay a log or build a
+    /// test fixture.
+    ///
+    /// like [`Database::ingest`], a failing action does not stop the processing, it is collected in the summary.
+    /// every action counts as a record, and none are invalid.
+    pub fn from_actions<I: IntoIterator<Item = AccountAction>>(
+        actions: I,
+    ) -> (Database, IngestSummary) {
+        let mut db = Database::new();
+        let mut summary = IngestSummary::default();
+        for (n, action) in actions.into_iter().enumerate() {
+            summary.records += 1;
+            db.ingest_action(&mut summary, n, action, &[]);
+        }
+        summary.locked = db.locked_count();
+        summary.possible_duplicates = db.possible_duplicates().len();
+        (db, summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{records, ReadOptions};
+    use crate::{actions::AccountAction, database::Database, Amount, Balance, ClientId, Error};
+
+    /// ensure processing a file applies it and counts what happened
+    #[test]
+    fn process_file() {
+        let path =
+            std::env::temp_dir().join(format!("ledger-process-file-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,1,2,1.00001\nwithdrawal,1,3,5.0\n# a comment\ndeposit,2,4,1.0\n",
+        )
+        .unwrap();
+        let result = Database::process_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        let (mut db, summary) = result.unwrap();
+        assert_eq!(summary.records, 4);
+        assert_eq!(summary.invalid, 1);
+        assert_eq!(summary.failed, 1);
+        assert_eq!(summary.ignored, 0);
+        assert!(matches!(
+            summary.all_errors().as_slice(),
+            [
+                (1, Error::InvalidRecord { .. }),
+                (2, Error::Action { index: 2, .. })
+            ]
+        ));
+        assert!(db.client_mut(ClientId(1)).available() == Balance(2_0000));
+        assert_eq!(db.clients().count(), 2);
+
+        assert!(matches!(Database::process_file(&path), Err(Error::Io(_))));
+    }
+
+    /// ensure amounts in minor units are read as the same amounts as their decimal form
+    #[test]
+    fn minor_units() {
+        let amounts = |input: &'static str, minor_units| {
+            let options = ReadOptions {
+                minor_units,
+                ..ReadOptions::default()
+            };
+            records(input.as_bytes(), &options)
+                .map(|record| match record {
+                    Ok(AccountAction::Deposit(deposit)) => Ok(deposit.amount),
+                    Ok(action) => panic!("unexpected action {action:?}"),
+                    Err(e) => Err(e),
+                })
+                .collect::<Vec<_>>()
+        };
+        let decimal = amounts("type,client,tx,amount\ndeposit,1,1,1.2345\n", false);
+        let minor = amounts("type,client,tx,amount\ndeposit,1,1,12345\n", true);
+        assert!(decimal[0]
+            .as_ref()
+            .is_ok_and(|&amount| amount == Amount::from_raw(12345)));
+        assert!(minor[0]
+            .as_ref()
+            .is_ok_and(|&amount| amount == Amount::from_raw(12345)));
+        // decimals are not minor units, and a missing amount is still only an error for deposits and withdrawals
+        let invalid = amounts("type,client,tx,amount\ndeposit,1,1,1.5\n", true);
+        assert!(invalid[0].is_err());
+        let options = ReadOptions {
+            minor_units: true,
+            ..ReadOptions::default()
+        };
+        let mut dispute = records("type,client,tx,amount\ndispute,1,1,\n".as_bytes(), &options);
+        assert!(matches!(
+            dispute.next(),
+            Some(Ok(AccountAction::Dispute(_)))
+        ));
+    }
+
+    /// ensure a negative adjustment can be given in minor units, while deposits stay non-negative
+    #[test]
+    fn minor_units_negative_adjustment() {
+        let options = ReadOptions {
+            minor_units: true,
+            ..ReadOptions::default()
+        };
+        let input = "type,client,tx,amount\ndeposit,1,1,20000\nadjustment,1,5,-5000\ndeposit,1,6,-5000\nadjustment,1,7,-+5000\n";
+        let mut db = Database::new();
+        let summary = db.ingest(input.as_bytes(), &options);
+        assert_eq!(summary.records, 4);
+        assert!(matches!(
+            summary.all_errors().as_slice(),
+            [
+                (2, Error::InvalidRecord { .. }),
+                (3, Error::InvalidRecord { .. })
+            ]
+        ));
+        assert!(db.client_mut(ClientId(1)).available() == Balance(1_5000));
+    }
+
+    /// ensure strict amounts must have exactly four decimal places, also with a decimal comma or for adjustments
+    #[test]
+    fn strict_amounts() {
+        let options = ReadOptions {
+            strict_amounts: true,
+            ..ReadOptions::default()
+        };
+        let input = "type,client,tx,amount\ndeposit,1,1,1.5000\ndeposit,1,2,1.5\ndeposit,1,3,1\nadjustment,1,4,-0.5000\ndispute,1,1,\n";
+        let results = records(input.as_bytes(), &options).collect::<Vec<_>>();
+        assert!(matches!(
+            results.as_slice(),
+            [
+                Ok(AccountAction::Deposit(_)),
+                Err(_),
+                Err(_),
+                Ok(AccountAction::Adjustment(_)),
+                Ok(AccountAction::Dispute(_))
+            ]
+        ));
+        // lenient by default
+        assert!(records(input.as_bytes(), &ReadOptions::default()).all(|record| record.is_ok()));
+
+        let options = ReadOptions {
+            decimal_comma: true,
+            delimiter: b';',
+            ..options
+        };
+        let input = "type;client;tx;amount\ndeposit;1;1;1,5000\ndeposit;1;2;1,5\n";
+        let results = records(input.as_bytes(), &options).collect::<Vec<_>>();
+        assert!(matches!(results.as_slice(), [Ok(_), Err(_)]));
+    }
+
+    /// ensure amounts with a decimal comma are read as the same amounts as with a decimal point
+    #[test]
+    fn decimal_comma() {
+        let amounts = |input: &'static str, delimiter| {
+            let options = ReadOptions {
+                decimal_comma: true,
+                delimiter,
+                ..ReadOptions::default()
+            };
+            records(input.as_bytes(), &options)
+                .map(|record| match record {
+                    Ok(AccountAction::Deposit(deposit)) => Some(deposit.amount),
+                    Ok(action) => panic!("unexpected action {action:?}"),
+                    Err(_) => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let expected = Some(Amount::from_raw(12345));
+        assert_eq!(
+            amounts(
+                "type;client;tx;amount\ndeposit;1;1;1,2345\ndeposit;1;2;1.2345\n",
+                b';'
+            ),
+            [expected, None]
+        );
+        // with the default delimiter the amount has to be quoted
+        assert_eq!(
+            amounts(
+                "type,client,tx,amount\ndeposit,1,1,\"1,2345\"\ndeposit,1,2,2\n",
+                b','
+            ),
+            [expected, Some(Amount::from_raw(2_0000))]
+        );
+        // thousands separators are rejected rather than guessed
+        assert_eq!(
+            amounts(
+                "type;client;tx;amount\ndeposit;1;1;1.234,5\ndeposit;1;2;1,234,5\n",
+                b';'
+            ),
+            [None, None]
+        );
+    }
+
+    /// ensure a file without records, with or without a header, is read without errors
+    #[test]
+    fn empty() {
+        for input in ["", "type,client,tx,amount\n"] {
+            let mut db = Database::new();
+            let summary = db.ingest(input.as_bytes(), &ReadOptions::default());
+            assert_eq!(summary.records, 0);
+            assert!(summary.errors.is_empty());
+            assert_eq!(db.clients().count(), 0);
+        }
+    }
+
+    /// ensure records of an unknown type are counted as unhandled instead of invalid if configured
+    #[test]
+    fn unknown_types_unhandled() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\nunknownaction,1,2,1.0\nheartbeat,0,0,\nunknownaction,1,3,\n,1,4,1.0\n";
+        let mut db = Database::new();
+        let summary = db.ingest(input.as_bytes(), &ReadOptions::default());
+        assert_eq!(summary.invalid, 4);
+        assert!(summary.unhandled.is_empty());
+
+        let options = ReadOptions {
+            unknown_types_unhandled: true,
+            ignore_types: vec!["heartbeat".to_string()],
+            ..ReadOptions::default()
+        };
+        let mut db = Database::new();
+        let summary = db.ingest(input.as_bytes(), &options);
+        assert_eq!(summary.records, 5);
+        // a record without a type is still corrupt
+        assert_eq!(summary.invalid, 1);
+        assert_eq!(summary.ignored, 1);
+        assert_eq!(
+            summary.unhandled.into_iter().collect::<Vec<_>>(),
+            [("unknownaction".to_string(), 2)]
+        );
+        assert!(db.client_mut(ClientId(1)).available() == Balance(1_0000));
+    }
+
+    /// ensure an input with more records or bytes than allowed is applied up to the limit, then aborted
+    #[test]
+    fn input_too_large() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,1.0\ndeposit,1,3,1.0\ndeposit,1,4,1.0\n";
+        let options = ReadOptions {
+            max_records: Some(2),
+            ..ReadOptions::default()
+        };
+        let mut db = Database::new();
+        let summary = db.ingest(input.as_bytes(), &options);
+        assert_eq!(summary.records, 2);
+        assert!(matches!(
+            summary.errors.as_slice(),
+            [(2, Error::InputTooLarge)]
+        ));
+        assert!(db.client_mut(ClientId(1)).available() == Balance(2_0000));
+        assert!(db.deposit_info(crate::TransactionId(3)).is_none());
+
+        // exactly at the limit is fine
+        let options = ReadOptions {
+            max_records: Some(4),
+            max_bytes: Some(input.len() as u64),
+            ..ReadOptions::default()
+        };
+        let summary = Database::new().ingest(input.as_bytes(), &options);
+        assert_eq!(summary.records, 4);
+        assert!(summary.errors.is_empty());
+
+        // a single byte too many fails
+        let options = ReadOptions {
+            max_bytes: Some(input.len() as u64 - 1),
+            ..ReadOptions::default()
+        };
+        let mut db = Database::new();
+        let summary = db.ingest(input.as_bytes(), &options);
+        assert!(matches!(
+            summary.errors.last(),
+            Some((_, Error::InputTooLarge))
+        ));
+        assert!(summary.records < 4);
+        assert!(
+            db.client_mut(ClientId(1)).available() == Balance(summary.records as i128 * 1_0000)
+        );
+    }
+
+    /// ensure invalid records report where they are in the input, also if their amount could not be converted
+    #[test]
+    fn invalid_record_position() {
+        let input =
+            "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,1.00001\ndeposit,1,3,1.5\n";
+        let mut db = Database::new();
+        let summary = db.ingest(input.as_bytes(), &ReadOptions::default());
+        let [(1, Error::InvalidRecord { position, message })] = summary.errors.as_slice() else {
+            panic!("unexpected errors {:?}", summary.errors);
+        };
+        assert_eq!(position.line, 3);
+        assert_eq!(position.byte, input.find("deposit,1,2").unwrap() as u64);
+        assert_eq!(position.record, 2);
+        assert!(message.contains("line: 3"));
+
+        // none of the amounts are minor units, which is found before the records are deserialized
+        let options = ReadOptions {
+            minor_units: true,
+            ..ReadOptions::default()
+        };
+        let summary = Database::new().ingest(input.as_bytes(), &options);
+        let lines = summary
+            .errors
+            .iter()
+            .map(|(_, error)| match error {
+                Error::InvalidRecord { position, .. } => position.line,
+                error => panic!("unexpected error {error:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(lines, [2, 3, 4]);
+    }
+
+    /// ensure failed actions are kept with their errors, so they can be fixed and resubmitted
+    #[test]
+    fn failed_actions() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,1,2,1.5\ndeposit,1,x,1.0\nwithdrawal,1,3,0.5\n";
+        let mut db = Database::new();
+        let summary = db.ingest(input.as_bytes(), &ReadOptions::default());
+        assert_eq!(summary.failed, 1);
+        let [(1, AccountAction::Withdrawal(withdrawal), Error::Action { index: 1, source })] =
+            summary.failed_actions.as_slice()
+        else {
+            panic!("unexpected failed actions {:?}", summary.failed_actions);
+        };
+        assert!(matches!(**source, Error::InsufficientFunds { .. }));
+        assert_eq!(withdrawal.transaction_id, crate::TransactionId(2));
+        assert_eq!(withdrawal.amount, Amount::from_raw(1_5000));
+        assert!(matches!(
+            summary.all_errors().as_slice(),
+            [
+                (1, Error::Action { index: 1, .. }),
+                (2, Error::InvalidRecord { .. })
+            ]
+        ));
+
+        // the failed action keeps its transaction id used, but can be resubmitted elsewhere, e.g. to a corrected ledger
+        let (_, action, _) = &summary.failed_actions[0];
+        assert!(matches!(
+            db.perform_action(action.clone()),
+            Err(Error::InvalidTransactionId)
+        ));
+        let mut corrected = Database::new();
+        let summary = corrected.ingest(
+            "type,client,tx,amount\ndeposit,1,1,2.0\n".as_bytes(),
+            &ReadOptions::default(),
+        );
+        assert!(summary.all_errors().is_empty());
+        assert!(corrected.perform_action(action.clone()).is_ok());
+        assert!(corrected.client_mut(ClientId(1)).available() == Balance(5000));
+    }
+
+    /// ensure the summary reports the counts of a run, leaving out those that are zero
+    #[test]
+    fn display() {
+        let input = "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,1,1,1.0\ndeposit,2,1,1.0\nwithdrawal,1,2,5.0\ndeposit,1,x,1.0\nheartbeat,1,,\ndispute,1,1,\nchargeback,1,1,\n";
+        let options = ReadOptions {
+            ignore_types: vec!["heartbeat".to_string()],
+            ..ReadOptions::default()
+        };
+        let summary = Database::new().ingest(input.as_bytes(), &options);
+        assert_eq!(
+            summary.to_string(),
+            "processed 8 records, 1 invalid, 3 failed (2 duplicates), 1 ignored, 1 account locked"
+        );
+        let summary = Database::new().ingest("type,client,tx,amount\n".as_bytes(), &options);
+        assert_eq!(summary.to_string(), "processed 0 records");
+    }
+
+    /// ensure ids beyond the `u16` client and `u32` transaction id ranges are read with the `wide-ids` feature,
+    /// and rejected as invalid records without it
+    #[test]
+    fn wide_ids() {
+        let input =
+            "type,client,tx,amount\ndeposit,70000,5000000000,1.0\ndispute,70000,5000000000,\n";
+        let mut db = Database::new();
+        let summary = db.ingest(input.as_bytes(), &ReadOptions::default());
+        #[cfg(feature = "wide-ids")]
+        {
+            assert!(summary.all_errors().is_empty());
+            let deposit = db
+                .deposit_info(crate::TransactionId(5_000_000_000))
+                .unwrap();
+            assert_eq!(deposit.client_id(), ClientId(70_000));
+            assert!(deposit.is_disputed());
+            assert!(db.client_mut(ClientId(70_000)).held() == Balance(1_0000));
+        }
+        #[cfg(not(feature = "wide-ids"))]
+        {
+            assert_eq!(summary.invalid, 2);
+            assert_eq!(db.clients().count(), 0);
+        }
+    }
+
+    /// ensure replaying in-memory actions gives the same state as performing them one by one
+    #[test]
+    fn from_actions() {
+        let input = "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,2,2,1.0\nwithdrawal,1,3,5.0\ndispute,2,2,\ndeposit,1,1,1.0\nwithdrawal,1,4,0.5\nchargeback,2,2,\n";
+        let actions = records(input.as_bytes(), &ReadOptions::default())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let mut expected = Database::new();
+        for action in actions.clone() {
+            let _ = expected.perform_action(action);
+        }
+        let (mut db, summary) = Database::from_actions(actions);
+        assert_eq!(db.state_hash(), expected.state_hash());
+        assert_eq!(summary.records, 7);
+        assert_eq!(summary.invalid, 0);
+        assert_eq!(summary.failed, 2);
+        assert_eq!(summary.duplicates(), 1);
+        assert_eq!(summary.locked, 1);
+        assert!(db.client_mut(ClientId(1)).available() == Balance(1_5000));
+        assert!(db.client_mut(ClientId(2)).locked);
+    }
+
+    /// ensure the summary counts the deposits the ingest flagged as possible duplicates
+    #[test]
+    fn possible_duplicates() {
+        let input = "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,1,2,2.0\ndeposit,2,3,2.0\n";
+        let mut db = Database::with_config(crate::config::Config {
+            duplicate_deposit_window: Some(1),
+            ..Default::default()
+        });
+        let summary = db.ingest(input.as_bytes(), &ReadOptions::default());
+        assert_eq!(summary.possible_duplicates, 1);
+        assert_eq!(summary.failed, 0);
+        assert_eq!(
+            summary.to_string(),
+            "processed 3 records, 1 possible duplicate deposit"
+        );
+    }
+}
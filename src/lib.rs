@@ -1,20 +1,44 @@
-//! This crate implements a toy payment engine that processes CSV files containing deposits, withdrawals, disputes, chargebacks, and dispute resolutions.
+//! This crate implements a toy payment engine that processes CSV files containing deposits, withdrawals, disputes, chargebacks, dispute resolutions, refunds, and account closures.
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Deserializer, Serialize};
 use std::fmt::Debug;
 
-/// The actions that can be performed on an account. (deposit, withdrawal, dispute, resolve, chargeback).
+/// The actions that can be performed on an account. (deposit, withdrawal, dispute, resolve, chargeback, refund, close).
 pub mod actions;
 /// The client's account.
 pub mod client;
+/// The configuration of the database.
+pub mod config;
 /// The database of clients and transactions.
 pub mod database;
+/// Reading CSV files of actions into the database.
+#[cfg(feature = "serde")]
+pub mod ingest;
+/// Output formats other than CSV.
+pub mod output;
+/// Checking the database against an independent tally of the input.
+pub mod reconcile;
+/// A naive reference implementation the database is tested against.
+#[cfg(test)]
+mod reference;
+/// Where the database keeps track of transactions.
+pub mod store;
 
 /// The errors that can occur when processing transactions.
+///
+/// More errors will be added over time, so matching on an error outside of this crate requires a wildcard arm.
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum Error {
-    /// the client does not have enough funds to perform the requested action
-    #[error("insufficient funds")]
-    InsufficientFunds,
+    /// the client does not have enough funds to perform the requested action,
+    /// e.g. so a UI can say "needed X, had Y"
+    #[error("insufficient funds, requested {requested:?} but only {available:?} is available")]
+    InsufficientFunds {
+        /// the amount of the withdrawal
+        requested: Amount,
+        /// the available funds of the client
+        available: Balance,
+    },
     /// the transaction id has already been used (these must be globally unique)
     #[error("requested transaction id has already been used")]
     InvalidTransactionId,
@@ -25,18 +49,148 @@ pub enum Error {
     /// the client's account is locked and no withdrawals can be made
     #[error("account is locked")]
     AccountLocked,
+    /// the client's account is locked, so the action was set aside instead of performed, see
+    /// [`Database::quarantined`](database::Database::quarantined)
+    #[error("account is locked, the action is quarantined")]
+    Quarantined,
+    /// the client's account is closed and accepts no more actions
+    #[error("account is closed")]
+    AccountClosed,
+    /// the account can't be closed while it has held funds or open disputes
+    #[error("account has held funds or open disputes")]
+    OpenDisputes,
+    /// the client was not found in the database
+    #[error("client not found")]
+    ClientNotFound,
     /// the transaction id was not found in the database
     #[error("transaction id not found")]
     TransactionNotFound,
+    /// no dispute with the dispute id was found in the database
+    #[error("dispute id not found")]
+    DisputeNotFound,
+    /// the disputed transaction is a withdrawal, only deposits can be disputed
+    #[error("transaction id {0:?} is a withdrawal, which can't be disputed")]
+    CannotDisputeWithdrawal(TransactionId),
     /// the transaction id was not disputed before a chargeback or resolve was attempted
     #[error("transaction id not disputed")]
     TransactionNotDisputed,
+    /// the transaction has been charged back and can no longer be disputed, resolved, or charged back
+    #[error("transaction id has been charged back")]
+    TransactionChargedBack,
+    /// the deposit is already disputed, only reported if [`Config::strict_disputes`](config::Config::strict_disputes) is set
+    #[error("transaction id {0:?} is already disputed")]
+    AlreadyDisputed(TransactionId),
+    /// the withdrawal has already been refunded, it can only be refunded once
+    #[error("transaction id {0:?} is already refunded")]
+    AlreadyRefunded(TransactionId),
+    /// the dispute id already refers to the dispute of another deposit
+    #[error("dispute id {0:?} already refers to another deposit")]
+    DisputeIdInUse(DisputeId),
+    /// a resolve or chargeback gave both a dispute id and a transaction id, but the dispute is of another deposit
+    #[error("dispute id {dispute:?} does not refer to transaction id {transaction:?}")]
+    DisputeIdMismatch {
+        dispute: DisputeId,
+        transaction: TransactionId,
+    },
     /// the clients balance would overflow if the requested action was performed
     #[error("overflow updating balance")]
     Overflow,
     /// the clients balance would underflow if the requested action was performed
     #[error("underflow updating balance")]
     Underflow,
+    /// the database is not configured to accept this kind of action
+    #[error("{0:?} actions are not allowed")]
+    ActionNotAllowed(actions::ActionKind),
+    /// the action would create a new client, but the database already holds the configured maximum number of clients
+    #[error("maximum number of clients exceeded")]
+    ClientLimitExceeded,
+    /// the amount of the deposit or withdrawal exceeds the configured maximum per transaction
+    #[error("amount exceeds the maximum per transaction")]
+    AmountTooLarge,
+    /// the input has more records or bytes than configured in the `ReadOptions` it is ingested with
+    #[error("input exceeds the configured maximum number of records or bytes")]
+    InputTooLarge,
+    /// a record of the input could not be parsed into an action
+    #[error("failed to parse record: {0}")]
+    Parse(String),
+    /// a record of a CSV input could not be parsed into an action, at a known position in the input.
+    /// the message is that of the CSV reader, which mentions the position as well.
+    #[error("{message}")]
+    InvalidRecord {
+        position: InputPosition,
+        message: String,
+    },
+    /// the client has a negative total, only reported if
+    /// [`Config::forbid_negative_total`](config::Config::forbid_negative_total) is set
+    #[error("client {0:?} has a negative total")]
+    NegativeTotal(ClientId),
+    /// the held funds of a client are negative, which no sequence of actions can lead to
+    #[error("held funds are negative")]
+    NegativeHeld,
+    /// the total of a client in a snapshot is not the sum of its available and held funds
+    #[error("total of client {0:?} in the snapshot does not match its available and held funds")]
+    SnapshotTotalMismatch(ClientId),
+    /// updating the balance of a client failed because of an over- or underflow.
+    /// the underlying [`ArithmeticError`] is available through [`std::error::Error::source`].
+    #[error("failed to update the balance of client {client:?}")]
+    BalanceUpdate {
+        client: ClientId,
+        #[source]
+        source: ArithmeticError,
+    },
+    /// the totals of the clients don't add up to the funds deposited, withdrawn, charged back, and refunded,
+    /// see [`Reconciliation`](reconcile::Reconciliation)
+    #[error("client totals add up to {actual:?}, expected {expected:?} from the input")]
+    ReconciliationMismatch { expected: Balance, actual: Balance },
+    /// performing the action with the given index (e.g. the record number of the input) failed,
+    /// see [`Database::apply_action_at`](database::Database::apply_action_at)
+    #[error("failed to perform action {index}: {source}")]
+    Action {
+        index: usize,
+        #[source]
+        source: Box<Error>,
+    },
+    /// performing the action panicked, see [`Database::try_perform_action`](database::Database::try_perform_action)
+    #[error("performing the action panicked: {0}")]
+    Panicked(String),
+    /// the input could not be read
+    #[error("failed to read input: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// The position of a record in a CSV input, e.g. to jump to an invalid record in a large file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputPosition {
+    /// the offset of the start of the record in bytes, counting from 0
+    pub byte: u64,
+    /// the line the record starts on, counting from 1.
+    /// as counted by the CSV reader, which can be off after blank lines and comments, so prefer the byte offset.
+    pub line: u64,
+    /// the index of the record, counting from 0 and including the header row
+    pub record: u64,
+}
+
+/// The arithmetic failure underlying an [`Error::BalanceUpdate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum ArithmeticError {
+    /// the balance would have exceeded the largest representable value
+    #[error("overflow")]
+    Overflow,
+    /// the balance would have gone below the smallest representable value
+    #[error("underflow")]
+    Underflow,
+}
+
+impl Error {
+    /// attach the client to an over- or underflow error, all other errors are returned unchanged.
+    pub(crate) fn for_client(self, client: ClientId) -> Self {
+        let source = match self {
+            Error::Overflow => ArithmeticError::Overflow,
+            Error::Underflow => ArithmeticError::Underflow,
+            other => return other,
+        };
+        Error::BalanceUpdate { client, source }
+    }
 }
 
 pub(crate) type Result<T> = std::result::Result<T, Error>;
@@ -46,7 +200,26 @@ pub(crate) type Result<T> = std::result::Result<T, Error>;
 /// The ID of a transaction (deposit or withdrawal).
 /// These are globally unique but need not be sequential.
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct TransactionId(u32);
+pub struct TransactionId(TransactionIdRepr);
+
+/// The integer a [`TransactionId`] wraps, a `u64` instead of a `u32` with the `wide-ids` feature.
+///
+/// the feature is not additive: enabling it anywhere in a build changes this type for every crate in it.
+#[cfg(not(feature = "wide-ids"))]
+pub type TransactionIdRepr = u32;
+/// The integer a [`TransactionId`] wraps, a `u64` instead of a `u32` with the `wide-ids` feature.
+///
+/// the feature is not additive: enabling it anywhere in a build changes this type for every crate in it.
+#[cfg(feature = "wide-ids")]
+pub type TransactionIdRepr = u64;
+
+// a conversion to the same type with the `wide-ids` feature
+#[allow(clippy::useless_conversion)]
+impl From<TransactionId> for u64 {
+    fn from(id: TransactionId) -> Self {
+        id.0.into()
+    }
+}
 
 impl Debug for TransactionId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -54,10 +227,40 @@ impl Debug for TransactionId {
     }
 }
 
+/// The ID of a dispute, assigned by the dispute system independently of the transaction ids.
+/// Resolves and chargebacks can refer to a dispute by this id instead of by the disputed deposit.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DisputeId(u32);
+
+impl Debug for DisputeId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("{}", self.0))
+    }
+}
+
 /// The ID of a client.
 /// These are unique but need not be sequential.
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct ClientId(u16);
+pub struct ClientId(ClientIdRepr);
+
+/// The integer a [`ClientId`] wraps, a `u32` instead of a `u16` with the `wide-ids` feature.
+///
+/// the feature is not additive: enabling it anywhere in a build changes this type for every crate in it.
+#[cfg(not(feature = "wide-ids"))]
+pub type ClientIdRepr = u16;
+/// The integer a [`ClientId`] wraps, a `u32` instead of a `u16` with the `wide-ids` feature.
+///
+/// the feature is not additive: enabling it anywhere in a build changes this type for every crate in it.
+#[cfg(feature = "wide-ids")]
+pub type ClientIdRepr = u32;
+
+// a conversion to the same type with the `wide-ids` feature
+#[allow(clippy::useless_conversion)]
+impl From<ClientId> for u32 {
+    fn from(id: ClientId) -> Self {
+        id.0.into()
+    }
+}
 
 impl Debug for ClientId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -68,10 +271,123 @@ impl Debug for ClientId {
 /// An "amount" of an asset. This represents a positive amount of a certain asset, with up to four decimal places.
 /// This is used for the amount field of a deposit or withdrawal, it is not used for the total balance of a client which can go negative.
 /// The amount is stored as an integer number, preventing rounding errors.
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
 // a u64 is enough to hold almost 30 billion dollars of a relatively weak token like SHIB
 pub struct Amount(u64);
 
+impl Amount {
+    /// an amount of zero.
+    pub const fn zero() -> Self {
+        Self(0)
+    }
+
+    /// an amount in minor units, i.e. in 0.0001ths, `Amount::from_raw(12345)` is `1.2345`.
+    pub const fn from_raw(units: u64) -> Self {
+        Self(units)
+    }
+
+    /// parse the text of a decimal literal, for [`amount!`]. panics on anything that is no valid amount,
+    /// which is a compile error in the const item the macro evaluates it in.
+    #[doc(hidden)]
+    pub const fn from_literal(literal: &str) -> Self {
+        let bytes = literal.as_bytes();
+        let mut units: u64 = 0;
+        let mut digits = 0;
+        // the number of decimal places, once the decimal point is seen
+        let mut decimals: Option<u32> = None;
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                digit @ b'0'..=b'9' => {
+                    if let Some(places) = decimals {
+                        if places == 4 {
+                            panic!("amount has more than 4 decimal places");
+                        }
+                        decimals = Some(places + 1);
+                    }
+                    units = match units.checked_mul(10) {
+                        Some(units) => match units.checked_add((digit - b'0') as u64) {
+                            Some(units) => units,
+                            None => panic!("amount too large"),
+                        },
+                        None => panic!("amount too large"),
+                    };
+                    digits += 1;
+                }
+                b'.' if decimals.is_none() => decimals = Some(0),
+                b'_' => {}
+                b'-' => panic!("amount is negative"),
+                _ => panic!("amount must be a decimal literal"),
+            }
+            i += 1;
+        }
+        if digits == 0 {
+            panic!("amount must be a decimal literal");
+        }
+        let places = match decimals {
+            Some(places) => places,
+            None => 0,
+        };
+        match units.checked_mul(10u64.pow(4 - places)) {
+            Some(units) => Self(units),
+            None => panic!("amount too large"),
+        }
+    }
+
+    /// subtract another amount, returning `None` if the result would be negative.
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    /// the portion of this amount given in basis points (1/100th of a percent), rounded to four decimal places.
+    /// the result is never more than the amount itself, even for more than 10000 basis points.
+    pub fn basis_points(self, bps: u32, rounding: RoundingMode) -> Amount {
+        let scaled = self.0 as u128 * bps as u128;
+        let (quotient, remainder) = (scaled / 10_000, scaled % 10_000);
+        let rounded = match rounding {
+            RoundingMode::Down => quotient,
+            RoundingMode::Up if remainder > 0 => quotient + 1,
+            RoundingMode::Up => quotient,
+            RoundingMode::HalfUp if remainder >= 5_000 => quotient + 1,
+            RoundingMode::HalfUp => quotient,
+        };
+        // the portion can only exceed the amount if bps > 10000, in which case it fits in the u64 of the amount
+        Amount(rounded.min(self.0 as u128) as u64)
+    }
+}
+
+/// An [`Amount`] from a decimal literal, checked at compile time, e.g. `amount!(1.2345)` or `amount!(100)`.
+///
+/// a literal with more than four decimal places, a negative one, or one too large for an amount fails to compile.
+///
+/// ```
+/// use ledger::{amount, Amount};
+///
+/// assert_eq!(amount!(1.5), Amount::from_raw(1_5000));
+/// ```
+#[macro_export]
+macro_rules! amount {
+    ($amount:literal) => {
+        {
+            // a const item rather than a const block, as only const items are evaluated by `cargo check`
+            const AMOUNT: $crate::Amount = $crate::Amount::from_literal(stringify!($amount));
+            AMOUNT
+        }
+    };
+}
+
+/// How to round a result that doesn't fit in four decimal places.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// towards zero
+    #[default]
+    Down,
+    /// away from zero
+    Up,
+    /// to the nearest value, halfway values away from zero
+    HalfUp,
+}
+
 impl Debug for Amount {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let whole = self.0 / 10_000;
@@ -80,40 +396,142 @@ impl Debug for Amount {
     }
 }
 
+/// parse a non-negative decimal with at most 4 decimal places into a number of 0.0001 units.
+#[cfg(feature = "serde")]
+fn parse_fixed_point(s: &str) -> std::result::Result<u128, String> {
+    let (whole, cents) = if let Some((base, after)) = s.split_once('.') {
+        (base, Some(after))
+    } else {
+        (s, None)
+    };
+    let whole: u128 = whole.parse().map_err(|e| format!("{e}"))?;
+    let cents: u128 = match cents {
+        Some("") => 0,
+        Some(cents) => {
+            if cents.len() > 4 || cents.chars().any(|c| !c.is_ascii_digit()) {
+                return Err("cents must be at most 4 digits".to_string());
+            }
+
+            cents.parse::<u128>().map_err(|e| format!("{e}"))? * 10u128.pow(4 - cents.len() as u32)
+        }
+        None => 0,
+    };
+    whole
+        .checked_mul(1_00_00)
+        .and_then(|whole| whole.checked_add(cents))
+        .ok_or_else(|| "amount too large".to_string())
+}
+
+/// the longest amount without leading zeros, `1844674407370955.1615` is the largest amount there is.
+#[cfg(feature = "serde")]
+const MAX_AMOUNT_LEN: usize = 21;
+
+/// parse an amount with at most 4 decimal places.
+#[cfg(feature = "serde")]
+fn parse_amount(s: &str) -> std::result::Result<Amount, String> {
+    // bail out before parsing anything of a length no amount can have, e.g. millions of digits from a hostile feed
+    if s.trim_start_matches('0').len() > MAX_AMOUNT_LEN {
+        return Err("amount has too many digits".to_string());
+    }
+    let amount = parse_fixed_point(s)?;
+    u64::try_from(amount)
+        .map(Amount)
+        .map_err(|_| "amount too large".to_string())
+}
+
+/// parse a balance with at most 4 decimal places and an optional leading `-`.
+#[cfg(feature = "serde")]
+fn parse_balance(s: &str) -> std::result::Result<Balance, String> {
+    let (negative, magnitude) = match s.strip_prefix('-') {
+        Some(magnitude) => (true, magnitude),
+        None => (false, s),
+    };
+    // the integer parser takes a sign of its own, which would allow `-+1`
+    if negative && magnitude.starts_with('+') {
+        return Err("unexpected sign after `-`".to_string());
+    }
+    let magnitude = parse_fixed_point(magnitude)?;
+    // the magnitude of `i128::MIN` does not fit in an `i128`, so subtract it from zero
+    let balance = if negative {
+        0i128.checked_sub_unsigned(magnitude)
+    } else {
+        i128::try_from(magnitude).ok()
+    };
+    balance
+        .map(Balance)
+        .ok_or_else(|| "balance too large".to_string())
+}
+
+/// A visitor parsing a value from a string it only borrows, so deserializing amounts and balances doesn't allocate.
+#[cfg(feature = "serde")]
+struct ParseStr<F> {
+    expecting: &'static str,
+    parse: F,
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T, F> serde::de::Visitor<'de> for ParseStr<F>
+where
+    F: FnOnce(&str) -> std::result::Result<T, String>,
+{
+    type Value = T;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.expecting)
+    }
+
+    // owned and borrowed strings are passed on to this by the default implementations
+    fn visit_str<E: serde::de::Error>(self, s: &str) -> std::result::Result<T, E> {
+        (self.parse)(s).map_err(E::custom)
+    }
+}
+
 /// deserialize from a string with 4 decimal places
+#[cfg(feature = "serde")]
 impl<'de> Deserialize<'de> for Amount {
     fn deserialize<D>(deserializer: D) -> std::result::Result<Amount, D::Error>
     where
         D: Deserializer<'de>,
     {
-        let s: String = Deserialize::deserialize(deserializer)?;
-        let (whole, cents) = if let Some((base, after)) = s.split_once('.') {
-            (base, Some(after))
-        } else {
-            (s.as_str(), None)
-        };
-        let whole: u64 = whole.parse().map_err(serde::de::Error::custom)?;
-        let cents: u64 = match cents {
-            Some("") => 0,
-            Some(cents) => {
-                if cents.len() > 4 || cents.chars().any(|c| !c.is_ascii_digit()) {
-                    return Err(serde::de::Error::custom("cents must be at most 4 digits"));
-                }
+        deserializer.deserialize_str(ParseStr {
+            expecting: "an amount with at most 4 decimal places",
+            parse: parse_amount,
+        })
+    }
+}
 
-                cents.parse::<u64>().map_err(serde::de::Error::custom)?
-                    * 10u64.pow(4 - cents.len() as u32)
-            }
-            None => 0,
-        };
-        whole
-            .checked_mul(1_00_00)
-            .and_then(|whole| whole.checked_add(cents))
-            .map(Amount)
-            .ok_or_else(|| serde::de::Error::custom("amount too large"))
+#[cfg(feature = "serde")]
+impl Amount {
+    /// deserialize like the [`Deserialize`] implementation, but reject amounts without exactly four decimal places
+    /// such as `100`, `100.`, or `1.5`.
+    ///
+    /// for strict feeds that always send the four decimal form, use it with `#[serde(deserialize_with = "Amount::deserialize_strict")]`.
+    /// the CSV input checks its amounts the same way with [`ReadOptions::strict_amounts`](ingest::ReadOptions::strict_amounts).
+    pub fn deserialize_strict<'de, D>(deserializer: D) -> std::result::Result<Amount, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(ParseStr {
+            expecting: "an amount with 4 decimal places",
+            parse: |s: &str| {
+                check_four_decimals(s)?;
+                parse_amount(s)
+            },
+        })
+    }
+}
+
+/// fails unless the decimal has exactly four decimal places, e.g. `1.5000` but not `1.5` or `1`.
+#[cfg(feature = "serde")]
+pub(crate) fn check_four_decimals(s: &str) -> std::result::Result<(), String> {
+    match s.split_once('.') {
+        Some((_, cents)) if cents.len() == 4 => Ok(()),
+        _ => Err(format!("amount `{s}` must have exactly 4 decimal places")),
     }
 }
 
 /// serialize as a string with 4 decimal places
+#[cfg(feature = "serde")]
 impl Serialize for Amount {
     fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
     where
@@ -124,12 +542,104 @@ impl Serialize for Amount {
     }
 }
 
+/// An amount that can be negative, e.g. of a manual adjustment, which can credit or debit an account.
+/// stored as a sign and an [`Amount`], so `-0` is the same as `0`.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct SignedAmount {
+    negative: bool,
+    magnitude: Amount,
+}
+
+impl SignedAmount {
+    /// a positive amount, which is added to a balance.
+    pub const fn credit(amount: Amount) -> Self {
+        Self {
+            negative: false,
+            magnitude: amount,
+        }
+    }
+
+    /// a negative amount, which is subtracted from a balance.
+    pub const fn debit(amount: Amount) -> Self {
+        Self {
+            negative: amount.0 != 0,
+            magnitude: amount,
+        }
+    }
+
+    /// whether the amount is below zero.
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    /// the amount without its sign.
+    pub fn magnitude(&self) -> Amount {
+        self.magnitude
+    }
+}
+
+impl Debug for SignedAmount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.negative { "-" } else { "" };
+        f.write_fmt(format_args!("{}{:?}", sign, self.magnitude))
+    }
+}
+
+/// parse an amount with at most 4 decimal places and an optional leading `-`.
+#[cfg(feature = "serde")]
+fn parse_signed_amount(s: &str) -> std::result::Result<SignedAmount, String> {
+    match s.strip_prefix('-') {
+        // the integer parser takes a sign of its own, which would allow `-+1`
+        Some(magnitude) if magnitude.starts_with('+') => {
+            Err("unexpected sign after `-`".to_string())
+        }
+        Some(magnitude) => parse_amount(magnitude).map(SignedAmount::debit),
+        None => parse_amount(s).map(SignedAmount::credit),
+    }
+}
+
+/// deserialize from a string with 4 decimal places, optionally starting with `-`
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for SignedAmount {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<SignedAmount, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(ParseStr {
+            expecting: "an amount with at most 4 decimal places",
+            parse: parse_signed_amount,
+        })
+    }
+}
+
 /// a balance of funds in an account.
 /// A decimal with 4 digits of precision which can go negative.
-#[derive(Default, Clone, Copy)]
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
 pub struct Balance(i128);
 
 impl Balance {
+    /// a balance of zero.
+    pub const fn zero() -> Self {
+        Self(0)
+    }
+
+    /// a balance in minor units, i.e. in 0.0001ths, `Balance::from_raw(-12345)` is `-1.2345`.
+    #[cfg(feature = "test-util")]
+    pub const fn from_raw(units: i128) -> Self {
+        Self(units)
+    }
+
+    /// whether the balance differs from `other` by at most `tolerance`, e.g. to compare against a value from an external
+    /// system that rounded it. balances computed by this crate are exact, so compare those with `==`.
+    pub fn approx_eq(self, other: Balance, tolerance: Amount) -> bool {
+        self.0.abs_diff(other.0) <= tolerance.0 as u128
+    }
+
+    /// whether the balance is below zero, e.g. the available funds of a client whose deposit was disputed after a withdrawal.
+    pub const fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
     /// try to add an amount to the balance, returning an error if it would overflow.
     /// returns the new balance if successful (it does not modify the original balance).
     #[must_use = "this returns the new balance, it does not modify the original balance"]
@@ -148,16 +658,110 @@ impl Balance {
             .ok_or(Error::Underflow)
             .map(Self)
     }
+    /// add an amount to the balance, clamping at the largest representable balance instead of overflowing.
+    /// only meant for display purposes, use [`Balance::try_add`] when updating a client.
+    #[must_use = "this returns the new balance, it does not modify the original balance"]
+    pub fn saturating_add(self, other: Amount) -> Self {
+        Self(self.0.saturating_add_unsigned(other.0 as u128))
+    }
+    /// subtract an amount from the balance, clamping at the smallest representable balance instead of underflowing.
+    /// only meant for display purposes, use [`Balance::try_sub`] when updating a client.
+    #[must_use = "this returns the new balance, it does not modify the original balance"]
+    pub fn saturating_sub(self, other: Amount) -> Self {
+        Self(self.0.saturating_sub_unsigned(other.0 as u128))
+    }
+    /// format the balance with `decimals` decimal places, e.g. `1.23` for `1.2345` with two decimals rounded down.
+    /// only meant for output, the balance itself keeps its four decimals. more than four decimals are padded with zeros.
+    pub fn format_rounded(self, decimals: u32, rounding: RoundingMode) -> String {
+        let magnitude = self.0.unsigned_abs();
+        if decimals >= 4 {
+            let sign = if self.0 < 0 { "-" } else { "" };
+            let padding = "0".repeat(decimals as usize - 4);
+            return format!(
+                "{sign}{}.{:04}{padding}",
+                magnitude / 10_000,
+                magnitude % 10_000
+            );
+        }
+        let divisor = 10u128.pow(4 - decimals);
+        let (quotient, remainder) = (magnitude / divisor, magnitude % divisor);
+        let rounded = match rounding {
+            RoundingMode::Down => quotient,
+            RoundingMode::Up if remainder > 0 => quotient + 1,
+            RoundingMode::Up => quotient,
+            RoundingMode::HalfUp if remainder * 2 >= divisor => quotient + 1,
+            RoundingMode::HalfUp => quotient,
+        };
+        // a balance that rounds to zero has no sign, `-0.00` would only confuse a reader
+        let sign = if self.0 < 0 && rounded != 0 { "-" } else { "" };
+        let scale = 10u128.pow(decimals);
+        match decimals {
+            0 => format!("{sign}{rounded}"),
+            decimals => format!(
+                "{sign}{}.{:0width$}",
+                rounded / scale,
+                rounded % scale,
+                width = decimals as usize
+            ),
+        }
+    }
+}
+
+/// Adds an amount to a balance.
+///
+/// # Panics
+/// panics if the balance overflows, like the integer operators do in debug builds.
+/// [`Balance::try_add`] is the safe alternative and is what the crate itself uses.
+#[cfg(feature = "ops")]
+impl std::ops::Add<Amount> for Balance {
+    type Output = Balance;
+
+    fn add(self, rhs: Amount) -> Balance {
+        self.try_add(rhs)
+            .expect("overflow adding an amount to a balance")
+    }
+}
+
+/// Subtracts an amount from a balance.
+///
+/// # Panics
+/// panics if the balance underflows, like the integer operators do in debug builds.
+/// [`Balance::try_sub`] is the safe alternative and is what the crate itself uses.
+#[cfg(feature = "ops")]
+impl std::ops::Sub<Amount> for Balance {
+    type Output = Balance;
+
+    fn sub(self, rhs: Amount) -> Balance {
+        self.try_sub(rhs)
+            .expect("underflow subtracting an amount from a balance")
+    }
 }
 
 impl Debug for Balance {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let whole = self.0 / 10_000;
-        let cents = self.0 % 10_000;
-        f.write_fmt(format_args!("{}.{:04}", whole, cents))
+        // format the magnitude and sign separately, otherwise both parts carry a sign (`-0.5` would become `0.-5000`)
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let whole = self.0.unsigned_abs() / 10_000;
+        let cents = self.0.unsigned_abs() % 10_000;
+        f.write_fmt(format_args!("{}{}.{:04}", sign, whole, cents))
     }
 }
 
+/// deserialize from a string with 4 decimal places, which may be negative
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Balance {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Balance, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(ParseStr {
+            expecting: "a balance with at most 4 decimal places",
+            parse: parse_balance,
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
 impl Serialize for Balance {
     fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
     where
@@ -168,10 +772,67 @@ impl Serialize for Balance {
     }
 }
 
+/// Why a [`rust_decimal::Decimal`] is not a valid [`Amount`].
+#[cfg(feature = "rust_decimal")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum DecimalError {
+    /// amounts can't be negative
+    #[error("amount is negative")]
+    Negative,
+    /// amounts have at most four decimal places
+    #[error("amount has more than 4 decimal places")]
+    TooPrecise,
+    /// the amount does not fit in an [`Amount`]
+    #[error("amount too large")]
+    TooLarge,
+}
+
+#[cfg(feature = "rust_decimal")]
+impl Amount {
+    /// the amount as a decimal with four decimal places, e.g. for accounting libraries that use `rust_decimal`.
+    pub fn to_decimal(self) -> rust_decimal::Decimal {
+        // a u64 always fits in the 96 bits of a decimal
+        rust_decimal::Decimal::from_i128_with_scale(self.0 as i128, 4)
+    }
+}
+
+#[cfg(feature = "rust_decimal")]
+impl TryFrom<rust_decimal::Decimal> for Amount {
+    type Error = DecimalError;
+
+    /// fails if the decimal is negative or has more than four (non-zero) decimal places.
+    fn try_from(decimal: rust_decimal::Decimal) -> std::result::Result<Self, Self::Error> {
+        if decimal.is_sign_negative() && !decimal.is_zero() {
+            return Err(DecimalError::Negative);
+        }
+        // trailing zeros don't count, `1.00000` is `1.0000`
+        let decimal = decimal.normalize();
+        if decimal.scale() > 4 {
+            return Err(DecimalError::TooPrecise);
+        }
+        decimal
+            .mantissa()
+            .unsigned_abs()
+            .checked_mul(10u128.pow(4 - decimal.scale()))
+            .and_then(|units| u64::try_from(units).ok())
+            .map(Amount)
+            .ok_or(DecimalError::TooLarge)
+    }
+}
+
+#[cfg(feature = "rust_decimal")]
+impl Balance {
+    /// the balance as a decimal with four decimal places, or `None` if it is too large for a decimal (over 96 bits).
+    pub fn to_decimal(self) -> Option<rust_decimal::Decimal> {
+        rust_decimal::Decimal::try_from_i128_with_scale(self.0, 4).ok()
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Amount;
+    use super::{Amount, Balance};
     /// ensure the amount in a transaction is always positive, to prevent someone withdrawing negative funds
+    #[cfg(feature = "serde")]
     #[test]
     fn amount_positive() {
         let entry = "amount\n-1.00\n-0.001\n0.-5";
@@ -187,6 +848,7 @@ mod tests {
     }
 
     /// ensure the amount in a transaction has at most 4 decimal places
+    #[cfg(feature = "serde")]
     #[test]
     fn amount_precision() {
         let entry = "amount
@@ -209,4 +871,333 @@ mod tests {
         assert!(records.next().is_some_and(|x| x.is_err()));
         assert!(records.next().is_none());
     }
+
+    /// ensure integer amounts are only accepted by the lenient deserializer
+    #[cfg(feature = "serde")]
+    #[test]
+    fn amount_strict() {
+        #[derive(serde::Deserialize)]
+        struct Strict {
+            #[serde(deserialize_with = "Amount::deserialize_strict")]
+            amount: Amount,
+        }
+        let lenient = |s: &str| serde_json::from_str::<Amount>(&format!("\"{s}\""));
+        let strict = |s: &str| serde_json::from_str::<Strict>(&format!("{{\"amount\":\"{s}\"}}"));
+        assert!(lenient("100").is_ok_and(|amount| amount == Amount(100_0000)));
+        assert!(strict("100").is_err());
+        assert!(strict("100.").is_err());
+        assert!(strict("1.5").is_err());
+        assert!(lenient("100.0000").is_ok_and(|amount| amount == Amount(100_0000)));
+        assert!(strict("100.0000").is_ok_and(|strict| strict.amount == Amount(100_0000)));
+        // the other checks still apply
+        assert!(strict("100.00000").is_err());
+        assert!(strict("-1.0").is_err());
+    }
+
+    /// ensure basis points are rounded as requested and never exceed the amount
+    #[test]
+    fn basis_points() {
+        use super::RoundingMode;
+        assert_eq!(
+            Amount(100_0000).basis_points(25, RoundingMode::Down),
+            Amount(2500)
+        );
+        // 1 bps of 0.0001 is 0.00000001
+        assert_eq!(Amount(1).basis_points(1, RoundingMode::Down), Amount(0));
+        assert_eq!(Amount(1).basis_points(1, RoundingMode::Up), Amount(1));
+        assert_eq!(Amount(1).basis_points(1, RoundingMode::HalfUp), Amount(0));
+        assert_eq!(
+            Amount(1).basis_points(5_000, RoundingMode::HalfUp),
+            Amount(1)
+        );
+        assert_eq!(Amount(3).basis_points(20_000, RoundingMode::Up), Amount(3));
+        assert_eq!(
+            Amount(u64::MAX).basis_points(10_000, RoundingMode::Down),
+            Amount(u64::MAX)
+        );
+    }
+
+    /// ensure the saturating operations clamp at both extremes
+    #[test]
+    fn balance_saturating() {
+        assert!(Balance(1).saturating_add(Amount(2)) == Balance(3));
+        assert!(Balance(1).saturating_sub(Amount(2)) == Balance(-1));
+        assert!(Balance(i128::MAX - 1).saturating_add(Amount(u64::MAX)) == Balance(i128::MAX));
+        assert!(Balance(i128::MIN + 1).saturating_sub(Amount(u64::MAX)) == Balance(i128::MIN));
+    }
+
+    /// ensure the operators add and subtract amounts
+    #[cfg(feature = "ops")]
+    #[test]
+    fn balance_ops() {
+        assert!(Balance(1) + Amount(2) == Balance(3));
+        assert!(Balance(1) - Amount(2) == Balance(-1));
+    }
+
+    /// ensure the operators panic instead of overflowing
+    #[cfg(feature = "ops")]
+    #[test]
+    #[should_panic(expected = "overflow adding an amount to a balance")]
+    fn balance_ops_overflow() {
+        let _ = Balance(i128::MAX) + Amount(1);
+    }
+
+    /// ensure the named zero constructors match the default values
+    #[test]
+    fn zero() {
+        assert_eq!(Amount::zero(), Amount::default());
+        assert_eq!(Balance::zero(), Balance::default());
+    }
+
+    /// ensure only balances below zero are negative
+    #[test]
+    fn balance_negative() {
+        assert!(Balance(-1).is_negative());
+        assert!(!Balance::zero().is_negative());
+        assert!(!Balance(1).is_negative());
+    }
+
+    /// ensure balances parse back from their serialized form, including negative balances
+    #[cfg(feature = "serde")]
+    #[test]
+    fn balance_roundtrip() {
+        for balance in [
+            0,
+            1,
+            -1,
+            12_3456,
+            -12_3456,
+            i128::MAX,
+            i128::MIN + 1,
+            i128::MIN,
+        ] {
+            let serialized = format!("{:?}", Balance(balance));
+            let parsed: Balance = serde_json::from_value(serialized.clone().into()).unwrap();
+            assert!(parsed == Balance(balance), "{serialized}");
+        }
+        assert!(serde_json::from_value::<Balance>("1.00001".into()).is_err());
+        assert!(serde_json::from_value::<Balance>("--1".into()).is_err());
+        // a sign after the sign is rejected, and only the minimum goes one beyond the maximum
+        assert!(serde_json::from_value::<Balance>("-+1".into()).is_err());
+        for beyond in [
+            "17014118346046923173168730371588410.5728",
+            "-17014118346046923173168730371588410.5729",
+        ] {
+            assert!(
+                serde_json::from_value::<Balance>(beyond.into()).is_err(),
+                "{beyond}"
+            );
+        }
+    }
+
+    /// ensure amounts and balances convert to decimals and back, and decimals that aren't amounts are rejected
+    #[cfg(feature = "rust_decimal")]
+    #[test]
+    fn decimal() {
+        use super::DecimalError;
+        use rust_decimal::Decimal;
+        use std::str::FromStr;
+
+        let amount = Amount(1_2345);
+        assert_eq!(amount.to_decimal().to_string(), "1.2345");
+        assert_eq!(Amount::try_from(amount.to_decimal()), Ok(amount));
+        assert_eq!(
+            Amount::try_from(Decimal::from_str("1.2").unwrap()),
+            Ok(Amount(1_2000))
+        );
+        assert_eq!(
+            Amount::try_from(Decimal::from_str("1.23450").unwrap()),
+            Ok(amount)
+        );
+        assert_eq!(
+            Amount::try_from(Decimal::from_str("-0").unwrap()),
+            Ok(Amount(0))
+        );
+        assert_eq!(
+            Amount::try_from(Decimal::from_str("1.23456").unwrap()),
+            Err(DecimalError::TooPrecise)
+        );
+        assert_eq!(
+            Amount::try_from(Decimal::from_str("-1").unwrap()),
+            Err(DecimalError::Negative)
+        );
+        assert_eq!(
+            Amount::try_from(Decimal::from(u64::MAX)),
+            Err(DecimalError::TooLarge)
+        );
+        assert_eq!(
+            Amount(u64::MAX).to_decimal().to_string(),
+            "1844674407370955.1615"
+        );
+
+        assert_eq!(
+            Balance(-5000)
+                .to_decimal()
+                .map(|decimal| decimal.to_string()),
+            Some("-0.5000".to_string())
+        );
+        assert_eq!(Balance(i128::MAX).to_decimal(), None);
+    }
+
+    /// ensure amounts and balances parse the same from borrowed and owned strings, and from CSV fields
+    #[cfg(feature = "serde")]
+    #[test]
+    fn borrowed_parser() {
+        let inputs = [
+            "0",
+            "1",
+            "1.",
+            "1.5",
+            "1.2345",
+            "1.23456",
+            "-1",
+            "--1",
+            ".5",
+            "",
+            "1.2.3",
+            "1e3",
+            " 1",
+            "+1",
+            "18446744073709551615",
+            "1844674407370955.1615",
+            "1844674407370955.1616",
+        ];
+        for input in inputs {
+            let borrowed = serde_json::from_str::<Amount>(&format!("\"{input}\"")).ok();
+            let owned = serde_json::from_value::<Amount>(input.into()).ok();
+            let expected = super::parse_amount(input).ok();
+            assert_eq!(borrowed, expected, "{input}");
+            assert_eq!(owned, expected, "{input}");
+
+            let borrowed = serde_json::from_str::<Balance>(&format!("\"{input}\"")).ok();
+            let owned = serde_json::from_value::<Balance>(input.into()).ok();
+            assert_eq!(borrowed, owned, "{input}");
+            assert_eq!(borrowed, super::parse_balance(input).ok(), "{input}");
+        }
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader("1.5,\n1.23456,\n".as_bytes());
+        let amounts = reader
+            .deserialize::<(Amount, Option<Amount>)>()
+            .map(|record| record.ok())
+            .collect::<Vec<_>>();
+        assert_eq!(amounts, [Some((Amount(1_5000), None)), None]);
+        // anything but a string is still rejected
+        assert!(serde_json::from_str::<Amount>("1.5").is_err());
+    }
+
+    /// ensure signed amounts parse like amounts, with an optional leading `-`
+    #[cfg(feature = "serde")]
+    #[test]
+    fn signed_amount() {
+        use super::SignedAmount;
+
+        let parse = |s: &str| super::parse_signed_amount(s).ok();
+        assert_eq!(parse("1.5"), Some(SignedAmount::credit(Amount(1_5000))));
+        assert_eq!(parse("-1.5"), Some(SignedAmount::debit(Amount(1_5000))));
+        assert_eq!(parse("-0"), Some(SignedAmount::credit(Amount(0))));
+        assert!(!SignedAmount::debit(Amount(0)).is_negative());
+        assert_eq!(parse("--1"), None);
+        assert_eq!(parse("-+1"), None);
+        assert_eq!(parse("-+0.5"), None);
+        assert_eq!(parse("+-1"), None);
+        assert_eq!(parse("-"), None);
+        assert_eq!(parse("- 1"), None);
+        assert_eq!(parse("-1.23456"), None);
+        assert_eq!(parse("-18446744073709551616"), None);
+        assert_eq!(
+            format!("{:?}", SignedAmount::debit(Amount(1_5000))),
+            "-1.5000"
+        );
+        let json = serde_json::from_str::<SignedAmount>("\"-2.25\"").unwrap();
+        assert_eq!(json, SignedAmount::debit(Amount(2_2500)));
+    }
+
+    /// ensure balances are rounded to fewer decimals as configured, and padded to more
+    #[test]
+    fn format_rounded() {
+        use super::RoundingMode;
+
+        let balance = Balance(1_2345);
+        assert_eq!(balance.format_rounded(2, RoundingMode::Down), "1.23");
+        assert_eq!(balance.format_rounded(2, RoundingMode::Up), "1.24");
+        assert_eq!(balance.format_rounded(2, RoundingMode::HalfUp), "1.23");
+        assert_eq!(
+            Balance(1_2350).format_rounded(2, RoundingMode::HalfUp),
+            "1.24"
+        );
+        assert_eq!(
+            Balance(-1_2350).format_rounded(2, RoundingMode::HalfUp),
+            "-1.24"
+        );
+        assert_eq!(
+            Balance(-1_2350).format_rounded(2, RoundingMode::Down),
+            "-1.23"
+        );
+        assert_eq!(Balance(9_9999).format_rounded(2, RoundingMode::Up), "10.00");
+        assert_eq!(Balance(-1).format_rounded(2, RoundingMode::Down), "0.00");
+        assert_eq!(Balance(1_5000).format_rounded(0, RoundingMode::HalfUp), "2");
+        assert_eq!(balance.format_rounded(6, RoundingMode::Down), "1.234500");
+        for value in [0, 1, -1, 1_2345, -1_2345, i128::MAX, i128::MIN] {
+            assert_eq!(
+                Balance(value).format_rounded(4, RoundingMode::Up),
+                format!("{:?}", Balance(value))
+            );
+        }
+    }
+
+    /// ensure over-long amounts are rejected by their length, before their digits are parsed
+    #[cfg(feature = "serde")]
+    #[test]
+    fn amount_too_long() {
+        let long = "9".repeat(10_000);
+        let start = std::time::Instant::now();
+        let err = serde_json::from_str::<Amount>(&format!("\"{long}\"")).unwrap_err();
+        assert!(err.to_string().contains("too many digits"), "{err}");
+        let record = format!("{long}.5\n");
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(record.as_bytes());
+        let err = reader.deserialize::<Amount>().next().unwrap().unwrap_err();
+        assert!(err.to_string().contains("too many digits"), "{err}");
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+
+        // the largest amount, with or without leading zeros, is still read
+        for max in ["1844674407370955.1615", "0001844674407370955.1615"] {
+            assert_eq!(super::parse_amount(max), Ok(Amount(u64::MAX)));
+        }
+        assert_eq!(
+            super::parse_amount("1844674407370955.1616"),
+            Err("amount too large".to_string())
+        );
+    }
+
+    /// ensure balances are approximately equal within the tolerance in either direction, and not outside of it
+    #[test]
+    fn approx_eq() {
+        let tolerance = Amount(1);
+        assert!(Balance(2_0000).approx_eq(Balance(2_0000), Amount::zero()));
+        assert!(Balance(2_0000).approx_eq(Balance(2_0001), tolerance));
+        assert!(Balance(2_0000).approx_eq(Balance(1_9999), tolerance));
+        assert!(!Balance(2_0000).approx_eq(Balance(2_0002), tolerance));
+        assert!(!Balance(2_0000).approx_eq(Balance(2_0001), Amount::zero()));
+        assert!(Balance(-1).approx_eq(Balance(0), tolerance));
+        // the difference of the extremes doesn't fit in a balance
+        assert!(!Balance(i128::MIN).approx_eq(Balance(i128::MAX), Amount(u64::MAX)));
+    }
+
+    /// ensure the amount macro accepts literals with up to four decimal places, with or without a decimal point
+    #[test]
+    fn amount_macro() {
+        assert_eq!(amount!(1.2345), Amount(1_2345));
+        assert_eq!(amount!(1.5), Amount(1_5000));
+        assert_eq!(amount!(0.0001), Amount(1));
+        assert_eq!(amount!(100), Amount(100_0000));
+        assert_eq!(amount!(1_000.25), Amount(1000_2500));
+        assert_eq!(amount!(0), Amount::zero());
+        assert_eq!(amount!(1844674407370955.1615), Amount(u64::MAX));
+        // also usable in constants
+        const FEE: Amount = amount!(0.5);
+        assert_eq!(FEE, Amount(5000));
+    }
 }
@@ -1,42 +1,338 @@
-use ledger::{actions::AccountAction, database::Database};
-use std::{fs::File, io::BufReader};
+use ledger::{
+    config::Config,
+    database::{ClientWithId, Database},
+    ingest::{records, IngestSummary, ReadOptions},
+    output::{write_disputes, FixedWidth, Precision, RoundedClient, CLIENT_COLUMNS},
+    reconcile::Reconciliation,
+    Balance, Error, RoundingMode,
+};
+use std::fs::File;
 
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        eprintln!("usage: {} <input.csv>", args[0]);
-        std::process::exit(1);
-    }
-    let path = &args[1];
-    let reader = BufReader::new(File::open(path).expect("failed to open file"));
-    let mut reader = csv::ReaderBuilder::new()
-        // we have headers in the CSV
-        .has_headers(true)
-        // allow for comments in the CSV using #
-        .comment(Some(b'#'))
-        // dispute, resolve, and chargeback actions don't have an amount field
-        .flexible(true)
-        // allow for whitespaces in the CSV
-        .trim(csv::Trim::All)
-        .from_reader(reader);
+/// The subcommands of the binary.
+enum Command {
+    /// apply all records and print the resulting client balances as CSV
+    Process,
+    /// only parse the records, reporting any that are malformed
+    Validate,
+    /// apply all records and print aggregate totals and counts
+    Stats,
+    /// apply all records and check the client totals add up to the amounts in the input
+    Reconcile,
+}
 
-    let mut db = Database::new();
-    for (n, record) in reader.deserialize::<AccountAction>().enumerate() {
-        match record {
-            Err(e) => {
-                eprintln!("failed to deserialize record {n}: {e}");
+/// The flags that can be passed to every subcommand.
+#[derive(Default)]
+struct Options {
+    /// how to read the input file
+    read: ReadOptions,
+    /// only output clients that have a nonzero balance or are locked
+    nonzero: bool,
+    /// write the clients as fixed-width columns instead of CSV
+    fixed_width: bool,
+    /// write the clients in the order they first appeared in the input instead of ordered by id
+    input_order: bool,
+    /// also write a report of the disputed deposits to this file
+    disputes_output: Option<String>,
+    /// how to quote and escape the fields of the CSV output, for strict consumers
+    write: csv::WriterBuilder,
+    /// the decimals the balances of the clients are written with
+    precision: Precision,
+    /// don't print a summary of the ingested records once the clients are written
+    quiet: bool,
+    /// flag deposits with the same client and amount as one at most this many records earlier
+    duplicate_deposit_window: Option<usize>,
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!("usage: {program} [process|validate|stats|reconcile] [--no-header] [--minor-units] [--decimal-comma] [--strict-amounts] [--delimiter <char>] [--limit <n>] [--max-records <n>] [--max-bytes <n>] [--nonzero] [--fixed-width] [--input-order] [--duplicate-window <n>] [--quiet] [--disputes-output <path>] [--decimals <n>] [--rounding down|up|half-up] [--quote always|necessary|non-numeric|never] [--quote-char <char>] [--escape <char>] [--comment <char>|none] [--ignore-type <type>]... [--unknown-types-unhandled] <input.csv>");
+    std::process::exit(1);
+}
+
+fn parse_args() -> (Command, Options, String) {
+    let args: Vec<String> = std::env::args().collect();
+    let program = args.first().map(String::as_str).unwrap_or("ledger");
+    let mut options = Options::default();
+    let mut positional = Vec::new();
+    let mut args_iter = args[1..].iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--no-header" => options.read.has_headers = false,
+            "--minor-units" => options.read.minor_units = true,
+            "--decimal-comma" => options.read.decimal_comma = true,
+            "--strict-amounts" => options.read.strict_amounts = true,
+            "--unknown-types-unhandled" => options.read.unknown_types_unhandled = true,
+            "--delimiter" => {
+                options.read.delimiter = match args_iter.next().map(String::as_bytes) {
+                    Some([byte]) if byte.is_ascii() => *byte,
+                    _ => usage(program),
+                }
+            }
+            "--limit" => match args_iter.next().map(|n| n.parse()) {
+                Some(Ok(n)) => options.read.limit = Some(n),
+                _ => usage(program),
+            },
+            "--max-records" => match args_iter.next().map(|n| n.parse()) {
+                Some(Ok(n)) => options.read.max_records = Some(n),
+                _ => usage(program),
+            },
+            "--max-bytes" => match args_iter.next().map(|n| n.parse()) {
+                Some(Ok(n)) => options.read.max_bytes = Some(n),
+                _ => usage(program),
+            },
+            "--duplicate-window" => match args_iter.next().map(|n| n.parse()) {
+                Some(Ok(n)) => options.duplicate_deposit_window = Some(n),
+                _ => usage(program),
+            },
+            "--decimals" => match args_iter.next().map(|n| n.parse()) {
+                Some(Ok(n)) => options.precision.decimals = n,
+                _ => usage(program),
+            },
+            "--rounding" => {
+                options.precision.rounding = match args_iter.next().map(String::as_str) {
+                    Some("down") => RoundingMode::Down,
+                    Some("up") => RoundingMode::Up,
+                    Some("half-up") => RoundingMode::HalfUp,
+                    _ => usage(program),
+                }
             }
-            Ok(action) => {
-                if let Err(e) = db.perform_action(action) {
-                    eprintln!("failed to perform action {n}: {e}");
+            "--nonzero" => options.nonzero = true,
+            "--fixed-width" => options.fixed_width = true,
+            "--input-order" => options.input_order = true,
+            "--quiet" => options.quiet = true,
+            "--quote" => {
+                let style = match args_iter.next().map(String::as_str) {
+                    Some("always") => csv::QuoteStyle::Always,
+                    Some("necessary") => csv::QuoteStyle::Necessary,
+                    Some("non-numeric") => csv::QuoteStyle::NonNumeric,
+                    Some("never") => csv::QuoteStyle::Never,
+                    _ => usage(program),
+                };
+                options.write.quote_style(style);
+            }
+            "--quote-char" => match args_iter.next().map(String::as_bytes) {
+                Some([byte]) if byte.is_ascii() => {
+                    options.write.quote(*byte);
+                }
+                _ => usage(program),
+            },
+            // quotes in fields are doubled by default, an escape character is used instead
+            "--escape" => match args_iter.next().map(String::as_bytes) {
+                Some([byte]) if byte.is_ascii() => {
+                    options.write.double_quote(false).escape(*byte);
+                }
+                _ => usage(program),
+            },
+            "--disputes-output" => match args_iter.next() {
+                Some(path) => options.disputes_output = Some(path.clone()),
+                None => usage(program),
+            },
+            "--comment" => {
+                options.read.comment = match args_iter.next().map(String::as_bytes) {
+                    Some(b"none") => None,
+                    // the reader only supports a single byte, so `//` comments are matched by their first `/`
+                    Some([byte, ..]) if byte.is_ascii() => Some(*byte),
+                    _ => usage(program),
                 }
             }
+            "--ignore-type" => match args_iter.next() {
+                Some(kind) => options.read.ignore_types.push(kind.clone()),
+                None => usage(program),
+            },
+            flag if flag.starts_with("--") => usage(program),
+            _ => positional.push(arg.clone()),
+        }
+    }
+    let (command, path) = match positional.as_slice() {
+        [command, path] => {
+            let command = match command.as_str() {
+                "process" => Command::Process,
+                "validate" => Command::Validate,
+                "stats" => Command::Stats,
+                "reconcile" => Command::Reconcile,
+                _ => usage(program),
+            };
+            (command, path.clone())
+        }
+        // without a subcommand we process the file, as before subcommands existed
+        [path] => (Command::Process, path.clone()),
+        _ => usage(program),
+    };
+    (command, options, path)
+}
+
+fn open(path: &str) -> File {
+    File::open(path).expect("failed to open file")
+}
+
+fn apply(path: &str, options: &Options, db: &mut Database) -> IngestSummary {
+    // without a header row there is nothing to tell an empty file from a file without records apart
+    if options.read.has_headers && std::fs::metadata(path).is_ok_and(|metadata| metadata.len() == 0)
+    {
+        eprintln!("{path} is empty, expected at least a header row");
+    }
+    let summary = db.ingest(open(path), &options.read);
+    for (n, e) in summary.all_errors() {
+        match e {
+            Error::Parse(e) | Error::InvalidRecord { message: e, .. } => {
+                eprintln!("failed to deserialize record {n}: {e}")
+            }
+            Error::InputTooLarge => eprintln!("stopped at record {n}: {e}"),
+            e => eprintln!("{e}"),
+        }
+    }
+    for (kind, count) in &summary.unhandled {
+        eprintln!("skipped {count} records of unhandled type {kind}");
+    }
+    summary
+}
+
+fn process(path: &str, options: &Options) {
+    let mut db = Database::with_config(Config {
+        track_insertion_order: options.input_order,
+        duplicate_deposit_window: options.duplicate_deposit_window,
+        ..Config::default()
+    });
+    let summary = apply(path, options, &mut db);
+    if let Some(disputes) = &options.disputes_output {
+        let file = File::create(disputes).expect("failed to create disputes file");
+        let mut w = options.write.from_writer(file);
+        if let Err(e) = write_disputes(&mut w, db.disputed_deposits()).and_then(|()| w.flush()) {
+            panic!("failed to write disputes report: {e}");
+        }
+    }
+    let clients: Box<dyn Iterator<Item = ClientWithId>> = if options.input_order {
+        Box::new(db.clients_in_insertion_order())
+    } else {
+        Box::new(db.clients())
+    };
+    let clients = clients.filter(|client| {
+        !options.nonzero
+            || client.is_locked()
+            || client.available() != Balance::zero()
+            || client.held() != Balance::zero()
+            || client.total() != Balance::zero()
+    });
+    if options.fixed_width {
+        let format = FixedWidth {
+            precision: options.precision,
+            ..FixedWidth::default()
+        };
+        if let Err(e) = format.write(std::io::stdout().lock(), clients) {
+            panic!("failed to serialize client database: {e}");
         }
+    } else {
+        write_csv(clients, options);
     }
-    let mut wtr = csv::Writer::from_writer(std::io::stdout());
-    for client in db.clients() {
+    if !options.quiet {
+        eprintln!("{summary}");
+    }
+}
+
+fn write_csv<'a>(clients: impl Iterator<Item = ClientWithId<'a>>, options: &Options) {
+    let mut wtr = options.write.from_writer(std::io::stdout());
+    let mut empty = true;
+    for client in clients {
+        let client = RoundedClient {
+            client,
+            precision: options.precision,
+        };
         if let Err(e) = wtr.serialize(client) {
             panic!("failed to serialize client database: {e}");
         }
+        empty = false;
+    }
+    // the header is only written along with the first client, but an empty result should still have one
+    if empty {
+        if let Err(e) = wtr.write_record(CLIENT_COLUMNS) {
+            panic!("failed to serialize client database: {e}");
+        }
+    }
+}
+
+/// returns whether all records were valid.
+fn validate(path: &str, options: &Options) -> bool {
+    let mut valid = true;
+    for (n, record) in records(open(path), &options.read).enumerate() {
+        if let Err(e) = record {
+            valid = false;
+            eprintln!("failed to deserialize record {n}: {e}");
+        }
+    }
+    valid
+}
+
+fn stats(path: &str, options: &Options) {
+    let mut db = Database::new();
+    let summary = apply(path, options, &mut db);
+    let totals = match db.totals() {
+        Ok(totals) => totals,
+        Err(e) => {
+            eprintln!("failed to sum the client balances: {e}");
+            std::process::exit(1);
+        }
+    };
+    println!("records: {}", summary.records);
+    println!("invalid records: {}", summary.invalid);
+    println!("failed actions: {}", summary.failed);
+    println!("ignored records: {}", summary.ignored);
+    println!(
+        "unhandled records: {}",
+        summary.unhandled.values().sum::<usize>()
+    );
+    println!("clients: {}", db.clients().count());
+    println!("locked clients: {}", db.locked_count());
+    println!("cancelled disputes: {}", db.cancelled_disputes());
+    println!(
+        "negative total clients: {}",
+        db.negative_total_clients().count()
+    );
+    println!("available: {:?}", totals.available);
+    println!("held: {:?}", totals.held);
+    println!("total: {:?}", totals.total);
+    for (reason, count) in db.dispute_reasons() {
+        println!("disputes with reason {}: {count}", reason.as_str());
+    }
+}
+
+/// returns whether the client totals match the input.
+fn reconcile(path: &str, options: &Options) -> bool {
+    let mut db = Database::new();
+    let mut reconciliation = Reconciliation::new(db.config());
+    for (n, record) in records(open(path), &options.read).enumerate() {
+        match record {
+            Err(e) => eprintln!("failed to deserialize record {n}: {e}"),
+            Ok(action) => match db.apply_action_at(n, &action) {
+                Ok(()) => reconciliation.record(&action, &db),
+                Err(e) => eprintln!("{e}"),
+            },
+        }
+    }
+    match reconciliation.check(&db) {
+        Ok(()) => {
+            println!("reconciled: {:?}", reconciliation.expected_total());
+            true
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            false
+        }
+    }
+}
+
+fn main() {
+    let (command, options, path) = parse_args();
+    match command {
+        Command::Process => process(&path, &options),
+        Command::Validate => {
+            if !validate(&path, &options) {
+                std::process::exit(1);
+            }
+        }
+        Command::Stats => stats(&path, &options),
+        Command::Reconcile => {
+            if !reconcile(&path, &options) {
+                std::process::exit(1);
+            }
+        }
     }
 }
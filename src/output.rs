@@ -0,0 +1,574 @@
+#[cfg(feature = "serde")]
+use crate::database::{DepositRecord, DepositState};
+use crate::{database::ClientWithId, Balance, RoundingMode};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::io::{self, Write};
+
+/// The columns of the client output, in order, for both the CSV and the fixed-width format.
+pub const CLIENT_COLUMNS: [&str; 5] = ["client", "available", "held", "total", "locked"];
+
+/// The number of decimal places of the balances in the client output, e.g. two for a USD report.
+///
+/// only the output is rounded, the balances are kept and summed with four decimals,
+/// so a rounded total can differ from the sum of the rounded available and held funds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Precision {
+    /// the number of decimal places, more than four are padded with zeros
+    pub decimals: u32,
+    /// how a balance with more decimals is rounded
+    pub rounding: RoundingMode,
+}
+
+impl Default for Precision {
+    /// four decimals, which are written as is.
+    fn default() -> Self {
+        Self {
+            decimals: 4,
+            rounding: RoundingMode::HalfUp,
+        }
+    }
+}
+
+impl Precision {
+    /// format a balance with this precision.
+    pub fn format(&self, balance: Balance) -> String {
+        balance.format_rounded(self.decimals, self.rounding)
+    }
+}
+
+/// A client that serializes like [`ClientWithId`], with its balances written with a [`Precision`].
+pub struct RoundedClient<'a> {
+    pub client: ClientWithId<'a>,
+    pub precision: Precision,
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for RoundedClient<'_> {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeStruct;
+        let client = &self.client;
+        let mut state = serializer.serialize_struct("Client", 5)?;
+        state.serialize_field("client", &client.id().0)?;
+        state.serialize_field("available", &self.precision.format(client.available()))?;
+        state.serialize_field("held", &self.precision.format(client.held()))?;
+        state.serialize_field("total", &self.precision.format(client.total()))?;
+        state.serialize_field("locked", &client.is_locked())?;
+        state.end()
+    }
+}
+
+/// A fixed-width (positional) format for the clients, for downstream systems that can't read CSV.
+///
+/// every row has the columns `client,available,held,total,locked` like the CSV output,
+/// each right-aligned and padded with spaces to the width of its column, without separators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedWidth {
+    /// the width of the `client` column
+    pub client: usize,
+    /// the width of each of the `available`, `held`, and `total` columns
+    pub balance: usize,
+    /// the width of the `locked` column
+    pub locked: usize,
+    /// the decimals the balances are written with
+    pub precision: Precision,
+}
+
+impl Default for FixedWidth {
+    fn default() -> Self {
+        Self {
+            client: 6,
+            balance: 24,
+            locked: 6,
+            precision: Precision::default(),
+        }
+    }
+}
+
+impl FixedWidth {
+    /// the total width of a row, without the line ending.
+    pub fn row_width(&self) -> usize {
+        self.client + 3 * self.balance + self.locked
+    }
+
+    /// write a header row followed by one row per client.
+    ///
+    /// fails with [`io::ErrorKind::InvalidData`] if a field does not fit in its column.
+    pub fn write<'a, W: Write>(
+        &self,
+        mut w: W,
+        clients: impl IntoIterator<Item = ClientWithId<'a>>,
+    ) -> io::Result<()> {
+        self.write_row(&mut w, CLIENT_COLUMNS)?;
+        for client in clients {
+            self.write_row(
+                &mut w,
+                [
+                    &client.id().0.to_string(),
+                    &self.precision.format(client.available()),
+                    &self.precision.format(client.held()),
+                    &self.precision.format(client.total()),
+                    &client.is_locked().to_string(),
+                ],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn write_row<W: Write>(&self, w: &mut W, fields: [&str; 5]) -> io::Result<()> {
+        let widths = [
+            self.client,
+            self.balance,
+            self.balance,
+            self.balance,
+            self.locked,
+        ];
+        for (field, width) in fields.into_iter().zip(widths) {
+            if field.len() > width {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("`{field}` does not fit in a column of width {width}"),
+                ));
+            }
+            write!(w, "{field:>width$}")?;
+        }
+        writeln!(w)
+    }
+}
+
+/// write a CSV report of disputed deposits with the columns `tx,client,amount,state`, for the disputes team.
+///
+/// the state is `disputed`, `chargedback`, or `resolved` for a deposit that is no longer disputed,
+/// pass it [`Database::disputed_deposits`](crate::database::Database::disputed_deposits).
+/// the fields are quoted and escaped as configured for the writer, like the client output.
+#[cfg(feature = "serde")]
+pub fn write_disputes<W: Write>(
+    w: &mut csv::Writer<W>,
+    deposits: impl IntoIterator<Item = DepositRecord>,
+) -> io::Result<()> {
+    w.write_record(["tx", "client", "amount", "state"])?;
+    for deposit in deposits {
+        let state = match deposit.state {
+            DepositState::Normal => "resolved",
+            DepositState::Disputed => "disputed",
+            DepositState::ChargedBack => "chargedback",
+        };
+        w.write_record([
+            deposit.transaction_id.0.to_string(),
+            deposit.client_id.0.to_string(),
+            format!("{:?}", deposit.amount),
+            state.to_string(),
+        ])?;
+    }
+    Ok(())
+}
+
+/// The scale of the balance and amount columns of the Parquet output, which hold integers in 0.0001ths:
+/// `12345` is `1.2345`. the scale is also stored in the `scale` metadata of these columns.
+#[cfg(feature = "parquet")]
+pub const PARQUET_SCALE: u32 = 4;
+
+/// write the clients as Parquet with the columns `client` (uint32), `available`, `held`, `total` (int64 with
+/// a scale of [`PARQUET_SCALE`]), and `locked` (bool), e.g. to query them with DuckDB or pandas.
+///
+/// fails with [`io::ErrorKind::InvalidData`] if a balance does not fit in an int64.
+#[cfg(feature = "parquet")]
+pub fn write_clients_parquet<'a, W: Write + Send>(
+    w: W,
+    clients: impl IntoIterator<Item = ClientWithId<'a>>,
+) -> io::Result<()> {
+    use arrow_array::{BooleanArray, Int64Array, UInt32Array};
+    use arrow_schema::DataType;
+
+    let (mut ids, mut available, mut held, mut total, mut locked) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for client in clients {
+        ids.push(u32::from(client.id()));
+        available.push(fixed_point(client.available().0)?);
+        held.push(fixed_point(client.held().0)?);
+        let sum = client.checked_total().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the total of client {:?} overflows", client.id()),
+            )
+        })?;
+        total.push(fixed_point(sum.0)?);
+        locked.push(client.is_locked());
+    }
+    write_parquet(
+        w,
+        [
+            ("client", DataType::UInt32, false),
+            ("available", DataType::Int64, true),
+            ("held", DataType::Int64, true),
+            ("total", DataType::Int64, true),
+            ("locked", DataType::Boolean, false),
+        ],
+        vec![
+            std::sync::Arc::new(UInt32Array::from(ids)),
+            std::sync::Arc::new(Int64Array::from(available)),
+            std::sync::Arc::new(Int64Array::from(held)),
+            std::sync::Arc::new(Int64Array::from(total)),
+            std::sync::Arc::new(BooleanArray::from(locked)),
+        ],
+    )
+}
+
+/// write deposits as Parquet with the columns `tx` (uint64), `client` (uint32), `amount` (int64 with a scale of
+/// [`PARQUET_SCALE`]), and `state` (`normal`, `disputed`, or `chargedback`),
+/// pass it [`Database::deposits`](crate::database::Database::deposits).
+///
+/// fails with [`io::ErrorKind::InvalidData`] if an amount does not fit in an int64.
+#[cfg(feature = "parquet")]
+pub fn write_deposits_parquet<W: Write + Send>(
+    w: W,
+    deposits: impl IntoIterator<Item = DepositRecord>,
+) -> io::Result<()> {
+    use arrow_array::{Int64Array, StringArray, UInt32Array, UInt64Array};
+    use arrow_schema::DataType;
+
+    let (mut ids, mut clients, mut amounts, mut states) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for deposit in deposits {
+        ids.push(u64::from(deposit.transaction_id));
+        clients.push(u32::from(deposit.client_id));
+        amounts.push(fixed_point(deposit.amount.0.into())?);
+        states.push(match deposit.state {
+            DepositState::Normal => "normal",
+            DepositState::Disputed => "disputed",
+            DepositState::ChargedBack => "chargedback",
+        });
+    }
+    write_parquet(
+        w,
+        [
+            ("tx", DataType::UInt64, false),
+            ("client", DataType::UInt32, false),
+            ("amount", DataType::Int64, true),
+            ("state", DataType::Utf8, false),
+        ],
+        vec![
+            std::sync::Arc::new(UInt64Array::from(ids)),
+            std::sync::Arc::new(UInt32Array::from(clients)),
+            std::sync::Arc::new(Int64Array::from(amounts)),
+            std::sync::Arc::new(StringArray::from(states)),
+        ],
+    )
+}
+
+/// a balance or amount in 0.0001ths as an int64, which holds almost a quadrillion.
+#[cfg(feature = "parquet")]
+fn fixed_point(units: i128) -> io::Result<i64> {
+    i64::try_from(units).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{units} does not fit in an int64 column"),
+        )
+    })
+}
+
+/// write the columns as a single row group, the int64 columns (flagged `true`) carry the scale as metadata.
+#[cfg(feature = "parquet")]
+fn write_parquet<W: Write + Send, const N: usize>(
+    w: W,
+    fields: [(&str, arrow_schema::DataType, bool); N],
+    columns: Vec<arrow_array::ArrayRef>,
+) -> io::Result<()> {
+    use arrow_schema::{Field, Schema};
+    use std::collections::HashMap;
+
+    let fields = fields.map(|(name, data_type, scaled)| {
+        let field = Field::new(name, data_type, false);
+        if scaled {
+            field.with_metadata(HashMap::from([(
+                "scale".to_string(),
+                PARQUET_SCALE.to_string(),
+            )]))
+        } else {
+            field
+        }
+    });
+    let schema = std::sync::Arc::new(Schema::new(fields.to_vec()));
+    let batch =
+        arrow_array::RecordBatch::try_new(schema.clone(), columns).map_err(io::Error::other)?;
+    let mut writer =
+        parquet::arrow::ArrowWriter::try_new(w, schema, None).map_err(io::Error::other)?;
+    writer.write(&batch).map_err(io::Error::other)?;
+    writer.close().map_err(io::Error::other)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "serde")]
+    use super::write_disputes;
+    use super::FixedWidth;
+    #[cfg(feature = "serde")]
+    use crate::actions::{Chargeback, Dispute, Resolve};
+    use crate::{
+        actions::{AccountAction, Deposit},
+        database::Database,
+        Amount, ClientId, TransactionId,
+    };
+
+    fn database() -> Database {
+        let mut db = Database::new();
+        for (client, amount) in [(1, 1_5000), (65535, 123_4567)] {
+            assert!(db
+                .perform_action(AccountAction::Deposit(Deposit {
+                    client_id: ClientId(client),
+                    transaction_id: TransactionId(client.into()),
+                    amount: Amount(amount),
+                    memo: None,
+                }))
+                .is_ok());
+        }
+        db
+    }
+
+    /// ensure every row has the configured column widths and can be read back by position
+    #[test]
+    fn fixed_width() {
+        let db = database();
+        let format = FixedWidth::default();
+        let mut out = Vec::new();
+        format.write(&mut out, db.clients()).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines.iter().all(|line| line.len() == format.row_width()));
+
+        // a positional reader only needs the widths to split the fields
+        let parse = |line: &str| {
+            let mut rest = line;
+            let mut fields = Vec::new();
+            for width in [6, 24, 24, 24, 6] {
+                let (field, tail) = rest.split_at(width);
+                fields.push(field.trim().to_string());
+                rest = tail;
+            }
+            fields
+        };
+        assert_eq!(
+            parse(lines[0]),
+            ["client", "available", "held", "total", "locked"]
+        );
+        assert_eq!(
+            parse(lines[1]),
+            ["1", "1.5000", "0.0000", "1.5000", "false"]
+        );
+        assert_eq!(
+            parse(lines[2]),
+            ["65535", "123.4567", "0.0000", "123.4567", "false"]
+        );
+    }
+
+    /// ensure a field wider than its column is an error instead of shifting the other columns
+    #[test]
+    fn fixed_width_overflow() {
+        let db = database();
+        let format = FixedWidth {
+            balance: 6,
+            ..FixedWidth::default()
+        };
+        let err = format.write(Vec::new(), db.clients()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    /// ensure the disputes report lists exactly the disputed deposits with their states
+    #[cfg(feature = "serde")]
+    #[test]
+    fn disputes() {
+        let mut db = Database::new();
+        for tx in 1..=4 {
+            assert!(db
+                .perform_action(AccountAction::Deposit(Deposit {
+                    client_id: ClientId(tx as crate::ClientIdRepr),
+                    transaction_id: TransactionId(tx as crate::TransactionIdRepr),
+                    amount: Amount(tx),
+                    memo: None,
+                }))
+                .is_ok());
+        }
+        for tx in 1..=3 {
+            assert!(db
+                .perform_action(AccountAction::Dispute(Dispute {
+                    client_id: ClientId(tx as crate::ClientIdRepr),
+                    disputed_transaction: TransactionId(tx),
+                    dispute_id: None,
+                    reason: None,
+                }))
+                .is_ok());
+        }
+        assert!(db
+            .perform_action(AccountAction::Resolve(Resolve {
+                client_id: ClientId(1),
+                disputed_transaction: Some(TransactionId(1)),
+                dispute_id: None,
+            }))
+            .is_ok());
+        assert!(db
+            .perform_action(AccountAction::Chargeback(Chargeback {
+                client_id: ClientId(2),
+                disputed_transaction: Some(TransactionId(2)),
+                dispute_id: None,
+                reason: None,
+                basis_points: None,
+            }))
+            .is_ok());
+        let mut out = csv::Writer::from_writer(Vec::new());
+        write_disputes(&mut out, db.disputed_deposits()).unwrap();
+        assert_eq!(
+            String::from_utf8(out.into_inner().unwrap()).unwrap(),
+            "tx,client,amount,state\n1,1,0.0001,resolved\n2,2,0.0002,chargedback\n3,3,0.0003,disputed\n"
+        );
+        let mut out = csv::WriterBuilder::new()
+            .quote_style(csv::QuoteStyle::NonNumeric)
+            .from_writer(Vec::new());
+        write_disputes(&mut out, db.disputed_deposits()).unwrap();
+        assert!(String::from_utf8(out.into_inner().unwrap())
+            .unwrap()
+            .ends_with("\n3,3,0.0003,\"disputed\"\n"));
+    }
+
+    /// ensure rounded clients are written with the configured decimals, while the balances keep four
+    #[cfg(feature = "serde")]
+    #[test]
+    fn precision() {
+        use super::{Precision, RoundedClient};
+        use crate::{Balance, RoundingMode};
+
+        let mut db = Database::new();
+        for (tx, amount) in [(1, 1_2345), (2, 50)] {
+            assert!(db
+                .perform_action(AccountAction::Deposit(Deposit {
+                    client_id: ClientId(1),
+                    transaction_id: TransactionId(tx),
+                    amount: Amount(amount),
+                    memo: None,
+                }))
+                .is_ok());
+        }
+        assert!(db
+            .perform_action(AccountAction::Dispute(Dispute {
+                client_id: ClientId(1),
+                disputed_transaction: TransactionId(2),
+                dispute_id: None,
+                reason: None,
+            }))
+            .is_ok());
+        let write = |precision| {
+            let mut wtr = csv::Writer::from_writer(Vec::new());
+            for client in db.clients() {
+                wtr.serialize(RoundedClient { client, precision }).unwrap();
+            }
+            String::from_utf8(wtr.into_inner().unwrap()).unwrap()
+        };
+        let cents = |rounding| Precision {
+            decimals: 2,
+            rounding,
+        };
+        assert_eq!(
+            write(cents(RoundingMode::Down)),
+            "client,available,held,total,locked\n1,1.23,0.00,1.23,false\n"
+        );
+        // the total is rounded from the four decimal sum, not summed from the rounded balances
+        assert_eq!(
+            write(cents(RoundingMode::HalfUp)),
+            "client,available,held,total,locked\n1,1.23,0.01,1.24,false\n"
+        );
+        assert_eq!(
+            write(Precision::default()),
+            "client,available,held,total,locked\n1,1.2345,0.0050,1.2395,false\n"
+        );
+        let client = db.clients().next().unwrap();
+        assert!(client.available() == Balance(1_2345));
+        assert!(client.total() == Balance(1_2395));
+    }
+
+    /// ensure the exported clients and deposits read back with the typed columns, the scale, and their values
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet() {
+        use super::{write_clients_parquet, write_deposits_parquet};
+        use arrow_array::{
+            cast::AsArray,
+            types::{Int64Type, UInt32Type, UInt64Type},
+        };
+        use arrow_schema::DataType;
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let read = |out: Vec<u8>| {
+            let reader = ParquetRecordBatchReaderBuilder::try_new(bytes::Bytes::from(out))
+                .unwrap()
+                .build()
+                .unwrap();
+            let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+            assert_eq!(batches.len(), 1);
+            batches.into_iter().next().unwrap()
+        };
+        let db = database();
+
+        let mut out = Vec::new();
+        write_clients_parquet(&mut out, db.clients()).unwrap();
+        let clients = read(out);
+        let schema = clients.schema();
+        let columns = schema
+            .fields()
+            .iter()
+            .map(|field| (field.name().as_str(), field.data_type().clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            columns,
+            [
+                ("client", DataType::UInt32),
+                ("available", DataType::Int64),
+                ("held", DataType::Int64),
+                ("total", DataType::Int64),
+                ("locked", DataType::Boolean),
+            ]
+        );
+        assert_eq!(
+            schema.field_with_name("total").unwrap().metadata()["scale"],
+            "4"
+        );
+        assert_eq!(clients.num_rows(), 2);
+        assert_eq!(
+            clients.column(0).as_primitive::<UInt32Type>().value(1),
+            65535
+        );
+        assert_eq!(
+            clients.column(1).as_primitive::<Int64Type>().value(1),
+            123_4567
+        );
+        assert_eq!(clients.column(2).as_primitive::<Int64Type>().value(1), 0);
+        assert_eq!(
+            clients.column(3).as_primitive::<Int64Type>().value(1),
+            123_4567
+        );
+        assert!(!clients.column(4).as_boolean().value(1));
+
+        let mut out = Vec::new();
+        write_deposits_parquet(&mut out, db.deposits()).unwrap();
+        let deposits = read(out);
+        assert_eq!(deposits.num_rows(), 2);
+        assert_eq!(deposits.column(0).as_primitive::<UInt64Type>().value(0), 1);
+        assert_eq!(deposits.column(1).as_primitive::<UInt32Type>().value(0), 1);
+        assert_eq!(
+            deposits.column(2).as_primitive::<Int64Type>().value(0),
+            1_5000
+        );
+        assert_eq!(deposits.column(3).as_string::<i32>().value(0), "normal");
+
+        // a total that doesn't fit is an error rather than a panic
+        let mut db = database();
+        let client = db.client_mut(crate::ClientId(1));
+        client.available = crate::Balance(i64::MAX.into());
+        client.held = crate::Balance(1);
+        let err = write_clients_parquet(Vec::new(), db.clients()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
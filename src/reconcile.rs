@@ -0,0 +1,252 @@
+use crate::{
+    actions::AccountAction,
+    config::{ChargebackShortfall, Config},
+    database::Database,
+    database::TransactionKey,
+    store::TransactionStore,
+    Amount, Balance, ClientId, DisputeId, Error, Result, RoundingMode, TransactionId,
+};
+use std::collections::BTreeMap;
+
+/// An independent tally of the funds the clients should hold in total, kept next to the database.
+///
+/// every successfully performed action is passed to [`Reconciliation::record`], which sums the deposits,
+/// subtracts the withdrawals and chargebacks, adds back refunds, and applies adjustments, without looking at the clients.
+/// [`Reconciliation::check`] then asserts this equals the sum of the totals of all clients.
+/// disputes, resolves, and cancellations only move funds between available and held, so they net to zero.
+///
+/// deposit fees are not accounted for, so the check only holds for a database without a deposit fee.
+/// a chargeback clamped by [`ChargebackShortfall::Clamp`] charges what the client held, which only the database knows,
+/// so its amount is taken from the deposit in the database.
+/// changes made outside of actions, like [`Database::sweep_dust`], are not seen either.
+#[derive(Debug, Default)]
+pub struct Reconciliation {
+    expected: i128,
+    per_client_transaction_ids: bool,
+    chargeback_rounding: RoundingMode,
+    chargeback_shortfall: ChargebackShortfall,
+    // the amounts of the deposits and withdrawals, to know how much a chargeback or refund moves,
+    // a partial chargeback leaves the rest of the deposit to be charged back later
+    deposits: BTreeMap<TransactionKey, u64>,
+    withdrawals: BTreeMap<TransactionKey, u64>,
+    // the deposit each dispute id refers to, for chargebacks that refer to the dispute by its id
+    disputes: BTreeMap<(Option<ClientId>, DisputeId), TransactionKey>,
+}
+
+impl Reconciliation {
+    /// an empty tally for a database with the given configuration.
+    pub fn new(config: &Config) -> Self {
+        Self {
+            per_client_transaction_ids: config.per_client_transaction_ids,
+            chargeback_rounding: config.chargeback_rounding,
+            chargeback_shortfall: config.chargeback_shortfall,
+            ..Self::default()
+        }
+    }
+
+    fn key(&self, client: ClientId, id: TransactionId) -> TransactionKey {
+        TransactionKey::new(self.per_client_transaction_ids.then_some(client), id)
+    }
+
+    /// record an action that was performed successfully on `db`, failed actions must not be recorded.
+    pub fn record<S: TransactionStore>(&mut self, action: &AccountAction, db: &Database<S>) {
+        match action {
+            AccountAction::Deposit(deposit) => {
+                self.expected += deposit.amount.0 as i128;
+                let key = self.key(deposit.client_id, deposit.transaction_id);
+                self.deposits.insert(key, deposit.amount.0);
+            }
+            AccountAction::Withdrawal(withdrawal) => {
+                self.expected -= withdrawal.amount.0 as i128;
+                let key = self.key(withdrawal.client_id, withdrawal.transaction_id);
+                self.withdrawals.insert(key, withdrawal.amount.0);
+            }
+            AccountAction::Dispute(dispute) => {
+                if let Some(id) = dispute.dispute_id {
+                    let scope = self.per_client_transaction_ids.then_some(dispute.client_id);
+                    let key = self.key(dispute.client_id, dispute.disputed_transaction);
+                    self.disputes.insert((scope, id), key);
+                }
+            }
+            AccountAction::Chargeback(chargeback) => {
+                let scope = self
+                    .per_client_transaction_ids
+                    .then_some(chargeback.client_id);
+                let key = match (chargeback.dispute_id, chargeback.disputed_transaction) {
+                    (Some(id), _) => self.disputes.get(&(scope, id)).copied(),
+                    (None, Some(transaction)) => Some(self.key(chargeback.client_id, transaction)),
+                    (None, None) => None,
+                };
+                let rounding = self.chargeback_rounding;
+                let Some(key) = key else {
+                    return;
+                };
+                if let Some(amount) = self.deposits.get_mut(&key) {
+                    let mut charged = chargeback
+                        .basis_points
+                        .map_or(*amount, |bps| Amount(*amount).basis_points(bps, rounding).0);
+                    if self.chargeback_shortfall == ChargebackShortfall::Clamp {
+                        // everything the database charged back of the deposit beyond what was recorded before,
+                        // which is less than the share if the chargeback was clamped
+                        if let Some(deposit) = db.store().get_deposit(&key) {
+                            let recorded = deposit.amount().0.saturating_sub(*amount);
+                            charged =
+                                charged.min(deposit.charged_back().0.saturating_sub(recorded));
+                        }
+                    }
+                    self.expected -= charged as i128;
+                    *amount -= charged;
+                }
+            }
+            AccountAction::Refund(refund) => {
+                let key = self.key(refund.client_id, refund.refunded_transaction);
+                if let Some(amount) = self.withdrawals.get(&key) {
+                    self.expected += *amount as i128;
+                }
+            }
+            AccountAction::Adjustment(adjustment) => {
+                let amount = adjustment.signed_amount.magnitude().0 as i128;
+                if adjustment.signed_amount.is_negative() {
+                    self.expected -= amount;
+                } else {
+                    self.expected += amount;
+                }
+            }
+            AccountAction::Resolve(_)
+            | AccountAction::CancelDispute(_)
+            | AccountAction::Close(_)
+            | AccountAction::NoOp(_) => {}
+        }
+    }
+
+    /// the sum of the totals of all clients according to the recorded actions.
+    pub fn expected_total(&self) -> Balance {
+        Balance(self.expected)
+    }
+
+    /// fails with [`Error::ReconciliationMismatch`] if the totals of the clients in `db` don't add up to
+    /// [`Reconciliation::expected_total`], or with [`Error::Overflow`] if they can't be summed.
+    pub fn check<S: TransactionStore>(&self, db: &Database<S>) -> Result<()> {
+        let actual = db.totals()?.total;
+        if actual != self.expected_total() {
+            return Err(Error::ReconciliationMismatch {
+                expected: self.expected_total(),
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Reconciliation;
+    use crate::{
+        actions::AccountAction,
+        config::{ChargebackShortfall, Config},
+        database::{
+            tests::{chargeback, deposit, dispute, refund, withdrawal},
+            Database,
+        },
+        Amount, Balance, ClientId, Error,
+    };
+
+    fn actions() -> Vec<AccountAction> {
+        vec![
+            deposit(1, 1, 10),
+            deposit(2, 2, 5),
+            withdrawal(1, 3, 4),
+            // fails, insufficient funds
+            withdrawal(2, 4, 6),
+            dispute(2, 2),
+            chargeback(2, 2),
+            refund(1, 3),
+            // fails, already charged back
+            chargeback(2, 2),
+        ]
+    }
+
+    /// ensure the tally matches the clients after a clean run, and a corrupted database is detected
+    #[test]
+    fn reconcile() {
+        let mut db = Database::new();
+        let mut reconciliation = Reconciliation::new(db.config());
+        for action in actions() {
+            if db.perform_action_ref(&action).is_ok() {
+                reconciliation.record(&action, &db);
+            }
+        }
+        assert!(reconciliation.expected_total() == Balance(10));
+        assert!(reconciliation.check(&db).is_ok());
+
+        // funds appearing out of nowhere
+        db.client_mut(ClientId(3)).deposit(Amount(1)).unwrap();
+        assert!(matches!(
+            reconciliation.check(&db),
+            Err(Error::ReconciliationMismatch {
+                expected: Balance(10),
+                actual: Balance(11)
+            })
+        ));
+    }
+
+    /// ensure chargebacks are matched to the deposit of the right client if transaction ids are scoped per client
+    #[test]
+    fn per_client() {
+        let config = Config {
+            per_client_transaction_ids: true,
+            ..Config::default()
+        };
+        let mut db = Database::with_config(config.clone());
+        let mut reconciliation = Reconciliation::new(&config);
+        let mut actions = Vec::new();
+        for (client, amount) in [(1, 3), (2, 7)] {
+            actions.push(deposit(client, 1, amount));
+        }
+        actions.push(dispute(2, 1));
+        actions.push(chargeback(2, 1));
+        for action in actions {
+            assert!(db.perform_action_ref(&action).is_ok());
+            reconciliation.record(&action, &db);
+        }
+        assert!(reconciliation.expected_total() == Balance(3));
+        assert!(reconciliation.check(&db).is_ok());
+    }
+
+    /// ensure a chargeback of more than the held funds reconciles under every policy, also if it is clamped
+    #[test]
+    fn chargeback_shortfall() {
+        for shortfall in [
+            ChargebackShortfall::LockAndFail,
+            ChargebackShortfall::Clamp,
+            ChargebackShortfall::Fail,
+        ] {
+            let config = Config {
+                chargeback_shortfall: shortfall,
+                ..Config::default()
+            };
+            let mut db = Database::with_config(config.clone());
+            let mut reconciliation = Reconciliation::new(&config);
+            let perform = |db: &mut Database, reconciliation: &mut Reconciliation, action| {
+                let result = db.perform_action_ref(&action);
+                if result.is_ok() {
+                    reconciliation.record(&action, db);
+                }
+                result
+            };
+            perform(&mut db, &mut reconciliation, deposit(1, 1, 5)).unwrap();
+            perform(&mut db, &mut reconciliation, dispute(1, 1)).unwrap();
+            // move held funds back to the available funds behind the database's back, keeping the total
+            let client = db.client_mut(ClientId(1));
+            client.held = Balance(2);
+            client.available = Balance(3);
+            let result = perform(&mut db, &mut reconciliation, chargeback(1, 1));
+            assert_eq!(
+                result.is_ok(),
+                shortfall == ChargebackShortfall::Clamp,
+                "{shortfall:?}"
+            );
+            assert!(reconciliation.check(&db).is_ok(), "{shortfall:?}");
+        }
+    }
+}
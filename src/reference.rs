@@ -0,0 +1,300 @@
+//! A deliberately naive implementation of the ledger, used to check the [`Database`] against.
+//!
+//! Instead of keeping running balances, it keeps a log of every accepted transaction
+//! and recomputes a client's balances from scratch whenever it needs them.
+use crate::{
+    actions::{AccountAction, CancelDispute, Resolve},
+    database::Database,
+    ClientIdRepr, TransactionIdRepr,
+};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Normal,
+    Disputed,
+    ChargedBack,
+    Refunded,
+}
+
+struct LoggedTransaction {
+    client: ClientIdRepr,
+    deposit: bool,
+    amount: i128,
+    state: State,
+}
+
+/// The balances of a single client as computed by the reference implementation.
+#[derive(Debug, PartialEq)]
+struct Balances {
+    available: i128,
+    held: i128,
+    locked: bool,
+}
+
+#[derive(Default)]
+struct Reference {
+    seen: HashSet<TransactionIdRepr>,
+    clients: HashSet<ClientIdRepr>,
+    closed: HashSet<ClientIdRepr>,
+    log: HashMap<TransactionIdRepr, LoggedTransaction>,
+    // the clients and signed amounts of the adjustments, which can't be disputed or refunded
+    adjustments: Vec<(ClientIdRepr, i128)>,
+}
+
+impl Reference {
+    fn balances(&self, client: ClientIdRepr) -> Balances {
+        let mut balances = Balances {
+            available: 0,
+            held: 0,
+            locked: false,
+        };
+        for tx in self.log.values().filter(|tx| tx.client == client) {
+            match (tx.deposit, tx.state) {
+                (false, State::Refunded) => {}
+                (false, _) => balances.available -= tx.amount,
+                (true, State::Normal) => balances.available += tx.amount,
+                (true, State::Disputed) => balances.held += tx.amount,
+                (true, State::ChargedBack) => balances.locked = true,
+                (true, State::Refunded) => unreachable!("deposits are never refunded"),
+            }
+        }
+        for (_, amount) in self.adjustments.iter().filter(|(id, _)| *id == client) {
+            balances.available += amount;
+        }
+        balances
+    }
+
+    /// apply an action, returning whether it succeeded.
+    fn apply(&mut self, action: &AccountAction) -> bool {
+        match action {
+            AccountAction::Deposit(deposit) => {
+                if !self.seen.insert(deposit.transaction_id.0) {
+                    return false;
+                }
+                self.clients.insert(deposit.client_id.0);
+                if self.closed.contains(&deposit.client_id.0) {
+                    return false;
+                }
+                self.log.insert(
+                    deposit.transaction_id.0,
+                    LoggedTransaction {
+                        client: deposit.client_id.0,
+                        deposit: true,
+                        amount: deposit.amount.0 as i128,
+                        state: State::Normal,
+                    },
+                );
+                true
+            }
+            AccountAction::Withdrawal(withdrawal) => {
+                if !self.seen.insert(withdrawal.transaction_id.0) {
+                    return false;
+                }
+                self.clients.insert(withdrawal.client_id.0);
+                let balances = self.balances(withdrawal.client_id.0);
+                let amount = withdrawal.amount.0 as i128;
+                if self.closed.contains(&withdrawal.client_id.0)
+                    || balances.locked
+                    || balances.available < amount
+                {
+                    return false;
+                }
+                self.log.insert(
+                    withdrawal.transaction_id.0,
+                    LoggedTransaction {
+                        client: withdrawal.client_id.0,
+                        deposit: false,
+                        amount,
+                        state: State::Normal,
+                    },
+                );
+                true
+            }
+            AccountAction::Dispute(dispute) => {
+                match self.log.get_mut(&dispute.disputed_transaction.0) {
+                    Some(tx) if tx.deposit && tx.state == State::Disputed => true,
+                    Some(tx)
+                        if tx.deposit
+                            && tx.state == State::Normal
+                            && !self.closed.contains(&tx.client) =>
+                    {
+                        tx.state = State::Disputed;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            AccountAction::Resolve(Resolve {
+                disputed_transaction,
+                ..
+            })
+            | AccountAction::CancelDispute(CancelDispute {
+                disputed_transaction,
+                ..
+            }) => match disputed_transaction.and_then(|tx| self.log.get_mut(&tx.0)) {
+                Some(tx) if tx.deposit && tx.state == State::Disputed => {
+                    tx.state = State::Normal;
+                    true
+                }
+                _ => false,
+            },
+            AccountAction::Chargeback(chargeback) => {
+                match chargeback
+                    .disputed_transaction
+                    .and_then(|tx| self.log.get_mut(&tx.0))
+                {
+                    Some(tx) if tx.deposit && tx.state == State::Disputed => {
+                        tx.state = State::ChargedBack;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            AccountAction::Refund(refund) => {
+                match self.log.get_mut(&refund.refunded_transaction.0) {
+                    Some(tx)
+                        if !tx.deposit
+                            && tx.state == State::Normal
+                            && !self.closed.contains(&tx.client) =>
+                    {
+                        tx.state = State::Refunded;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            AccountAction::Close(close) => {
+                let client = close.client_id.0;
+                let open_dispute = self
+                    .log
+                    .values()
+                    .any(|tx| tx.client == client && tx.state == State::Disputed);
+                self.clients.contains(&client) && !open_dispute && self.closed.insert(client)
+            }
+            AccountAction::Adjustment(adjustment) => {
+                if !self.seen.insert(adjustment.transaction_id.0) {
+                    return false;
+                }
+                self.clients.insert(adjustment.client_id.0);
+                if self.closed.contains(&adjustment.client_id.0) {
+                    return false;
+                }
+                let amount = adjustment.signed_amount.magnitude().0 as i128;
+                let amount = if adjustment.signed_amount.is_negative() {
+                    -amount
+                } else {
+                    amount
+                };
+                self.adjustments.push((adjustment.client_id.0, amount));
+                true
+            }
+            AccountAction::NoOp(_) => true,
+        }
+    }
+
+    /// assert that the database holds exactly the same clients with the same balances.
+    fn assert_matches(&self, db: &Database) {
+        let mut expected: Vec<_> = self.clients.iter().copied().collect();
+        expected.sort();
+        let actual: Vec<_> = db.clients().map(|client| client.id().0).collect();
+        assert_eq!(expected, actual);
+        for client in db.clients() {
+            let actual = Balances {
+                available: client.available().0,
+                held: client.held().0,
+                locked: client.is_locked(),
+            };
+            assert_eq!(
+                self.balances(client.id().0),
+                actual,
+                "client {:?}",
+                client.id()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Reference;
+    use crate::{
+        actions::{
+            AccountAction, CancelDispute, Chargeback, Close, Deposit, Dispute, Resolve, Withdrawal,
+        },
+        database::Database,
+        Amount, ClientId, ClientIdRepr, TransactionId, TransactionIdRepr,
+    };
+
+    /// generate a random action, with few clients and transaction ids so collisions and disputes are common.
+    fn random_action(state: &mut u64) -> AccountAction {
+        let mut next = || {
+            *state ^= *state << 13;
+            *state ^= *state >> 7;
+            *state ^= *state << 17;
+            *state
+        };
+        let client_id = ClientId((next() % 5) as ClientIdRepr);
+        let transaction_id = TransactionId((next() % 200) as TransactionIdRepr);
+        let amount = Amount(next() % 50_000);
+        match next() % 12 {
+            0..=3 => AccountAction::Deposit(Deposit {
+                client_id,
+                transaction_id,
+                amount,
+                memo: None,
+            }),
+            4..=5 => AccountAction::Withdrawal(Withdrawal {
+                client_id,
+                transaction_id,
+                amount,
+                memo: None,
+            }),
+            6..=7 => AccountAction::Dispute(Dispute {
+                client_id,
+                disputed_transaction: transaction_id,
+                dispute_id: None,
+                reason: None,
+            }),
+            8 => AccountAction::Resolve(Resolve {
+                client_id,
+                disputed_transaction: Some(transaction_id),
+                dispute_id: None,
+            }),
+            9 => AccountAction::Chargeback(Chargeback {
+                client_id,
+                disputed_transaction: Some(transaction_id),
+                dispute_id: None,
+                reason: None,
+                basis_points: None,
+            }),
+            10 => AccountAction::CancelDispute(CancelDispute {
+                client_id,
+                disputed_transaction: Some(transaction_id),
+                dispute_id: None,
+            }),
+            _ => AccountAction::Close(Close { client_id }),
+        }
+    }
+
+    /// ensure the database agrees with the reference implementation on every action and on the final state
+    #[test]
+    fn differential() {
+        for seed in 1..=20u64 {
+            let mut state = seed;
+            let mut db = Database::new();
+            let mut reference = Reference::default();
+            for n in 0..1_000 {
+                let action = random_action(&mut state);
+                let expected = reference.apply(&action);
+                let description = format!("{action:?}");
+                assert_eq!(
+                    db.perform_action(action).is_ok(),
+                    expected,
+                    "seed {seed}, action {n}: {description}"
+                );
+            }
+            reference.assert_matches(&db);
+        }
+    }
+}
@@ -0,0 +1,373 @@
+use crate::database::{SeenAdjustment, SeenDeposit, SeenWithdrawal, TransactionKey};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Where the [`Database`](crate::database::Database) keeps track of used transaction ids and deposits.
+///
+/// the clients are always kept in memory, the transactions are the part that grows without bounds,
+/// so this is the part that can be moved to e.g. an embedded database.
+pub trait TransactionStore {
+    /// mark a transaction id as used, returns `false` if it was already used.
+    fn mark_seen(&mut self, key: TransactionKey) -> bool;
+
+    /// whether a transaction id is used. scans [`TransactionStore::seen`] by default.
+    fn is_seen(&self, key: &TransactionKey) -> bool {
+        self.seen().any(|seen| seen == key)
+    }
+
+    /// undo a new transaction: its id is no longer used, and its deposit, withdrawal, or adjustment is dropped.
+    /// used to roll back an action that panicked, see
+    /// [`Database::try_perform_action`](crate::database::Database::try_perform_action).
+    fn forget(&mut self, key: &TransactionKey);
+
+    /// all transaction ids marked as used, ordered by key.
+    fn seen(&self) -> impl Iterator<Item = &TransactionKey>;
+
+    /// keep a deposit so it can be disputed later. the key has already been marked as seen.
+    fn record_deposit(&mut self, key: TransactionKey, deposit: SeenDeposit);
+
+    /// look up a deposit.
+    fn get_deposit(&self, key: &TransactionKey) -> Option<&SeenDeposit>;
+
+    /// look up a deposit to update its dispute state.
+    fn get_deposit_mut(&mut self, key: &TransactionKey) -> Option<&mut SeenDeposit>;
+
+    /// forget a deposit, it can no longer be disputed. its transaction id stays used.
+    fn remove_deposit(&mut self, key: &TransactionKey) -> Option<SeenDeposit>;
+
+    /// all deposits, ordered by key.
+    fn deposits(&self) -> impl Iterator<Item = (&TransactionKey, &SeenDeposit)>;
+
+    /// keep a withdrawal so it can be refunded later. the key has already been marked as seen.
+    fn record_withdrawal(&mut self, key: TransactionKey, withdrawal: SeenWithdrawal);
+
+    /// look up a withdrawal.
+    fn get_withdrawal(&self, key: &TransactionKey) -> Option<&SeenWithdrawal>;
+
+    /// look up a withdrawal to mark it as refunded.
+    fn get_withdrawal_mut(&mut self, key: &TransactionKey) -> Option<&mut SeenWithdrawal>;
+
+    /// keep a manual adjustment for auditing. the key has already been marked as seen.
+    /// does nothing by default, for stores that only keep what is needed to dispute and refund.
+    fn record_adjustment(&mut self, key: TransactionKey, adjustment: SeenAdjustment) {
+        let _ = (key, adjustment);
+    }
+
+    /// look up a manual adjustment, always `None` for stores that don't keep them.
+    fn get_adjustment(&self, key: &TransactionKey) -> Option<&SeenAdjustment> {
+        let _ = key;
+        None
+    }
+
+    /// reserve room for at least `additional` more transactions, if the store can.
+    /// does nothing by default.
+    fn reserve(&mut self, additional: usize) {
+        let _ = additional;
+    }
+
+    /// a rough estimate of the heap memory the store uses, in bytes.
+    /// `0` by default, e.g. for stores that keep their transactions on disk.
+    fn estimated_memory_bytes(&self) -> usize {
+        0
+    }
+}
+
+/// a rough estimate of the heap memory of a `BTreeMap<K, V>` (or `BTreeSet<K>` with `V = ()`) with `len` entries.
+///
+/// the nodes hold up to 11 entries but are only about two thirds full on average,
+/// and every node has a few words of overhead, which we count as one word per entry.
+pub(crate) fn btree_memory_bytes<K, V>(len: usize) -> usize {
+    let entry = std::mem::size_of::<K>() + std::mem::size_of::<V>();
+    len * (entry * 3 / 2 + std::mem::size_of::<usize>())
+}
+
+/// The default [`TransactionStore`], which keeps everything in memory.
+///
+/// it keeps the transactions in ordered maps, which allocate per node and can't reserve,
+/// so [`TransactionStore::reserve`] has no effect.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    // tracks which transaction ids have been seen
+    // we can assume that transaction ids are unique
+    // but if they aren't for any reason, the code breaks in weird ways so we include a guard rail to be sure
+    //  if this check is implemented in production, we should use a more performant data structure
+    // either roaring or range-set-blaze look like good choices here
+    // https://github.com/CarlKCarlK/range-set-blaze/blob/main/docs/bench.md
+    seen_transactions: BTreeSet<TransactionKey>,
+    // TransactionId is said to be globally unique, but disputes/resolves/chargebacks actions include a client id in the CSV.
+    //  it is unclear what the correct behaviour should be if these disagree with the client id in the deposit/withdrawal.
+    // By default, I have opted for ignoring the client id in the dispute/resolve/chargback action, and only using the transaction id.
+    //  in the real world, this would be an important detail to clarify with the product owner / docs / upstream team / partner.
+    // if transaction ids are scoped per client (see `Config::per_client_transaction_ids`) the client id becomes part of the key.
+    deposit_transactions: BTreeMap<TransactionKey, SeenDeposit>,
+    // successful withdrawals, kept so they can be refunded. keyed like the deposits.
+    withdrawal_transactions: BTreeMap<TransactionKey, SeenWithdrawal>,
+    // manual adjustments, kept for auditing only. keyed like the deposits.
+    adjustments: BTreeMap<TransactionKey, SeenAdjustment>,
+}
+
+impl TransactionStore for MemoryStore {
+    fn mark_seen(&mut self, key: TransactionKey) -> bool {
+        self.seen_transactions.insert(key)
+    }
+
+    fn seen(&self) -> impl Iterator<Item = &TransactionKey> {
+        self.seen_transactions.iter()
+    }
+
+    fn is_seen(&self, key: &TransactionKey) -> bool {
+        self.seen_transactions.contains(key)
+    }
+
+    fn forget(&mut self, key: &TransactionKey) {
+        self.seen_transactions.remove(key);
+        self.remove_deposit(key);
+        self.withdrawal_transactions.remove(key);
+        self.adjustments.remove(key);
+    }
+
+    fn record_deposit(&mut self, key: TransactionKey, deposit: SeenDeposit) {
+        self.deposit_transactions.insert(key, deposit);
+    }
+
+    fn get_deposit(&self, key: &TransactionKey) -> Option<&SeenDeposit> {
+        self.deposit_transactions.get(key)
+    }
+
+    fn get_deposit_mut(&mut self, key: &TransactionKey) -> Option<&mut SeenDeposit> {
+        self.deposit_transactions.get_mut(key)
+    }
+
+    fn remove_deposit(&mut self, key: &TransactionKey) -> Option<SeenDeposit> {
+        self.deposit_transactions.remove(key)
+    }
+
+    fn deposits(&self) -> impl Iterator<Item = (&TransactionKey, &SeenDeposit)> {
+        self.deposit_transactions.iter()
+    }
+
+    fn record_withdrawal(&mut self, key: TransactionKey, withdrawal: SeenWithdrawal) {
+        self.withdrawal_transactions.insert(key, withdrawal);
+    }
+
+    fn get_withdrawal(&self, key: &TransactionKey) -> Option<&SeenWithdrawal> {
+        self.withdrawal_transactions.get(key)
+    }
+
+    fn get_withdrawal_mut(&mut self, key: &TransactionKey) -> Option<&mut SeenWithdrawal> {
+        self.withdrawal_transactions.get_mut(key)
+    }
+
+    fn record_adjustment(&mut self, key: TransactionKey, adjustment: SeenAdjustment) {
+        self.adjustments.insert(key, adjustment);
+    }
+
+    fn get_adjustment(&self, key: &TransactionKey) -> Option<&SeenAdjustment> {
+        self.adjustments.get(key)
+    }
+
+    fn estimated_memory_bytes(&self) -> usize {
+        // the memos are the only data of the transactions that lives outside of the maps
+        let memos = self
+            .deposit_transactions
+            .values()
+            .filter_map(SeenDeposit::memo)
+            .chain(
+                self.withdrawal_transactions
+                    .values()
+                    .filter_map(SeenWithdrawal::memo),
+            )
+            .chain(self.adjustments.values().filter_map(SeenAdjustment::memo))
+            .map(str::len)
+            .sum::<usize>();
+        btree_memory_bytes::<TransactionKey, ()>(self.seen_transactions.len())
+            + btree_memory_bytes::<TransactionKey, SeenDeposit>(self.deposit_transactions.len())
+            + btree_memory_bytes::<TransactionKey, SeenWithdrawal>(
+                self.withdrawal_transactions.len(),
+            )
+            + btree_memory_bytes::<TransactionKey, SeenAdjustment>(self.adjustments.len())
+            + memos
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TransactionStore;
+    use crate::{
+        actions::AccountAction,
+        config::Config,
+        database::{
+            tests::{chargeback, deposit, dispute, withdrawal},
+            Database, SeenDeposit, SeenWithdrawal, TransactionKey,
+        },
+        ClientId, TransactionId,
+    };
+    use std::collections::BTreeMap;
+
+    /// a store on different collections, which counts how often it is asked for a deposit.
+    #[derive(Default)]
+    struct MockStore {
+        seen: Vec<TransactionKey>,
+        deposits: BTreeMap<TransactionKey, SeenDeposit>,
+        withdrawals: BTreeMap<TransactionKey, SeenWithdrawal>,
+        lookups: usize,
+    }
+
+    impl TransactionStore for MockStore {
+        fn mark_seen(&mut self, key: TransactionKey) -> bool {
+            if self.seen.contains(&key) {
+                return false;
+            }
+            self.seen.push(key);
+            true
+        }
+
+        fn seen(&self) -> impl Iterator<Item = &TransactionKey> {
+            let mut seen = self.seen.iter().collect::<Vec<_>>();
+            seen.sort();
+            seen.into_iter()
+        }
+
+        fn forget(&mut self, key: &TransactionKey) {
+            self.seen.retain(|seen| seen != key);
+            self.remove_deposit(key);
+            self.withdrawals.remove(key);
+        }
+
+        fn record_deposit(&mut self, key: TransactionKey, deposit: SeenDeposit) {
+            self.deposits.insert(key, deposit);
+        }
+
+        fn get_deposit(&self, key: &TransactionKey) -> Option<&SeenDeposit> {
+            self.deposits.get(key)
+        }
+
+        fn get_deposit_mut(&mut self, key: &TransactionKey) -> Option<&mut SeenDeposit> {
+            self.lookups += 1;
+            self.deposits.get_mut(key)
+        }
+
+        fn remove_deposit(&mut self, key: &TransactionKey) -> Option<SeenDeposit> {
+            self.deposits.remove(key)
+        }
+
+        fn deposits(&self) -> impl Iterator<Item = (&TransactionKey, &SeenDeposit)> {
+            self.deposits.iter()
+        }
+
+        fn record_withdrawal(&mut self, key: TransactionKey, withdrawal: SeenWithdrawal) {
+            self.withdrawals.insert(key, withdrawal);
+        }
+
+        fn get_withdrawal(&self, key: &TransactionKey) -> Option<&SeenWithdrawal> {
+            self.withdrawals.get(key)
+        }
+
+        fn get_withdrawal_mut(&mut self, key: &TransactionKey) -> Option<&mut SeenWithdrawal> {
+            self.withdrawals.get_mut(key)
+        }
+
+        fn reserve(&mut self, additional: usize) {
+            self.seen.reserve(additional);
+        }
+    }
+
+    fn actions() -> Vec<AccountAction> {
+        vec![
+            deposit(1, 1, 5),
+            deposit(2, 2, 3),
+            // duplicate transaction id
+            deposit(2, 2, 3),
+            withdrawal(1, 3, 1),
+            dispute(2, 2),
+            chargeback(2, 2),
+            // unknown deposit
+            dispute(1, 3),
+        ]
+    }
+
+    /// ensure the database behaves the same on the default store and on another store
+    #[test]
+    fn mock_store() {
+        let mut memory = Database::new();
+        let mut mock = Database::with_store(Config::default(), MockStore::default());
+        for action in actions() {
+            let expected = memory.perform_action_ref(&action).is_ok();
+            assert_eq!(mock.perform_action(action).is_ok(), expected);
+        }
+        assert!(memory.diff(&mock).is_empty());
+        assert!(mock.deposits().eq(memory.deposits()));
+        assert_eq!(mock.store().lookups, 3);
+        assert_eq!(mock.store().seen.len(), 3);
+    }
+
+    /// ensure a removed deposit can no longer be found or disputed, but its transaction id stays used
+    #[test]
+    fn remove_deposit() {
+        let key = TransactionKey::new(None, TransactionId(1));
+        let mut memory = Database::new();
+        let mut mock = Database::with_store(Config::default(), MockStore::default());
+        for action in actions() {
+            let _ = memory.perform_action_ref(&action);
+            let _ = mock.perform_action(action);
+        }
+        assert!(memory.store_mut().remove_deposit(&key).is_some());
+        assert!(mock.store_mut().remove_deposit(&key).is_some());
+        assert!(memory.store_mut().remove_deposit(&key).is_none());
+        assert!(memory.store().get_deposit(&key).is_none());
+        assert!(mock.store().get_deposit(&key).is_none());
+        assert!(memory
+            .store()
+            .deposits()
+            .all(|(deposit, _)| *deposit != key));
+        assert!(mock.store().deposits().all(|(deposit, _)| *deposit != key));
+        assert!(memory.store().is_seen(&key));
+        assert!(!memory.store_mut().mark_seen(key));
+        assert!(matches!(
+            memory.perform_action(dispute(1, 1)),
+            Err(crate::Error::TransactionNotFound)
+        ));
+    }
+
+    /// ensure a forgotten transaction id can be used again, on the default store and on another store
+    #[test]
+    fn forget() {
+        let key = TransactionKey::new(None, TransactionId(1));
+        let mut memory = Database::new();
+        let mut mock = Database::with_store(Config::default(), MockStore::default());
+        for action in actions() {
+            let _ = memory.perform_action_ref(&action);
+            let _ = mock.perform_action(action);
+        }
+        assert!(memory.store().is_seen(&key));
+        assert!(mock.store().is_seen(&key));
+        memory.store_mut().forget(&key);
+        mock.store_mut().forget(&key);
+        assert!(!memory.store().is_seen(&key));
+        assert!(!mock.store().is_seen(&key));
+        assert!(memory.deposit_info(TransactionId(1)).is_none());
+        assert!(mock.deposit_info(TransactionId(1)).is_none());
+        assert!(memory.store_mut().mark_seen(key));
+    }
+
+    /// ensure reserving on the database reserves in a store that supports it
+    #[test]
+    fn reserve() {
+        let mut db = Database::with_store(Config::default(), MockStore::default());
+        db.reserve(10, 1_000);
+        assert!(db.store().seen.capacity() >= 1_000);
+        assert_eq!(db.estimated_memory_bytes(), 0);
+        // the insertion order of the clients is only reserved if it is tracked
+        let mut db = Database::with_config(Config {
+            track_insertion_order: true,
+            ..Config::default()
+        });
+        db.reserve(10, 1_000);
+        assert!(db.estimated_memory_bytes() >= 10 * std::mem::size_of::<ClientId>());
+        // the default store ignores the hint
+        let mut db = Database::with_capacity(10, 1_000);
+        for action in actions() {
+            let _ = db.perform_action(action);
+        }
+        assert_eq!(db.clients().count(), 2);
+    }
+}
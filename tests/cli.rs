@@ -0,0 +1,464 @@
+//! Tests running the binary on the sample files in `tests/data`.
+use std::process::{Command, Output};
+
+fn ledger(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ledger"))
+        .args(args)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("failed to run the ledger binary")
+}
+
+/// ensure `process` prints the client balances, and is the default without a subcommand
+#[test]
+fn process() {
+    let output = ledger(&["process", "tests/data/sample.csv"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout,
+        "client,available,held,total,locked\n1,1.5000,0.0000,1.5000,false\n2,0.0000,0.0000,0.0000,true\n"
+    );
+    // the withdrawal of client 2 fails because of insufficient funds
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("failed to perform action 4"));
+
+    let default = ledger(&["tests/data/sample.csv"]);
+    assert!(default.status.success());
+    assert_eq!(String::from_utf8(default.stdout).unwrap(), stdout);
+}
+
+/// ensure a summary of the run is printed after the balances, unless `--quiet` is passed
+#[test]
+fn summary() {
+    let output = ledger(&["tests/data/sample.csv"]);
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .ends_with("\nprocessed 7 records, 1 failed, 1 account locked\n"));
+
+    let quiet = ledger(&["--quiet", "tests/data/sample.csv"]);
+    assert!(quiet.status.success());
+    assert_eq!(quiet.stdout, output.stdout);
+    assert!(!String::from_utf8(quiet.stderr)
+        .unwrap()
+        .contains("processed"));
+}
+
+/// ensure files without a header row, or with the columns in a different order, are read like the sample
+#[test]
+fn headers() {
+    let expected = ledger(&["tests/data/sample.csv"]).stdout;
+    let output = ledger(&["--no-header", "tests/data/no_header.csv"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, expected);
+    let output = ledger(&["process", "tests/data/reordered.csv"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, expected);
+}
+
+/// ensure a file starting with a UTF-8 byte order mark is read like one without
+#[test]
+fn bom() {
+    let expected = ledger(&["tests/data/sample.csv"]).stdout;
+    let output = ledger(&["tests/data/bom.csv"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, expected);
+}
+
+/// ensure `--comment` changes the comment character, and `--comment none` reads `#` lines as data
+#[test]
+fn comment() {
+    let expected = "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n2,2.0000,0.0000,2.0000,false\n";
+    let output = ledger(&["--comment", ";", "tests/data/comments.csv"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "processed 2 records\n"
+    );
+
+    let output = ledger(&["validate", "tests/data/hash_data.csv"]);
+    assert!(output.status.success());
+    let output = ledger(&["validate", "--comment", "none", "tests/data/hash_data.csv"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("failed to deserialize record 1"));
+}
+
+/// ensure records of an `--ignore-type` are skipped and counted instead of reported as invalid
+#[test]
+fn ignore_type() {
+    let output = ledger(&["--ignore-type", "heartbeat", "tests/data/heartbeat.csv"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "processed 3 records, 1 ignored\n"
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n2,2.0000,0.0000,2.0000,false\n"
+    );
+
+    let output = ledger(&[
+        "stats",
+        "--ignore-type",
+        "heartbeat",
+        "tests/data/heartbeat.csv",
+    ]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("invalid records: 0\n"));
+    assert!(stdout.contains("ignored records: 1\n"));
+
+    // without the flag the heartbeat is an unknown type
+    let output = ledger(&["validate", "tests/data/heartbeat.csv"]);
+    assert!(!output.status.success());
+}
+
+/// ensure records of an unknown type are reported as unhandled with `--unknown-types-unhandled`
+#[test]
+fn unknown_types_unhandled() {
+    let output = ledger(&["--unknown-types-unhandled", "tests/data/heartbeat.csv"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "skipped 1 records of unhandled type heartbeat\nprocessed 3 records, 1 unhandled\n"
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n2,2.0000,0.0000,2.0000,false\n"
+    );
+
+    let output = ledger(&[
+        "stats",
+        "--unknown-types-unhandled",
+        "tests/data/heartbeat.csv",
+    ]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("invalid records: 0\n"));
+    assert!(stdout.contains("unhandled records: 1\n"));
+}
+
+/// ensure `--max-records` stops with an error at the first record past the limit
+#[test]
+fn max_records() {
+    let output = ledger(&["--max-records", "2", "tests/data/sample.csv"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n2,2.0000,0.0000,2.0000,false\n"
+    );
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "stopped at record 2: input exceeds the configured maximum number of records or bytes\n\
+         processed 2 records, stopped early because the input is too large\n"
+    );
+}
+
+/// ensure a file with decimal commas and `;` separated fields is read like the sample
+#[test]
+fn decimal_comma() {
+    let expected = ledger(&["tests/data/sample.csv"]).stdout;
+    let output = ledger(&[
+        "--decimal-comma",
+        "--delimiter",
+        ";",
+        "tests/data/decimal_comma.csv",
+    ]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, expected);
+}
+
+/// ensure `--duplicate-window` counts the deposits with the same client and amount as a recent one in the summary
+#[test]
+fn duplicate_window() {
+    let output = ledger(&["--duplicate-window", "2", "tests/data/duplicates.csv"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "processed 5 records, 2 possible duplicate deposits\n"
+    );
+    let output = ledger(&["--duplicate-window", "1", "tests/data/duplicates.csv"]);
+    assert!(!String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("possible duplicate"));
+    assert!(
+        !ledger(&["--duplicate-window", "-1", "tests/data/duplicates.csv"])
+            .status
+            .success()
+    );
+}
+
+/// ensure `--input-order` prints the clients in the order they first appear
+#[test]
+fn input_order() {
+    let output = ledger(&["--input-order", "tests/data/shuffled.csv"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n3,1.0000,0.0000,1.0000,false\n1,3.0000,0.0000,3.0000,false\n2,2.0000,0.0000,2.0000,false\n"
+    );
+    let output = ledger(&["tests/data/shuffled.csv"]);
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .starts_with("client,available,held,total,locked\n1,"));
+}
+
+/// ensure a file without records prints only the header, and an empty file is reported but not an error
+#[test]
+fn empty() {
+    let output = ledger(&["tests/data/header_only.csv"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n"
+    );
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "processed 0 records\n"
+    );
+
+    let output = ledger(&["tests/data/empty.csv"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n"
+    );
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "tests/data/empty.csv is empty, expected at least a header row\nprocessed 0 records\n"
+    );
+    let output = ledger(&["validate", "tests/data/header_only.csv"]);
+    assert!(output.status.success());
+}
+
+/// ensure `--disputes-output` writes the disputed deposits next to the normal output
+#[test]
+fn disputes_output() {
+    let path = std::env::temp_dir().join(format!("ledger-disputes-{}.csv", std::process::id()));
+    let expected = ledger(&["tests/data/sample.csv"]).stdout;
+    let output = ledger(&[
+        "--disputes-output",
+        path.to_str().unwrap(),
+        "tests/data/sample.csv",
+    ]);
+    let disputes = std::fs::read_to_string(&path);
+    let _ = std::fs::remove_file(&path);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, expected);
+    assert_eq!(
+        disputes.unwrap(),
+        "tx,client,amount,state\n2,2,2.0000,chargedback\n"
+    );
+
+    // the report is quoted like the client output
+    let output = ledger(&[
+        "--quote",
+        "always",
+        "--disputes-output",
+        path.to_str().unwrap(),
+        "tests/data/sample.csv",
+    ]);
+    let disputes = std::fs::read_to_string(&path);
+    let _ = std::fs::remove_file(&path);
+    assert!(output.status.success());
+    assert_eq!(
+        disputes.unwrap(),
+        "\"tx\",\"client\",\"amount\",\"state\"\n\"2\",\"2\",\"2.0000\",\"chargedback\"\n"
+    );
+}
+
+/// ensure `--limit` only applies the first records
+#[test]
+fn limit() {
+    let output = ledger(&["--limit", "2", "tests/data/sample.csv"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n2,2.0000,0.0000,2.0000,false\n"
+    );
+    let output = ledger(&["stats", "--limit", "3", "tests/data/sample.csv"]);
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .starts_with("records: 3\n"));
+    assert!(!ledger(&["--limit", "two", "tests/data/sample.csv"])
+        .status
+        .success());
+}
+
+/// ensure `reconcile` accepts the totals of a clean run
+#[test]
+fn reconcile() {
+    let output = ledger(&["reconcile", "tests/data/sample.csv"]);
+    assert!(output.status.success());
+    // 5.0 deposited, 1.5 withdrawn, and 2.0 charged back
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "reconciled: 1.5000\n"
+    );
+}
+
+/// ensure `--quote always` wraps every field of the output in quotes
+#[test]
+fn quote() {
+    let output = ledger(&["--quote", "always", "tests/data/sample.csv"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "\"client\",\"available\",\"held\",\"total\",\"locked\"\n\"1\",\"1.5000\",\"0.0000\",\"1.5000\",\"false\"\n\"2\",\"0.0000\",\"0.0000\",\"0.0000\",\"true\"\n"
+    );
+    let output = ledger(&[
+        "--quote",
+        "always",
+        "--quote-char",
+        "'",
+        "tests/data/sample.csv",
+    ]);
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .starts_with("'client','available'"));
+    assert!(!ledger(&["--quote", "sometimes", "tests/data/sample.csv"])
+        .status
+        .success());
+}
+
+/// ensure `--nonzero` skips emptied accounts but keeps locked ones
+#[test]
+fn nonzero() {
+    let output = ledger(&["process", "--nonzero", "tests/data/nonzero.csv"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n2,0.0000,0.0000,0.0000,true\n3,3.0000,0.0000,3.0000,false\n"
+    );
+    // without the flag the emptied account is still there
+    let output = ledger(&["process", "tests/data/nonzero.csv"]);
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("1,0.0000,0.0000,0.0000,false\n"));
+}
+
+/// ensure `--decimals` rounds the balances in the output, in both formats
+#[test]
+fn decimals() {
+    let output = ledger(&[
+        "--decimals",
+        "0",
+        "--rounding",
+        "down",
+        "tests/data/sample.csv",
+    ]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("\n1,1,0,1,false\n"));
+    let output = ledger(&["--decimals", "0", "--fixed-width", "tests/data/sample.csv"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(&format!(
+        "{:>6}{:>24}{:>24}{:>24}{:>6}",
+        1, "2", "0", "2", false
+    )));
+    let output = ledger(&["--rounding", "sideways", "tests/data/sample.csv"]);
+    assert!(!output.status.success());
+}
+
+/// ensure `--fixed-width` prints the balances in right-aligned columns
+#[test]
+fn fixed_width() {
+    let output = ledger(&["--fixed-width", "tests/data/sample.csv"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines.iter().all(|line| line.len() == 84));
+    assert_eq!(
+        lines[1],
+        format!(
+            "{:>6}{:>24}{:>24}{:>24}{:>6}",
+            1, "1.5000", "0.0000", "1.5000", false
+        )
+    );
+}
+
+/// ensure `validate` prints nothing to stdout and fails only on malformed records
+#[test]
+fn validate() {
+    let output = ledger(&["validate", "tests/data/sample.csv"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let output = ledger(&["validate", "tests/data/malformed.csv"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("failed to deserialize record 1"));
+    assert!(stderr.contains("failed to deserialize record 2"));
+}
+
+/// ensure `stats` reports the counts and totals
+#[test]
+fn stats() {
+    let output = ledger(&["stats", "tests/data/sample.csv"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("records: 7\n"));
+    assert!(stdout.contains("invalid records: 0\n"));
+    assert!(stdout.contains("failed actions: 1\n"));
+    assert!(stdout.contains("locked clients: 1\n"));
+    assert!(stdout.contains("negative total clients: 0\n"));
+    assert!(stdout.contains("total: 1.5000\n"));
+}
+
+/// ensure `stats` counts the disputes per reason code
+#[test]
+fn stats_reasons() {
+    let output = ledger(&["stats", "tests/data/reasons.csv"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("disputes with reason 10.4: 1\n"));
+    assert!(stdout.contains("disputes with reason 13.1: 1\n"));
+}
+
+/// ensure processing the files in `tests/golden` prints exactly the expected `.stdout` and `.stderr`,
+/// covering the balances, the locking of accounts, and the reporting of bad records and failed actions
+#[test]
+fn golden() {
+    for case in ["happy", "chargeback", "malformed"] {
+        let output = ledger(&["process", &format!("tests/golden/{case}.csv")]);
+        assert!(output.status.success(), "{case}");
+        let expected = |stream| {
+            std::fs::read_to_string(format!("tests/golden/{case}.{stream}"))
+                .expect("failed to read the expected output")
+        };
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            expected("stdout"),
+            "{case}"
+        );
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            expected("stderr"),
+            "{case}"
+        );
+    }
+}
+
+/// ensure unknown subcommands are rejected
+#[test]
+fn unknown_command() {
+    let output = ledger(&["frobnicate", "tests/data/sample.csv"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+/// ensure `--strict-amounts` rejects amounts without exactly four decimal places
+#[test]
+fn strict_amounts() {
+    let output = ledger(&["validate", "tests/data/sample.csv"]);
+    assert!(output.status.success());
+    let output = ledger(&["validate", "--strict-amounts", "tests/data/sample.csv"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("amount `1.0` must have exactly 4 decimal places"));
+}
@@ -0,0 +1,6 @@
+/// ensure the `amount!` macro rejects literals that are no valid amount at compile time
+#[test]
+fn amount_macro() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/amount_*.rs");
+}
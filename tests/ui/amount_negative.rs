@@ -0,0 +1,3 @@
+fn main() {
+    let _ = ledger::amount!(-1.5);
+}